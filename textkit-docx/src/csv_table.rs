@@ -0,0 +1,318 @@
+//! Loading of CSV/TSV files as tables, so that they can be rendered with the
+//! `table` (and related) helpers without hand-writing the datakit JSON.
+//!
+
+use crate::errors::TextkitDocxError;
+use crate::render::table_data::TableData;
+use datakit::{value::definitions::*, value::primitives::*};
+use std::path::PathBuf;
+
+/// Where the CSV data comes from.
+#[derive(Debug, Clone)]
+pub enum CsvSource {
+    Path(PathBuf),
+    Inline(String),
+}
+
+impl CsvSource {
+    /// Interpret text as inline CSV data if it spans several lines, and as a
+    /// path otherwise.
+    pub fn guess(text: &str) -> Self {
+        if text.contains('\n') {
+            Self::Inline(String::from(text))
+        } else {
+            Self::Path(PathBuf::from(text))
+        }
+    }
+}
+
+/// How CSV data is read.
+#[derive(Debug, Clone)]
+pub struct CsvOptions {
+    /// Field delimiter. If `None`, a tab is used for `.tsv` and `.tab` files,
+    /// otherwise the most frequent of `,`, `;` and tab in the first line.
+    pub delimiter: Option<char>,
+
+    /// Use the first record as column names.
+    pub has_header: bool,
+
+    /// Turn columns consisting only of integers, numbers or booleans into
+    /// such values. Otherwise all values are text.
+    pub infer_types: bool,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        Self {
+            delimiter: None,
+            has_header: true,
+            infer_types: true,
+        }
+    }
+}
+
+/// A delimiter given as text, e.g. on the command line: a single character,
+/// or `\t` for a tab.
+pub fn parse_delimiter(text: &str) -> Option<char> {
+    if text == "\\t" {
+        return Some('\t');
+    }
+    let mut chars = text.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Some(c),
+        _ => None,
+    }
+}
+
+/// Read CSV data into a table. Empty fields are missing values.
+pub fn read_csv_table(
+    source: &CsvSource,
+    options: &CsvOptions,
+) -> Result<TableData, TextkitDocxError> {
+    let (contents, tab_by_extension) = match source {
+        CsvSource::Path(path) => {
+            let extension = path
+                .extension()
+                .map(|ext| ext.to_string_lossy().to_lowercase())
+                .unwrap_or_default();
+            let contents = std::fs::read_to_string(path)?;
+            (contents, extension == "tsv" || extension == "tab")
+        }
+        CsvSource::Inline(contents) => (contents.clone(), false),
+    };
+
+    let delimiter = match options.delimiter {
+        Some(delimiter) => delimiter,
+        None if tab_by_extension => '\t',
+        None => guess_delimiter(&contents),
+    };
+
+    let mut records = parse_records(&contents, delimiter)?;
+    let headers = if options.has_header && !records.is_empty() {
+        records.remove(0)
+    } else {
+        vec![]
+    };
+
+    let no_of_cols = records
+        .iter()
+        .map(|record| record.len())
+        .chain(std::iter::once(headers.len()))
+        .max()
+        .unwrap_or(0);
+    let mut headers = headers;
+    while headers.len() < no_of_cols && options.has_header {
+        headers.push(format!("{}", headers.len() + 1));
+    }
+
+    let columns = (0..no_of_cols)
+        .map(|col_i| {
+            let fields: Vec<&str> = records
+                .iter()
+                .map(|record| record.get(col_i).map(|field| field.as_str()).unwrap_or(""))
+                .collect();
+            column_values(&fields, options.infer_types)
+        })
+        .collect();
+
+    Ok(TableData { headers, columns })
+}
+
+/// Read CSV data into a serialized datakit `Table` (with column contracts
+/// carrying the column names), ready to be put into the template data.
+pub fn read_csv_as_datakit_json(
+    source: &CsvSource,
+    options: &CsvOptions,
+) -> Result<serde_json::Value, TextkitDocxError> {
+    read_csv_table(source, options)?.to_datakit_json()
+}
+
+fn guess_delimiter(contents: &str) -> char {
+    let first_line = contents.lines().next().unwrap_or("");
+    [',', ';', '\t']
+        .iter()
+        .copied()
+        .max_by_key(|c| (first_line.matches(*c).count(), *c == ','))
+        .unwrap_or(',')
+}
+
+/// Split CSV data into records and fields. Fields can be quoted with `"`, in
+/// which case they may contain delimiters, line breaks and doubled quotes.
+/// Blank lines are skipped.
+fn parse_records(contents: &str, delimiter: char) -> Result<Vec<Vec<String>>, TextkitDocxError> {
+    let mut records: Vec<Vec<String>> = Vec::new();
+    let mut record: Vec<String> = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = contents.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    field.push('"');
+                    chars.next();
+                }
+                '"' => in_quotes = false,
+                c => field.push(c),
+            }
+        } else if c == '"' && field.is_empty() {
+            in_quotes = true;
+        } else if c == delimiter {
+            record.push(std::mem::take(&mut field));
+        } else if c == '\n' || c == '\r' {
+            if c == '\r' && chars.peek() == Some(&'\n') {
+                chars.next();
+            }
+            record.push(std::mem::take(&mut field));
+            let is_blank = record.len() == 1 && record[0].is_empty();
+            let finished = std::mem::take(&mut record);
+            if !is_blank {
+                records.push(finished);
+            }
+        } else {
+            field.push(c);
+        }
+    }
+
+    if in_quotes {
+        return Err(TextkitDocxError::BadCsv(String::from(
+            "unterminated quoted field",
+        )));
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+
+    Ok(records)
+}
+
+/// Values of a column, typed as integers, reals or booleans if all of its
+/// (non-empty) fields are of that type.
+fn column_values(fields: &[&str], infer_types: bool) -> Vec<Option<Value>> {
+    let present: Vec<&str> = fields
+        .iter()
+        .map(|field| field.trim())
+        .filter(|field| !field.is_empty())
+        .collect();
+
+    let all = |predicate: &dyn Fn(&str) -> bool| {
+        infer_types && !present.is_empty() && present.iter().all(|field| predicate(field))
+    };
+    let is_integer = all(&|field| field.parse::<i64>().is_ok());
+    let is_real = all(&|field| field.parse::<f64>().is_ok());
+    let is_boolean =
+        all(&|field| field.eq_ignore_ascii_case("true") || field.eq_ignore_ascii_case("false"));
+
+    fields
+        .iter()
+        .map(|field| {
+            let trimmed = field.trim();
+            if trimmed.is_empty() {
+                None
+            } else if is_integer {
+                trimmed
+                    .parse::<i64>()
+                    .ok()
+                    .map(|int| Value::Number(Numeric::Integer(int)))
+            } else if is_real {
+                trimmed
+                    .parse::<f64>()
+                    .ok()
+                    .map(|real| Value::Number(Numeric::Real(real)))
+            } else if is_boolean {
+                Some(Value::Boolean(trimmed.eq_ignore_ascii_case("true")))
+            } else {
+                Some(Value::Text(String::from(*field)))
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(csv: &str, options: &CsvOptions) -> TableData {
+        read_csv_table(&CsvSource::Inline(String::from(csv)), options).unwrap()
+    }
+
+    fn text(text: &str) -> Option<Value> {
+        Some(Value::Text(String::from(text)))
+    }
+
+    #[test]
+    fn type_inference() {
+        let csv = "int,real,flag,mixed,empty\n1,1.5,true,1,\n-2, 3 ,FALSE,a,\n,,,,\n";
+        let table = read(csv, &CsvOptions::default());
+        assert_eq!(table.headers, vec!["int", "real", "flag", "mixed", "empty"]);
+        let integer = |int: i64| Some(Value::Number(Numeric::Integer(int)));
+        let real = |real: f64| Some(Value::Number(Numeric::Real(real)));
+        assert_eq!(table.columns[0], vec![integer(1), integer(-2), None]);
+        assert_eq!(table.columns[1], vec![real(1.5), real(3.0), None]);
+        assert_eq!(
+            table.columns[2],
+            vec![
+                Some(Value::Boolean(true)),
+                Some(Value::Boolean(false)),
+                None
+            ]
+        );
+        assert_eq!(table.columns[3], vec![text("1"), text("a"), None]);
+        assert_eq!(table.columns[4], vec![None, None, None]);
+
+        let options = CsvOptions {
+            infer_types: false,
+            ..CsvOptions::default()
+        };
+        let table = read(csv, &options);
+        assert_eq!(table.columns[0], vec![text("1"), text("-2"), None]);
+    }
+
+    #[test]
+    fn quoting() {
+        let csv = "name,note\r\n\"Smith, John\",\"said \"\"hi\"\"\nand left\"\r\n\r\nplain,a\"b\n";
+        let table = read(csv, &CsvOptions::default());
+        assert_eq!(table.no_of_rows(), 2);
+        assert_eq!(table.columns[0], vec![text("Smith, John"), text("plain")]);
+        assert_eq!(
+            table.columns[1],
+            vec![text("said \"hi\"\nand left"), text("a\"b")]
+        );
+
+        let unterminated = CsvSource::Inline(String::from("a,\"b\n1,2\n"));
+        assert!(matches!(
+            read_csv_table(&unterminated, &CsvOptions::default()),
+            Err(TextkitDocxError::BadCsv(_))
+        ));
+    }
+
+    #[test]
+    fn delimiters() {
+        let table = read("a;b;c\n1,5;2;3\n", &CsvOptions::default());
+        assert_eq!(table.headers, vec!["a", "b", "c"]);
+        assert_eq!(table.columns[0], vec![text("1,5")]);
+
+        let table = read("a\tb\n1\t2\n", &CsvOptions::default());
+        assert_eq!(table.headers, vec!["a", "b"]);
+
+        let options = CsvOptions {
+            delimiter: Some('|'),
+            has_header: false,
+            ..CsvOptions::default()
+        };
+        let table = read("a|b,c\n", &options);
+        assert!(table.headers.is_empty());
+        assert_eq!(table.columns[1], vec![text("b,c")]);
+    }
+
+    #[test]
+    fn delimiters_as_text() {
+        assert_eq!(parse_delimiter(";"), Some(';'));
+        assert_eq!(parse_delimiter("\t"), Some('\t'));
+        assert_eq!(parse_delimiter("\\t"), Some('\t'));
+        assert_eq!(parse_delimiter(""), None);
+        assert_eq!(parse_delimiter(";;"), None);
+    }
+}
@@ -11,6 +11,18 @@ pub enum TextkitDocxError {
     #[error("Malformed document")]
     Malformed(String),
 
+    #[error("Unable to parse helper placeholder: {0}")]
+    BadHelperCall(String),
+
+    #[error("Invalid helper option: {0}")]
+    BadHelperOption(String),
+
+    #[error("Unable to read CSV data: {0}")]
+    BadCsv(String),
+
+    #[error("Local file cannot be read: {0}")]
+    FileAccess(String),
+
     #[error("Failed to write XML data")]
     FailedWriteXml,
 
@@ -0,0 +1,87 @@
+//! Local files named in the template or its data: images in markdown and
+//! the paths of `csv` subexpressions.
+//! The data often comes from elsewhere (a notebook, an API), so no files are
+//! read unless a base directory is given, and then only files inside it.
+//!
+
+use crate::errors::TextkitDocxError;
+use std::path::{Path, PathBuf};
+
+/// Which local files the data may refer to.
+#[derive(Debug, Clone, Default)]
+pub struct FileAccess {
+    /// Canonical base directory. `None` (the default) means that no files
+    /// are read.
+    base_dir: Option<PathBuf>,
+}
+
+impl FileAccess {
+    /// Allow reading the files inside `base_dir` and its subdirectories.
+    /// Relative paths in the data are taken relative to it.
+    pub fn within(base_dir: &Path) -> Result<Self, TextkitDocxError> {
+        Ok(Self {
+            base_dir: Some(base_dir.canonicalize()?),
+        })
+    }
+
+    /// The canonical path of a file named in the data, if it may be read. Paths
+    /// that lead out of the base directory (`..`, absolute paths, symbolic
+    /// links) are refused.
+    pub(crate) fn resolve(&self, path: &str) -> Result<PathBuf, TextkitDocxError> {
+        let denied =
+            |reason: String| TextkitDocxError::FileAccess(format!("{} ({})", path, reason));
+        let base_dir = self
+            .base_dir
+            .as_ref()
+            .ok_or_else(|| denied(String::from("reading local files is not enabled")))?;
+        let full_path = base_dir
+            .join(path)
+            .canonicalize()
+            .map_err(|error| denied(error.to_string()))?;
+
+        if full_path.starts_with(base_dir) {
+            Ok(full_path)
+        } else {
+            Err(denied(format!("outside of {}", base_dir.display())))
+        }
+    }
+
+    /// Contents of a file named in the data, see [`FileAccess::resolve`].
+    pub(crate) fn read(&self, path: &str) -> Result<Vec<u8>, TextkitDocxError> {
+        Ok(std::fs::read(self.resolve(path)?)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh directory `base` with `inside.txt`, next to `outside.txt`.
+    fn test_dirs(name: &str) -> PathBuf {
+        let root =
+            std::env::temp_dir().join(format!("textkit-files-{}-{}", name, std::process::id()));
+        let base = root.join("base");
+        std::fs::create_dir_all(&base).unwrap();
+        std::fs::write(base.join("inside.txt"), "inside").unwrap();
+        std::fs::write(root.join("outside.txt"), "outside").unwrap();
+        base
+    }
+
+    #[test]
+    fn no_files_by_default() {
+        let base = test_dirs("default");
+        let path = base.join("inside.txt");
+        assert!(FileAccess::default().read(path.to_str().unwrap()).is_err());
+    }
+
+    #[test]
+    fn files_inside_the_base_directory() {
+        let base = test_dirs("inside");
+        let files = FileAccess::within(&base).unwrap();
+        assert_eq!(files.read("inside.txt").unwrap(), b"inside");
+        assert!(files.read("../outside.txt").is_err());
+        let outside = base.join("..").join("outside.txt");
+        assert!(files.read(outside.to_str().unwrap()).is_err());
+        assert!(files.read("missing.txt").is_err());
+    }
+}
@@ -1,4 +1,6 @@
+pub mod csv_table;
 pub mod errors;
+pub mod files;
 pub mod parse;
 pub mod render;
 pub mod template;
@@ -20,6 +22,9 @@ static NS_DWML_PIC: &str = "http://schemas.openxmlformats.org/drawingml/2006/pic
 /// Relationship Namespace in DOCX
 static NS_REL: &str = "http://schemas.openxmlformats.org/officeDocument/2006/relationships";
 
+/// Namespace of Office Math (OMML) elements, i.e. equations.
+static NS_OMML: &str = "http://schemas.openxmlformats.org/officeDocument/2006/math";
+
 // Regex patterns used to match Handlebars placeholders
 static PAT_HB_ALL: &str = r"\{\{(\S+)\s*([^\{\}]+)?\}\}"; // All placeholders
 static PAT_HB_SMP: &str = r"\{\{\S+\}\}"; // Only simple placeholders
@@ -59,7 +64,6 @@ pub(crate) struct TemplateArea {
 #[derive(Debug)]
 pub(crate) struct TemplatePlaceholder {
     pub helper_name: Option<String>,
-    pub expression: String,
     pub start_position: usize,
     pub end_position: usize,
 }
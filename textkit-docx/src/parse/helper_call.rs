@@ -0,0 +1,512 @@
+//! Parsing of Handlebars helper calls like
+//! `{{table results.summary columns="a,b" limit=20 styling=(lookup styles "summary")}}`
+//! and evaluation of their arguments against the template data.
+
+use super::quote_kind;
+use crate::csv_table::{parse_delimiter, read_csv_as_datakit_json, CsvOptions, CsvSource};
+use crate::errors::TextkitDocxError;
+use crate::files::FileAccess;
+use std::collections::BTreeMap;
+
+/// What helper arguments are evaluated against: the template data, and the
+/// local files that the `csv` subexpression may read.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct HelperScope<'a> {
+    pub data: &'a serde_json::Value,
+    pub files: &'a FileAccess,
+}
+
+/// A helper call: the helper name, its positional parameters and its hash
+/// arguments (`key=value`).
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct HelperCall {
+    pub name: String,
+    pub params: Vec<HelperArg>,
+    pub hash: BTreeMap<String, HelperArg>,
+}
+
+/// A single argument of a helper call.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum HelperArg {
+    /// A path into the data, e.g. `results.summary` or `rows.0`.
+    Path(String),
+    Str(String),
+    Number(serde_json::Number),
+    Bool(bool),
+    Null,
+    /// A nested helper call in parentheses, e.g. `(lookup styles "summary")`.
+    SubExpr(HelperCall),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Lexeme {
+    Open,
+    Close,
+    Equals,
+    Quoted(String),
+    Word(String),
+}
+
+impl HelperCall {
+    /// Parse the text between `{{` and `}}` of a helper placeholder.
+    pub(crate) fn parse(text: &str) -> Result<Self, TextkitDocxError> {
+        let lexemes = lex(text)?;
+        let mut position: usize = 0;
+        let call = parse_call(text, &lexemes, &mut position)?;
+
+        if position != lexemes.len() {
+            return Err(TextkitDocxError::BadHelperCall(String::from(text)));
+        }
+
+        Ok(call)
+    }
+
+    /// The value of the n-th positional parameter, if given.
+    pub(crate) fn param(
+        &self,
+        n: usize,
+        scope: &HelperScope,
+    ) -> Result<Option<serde_json::Value>, TextkitDocxError> {
+        match self.params.get(n) {
+            Some(arg) => Ok(Some(arg.resolve(scope)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// The values of all hash arguments.
+    pub(crate) fn hash_values(
+        &self,
+        scope: &HelperScope,
+    ) -> Result<BTreeMap<String, serde_json::Value>, TextkitDocxError> {
+        let mut result: BTreeMap<String, serde_json::Value> = BTreeMap::new();
+        for (key, arg) in self.hash.iter() {
+            result.insert(key.clone(), arg.resolve(scope)?);
+        }
+        Ok(result)
+    }
+}
+
+impl HelperArg {
+    /// The value of the argument given the template data. Paths that do not
+    /// exist resolve to `null`, like in Handlebars.
+    pub(crate) fn resolve(
+        &self,
+        scope: &HelperScope,
+    ) -> Result<serde_json::Value, TextkitDocxError> {
+        match self {
+            Self::Path(path) => Ok(lookup_path(scope.data, path)
+                .cloned()
+                .unwrap_or(serde_json::Value::Null)),
+            Self::Str(text) => Ok(serde_json::Value::String(text.clone())),
+            Self::Number(number) => Ok(serde_json::Value::Number(number.clone())),
+            Self::Bool(boolean) => Ok(serde_json::Value::Bool(*boolean)),
+            Self::Null => Ok(serde_json::Value::Null),
+            Self::SubExpr(call) => evaluate_subexpression(call, scope),
+        }
+    }
+}
+
+/// Look up a dotted (or slashed) path such as `complex_data.three.some_array.0`
+/// in the data. `this` refers to the data itself.
+pub(crate) fn lookup_path<'a>(
+    data: &'a serde_json::Value,
+    path: &str,
+) -> Option<&'a serde_json::Value> {
+    let mut current = data;
+
+    for segment in path.split(&['.', '/'][..]) {
+        if segment.is_empty() || segment == "this" {
+            continue;
+        }
+        current = match current {
+            serde_json::Value::Object(map) => map.get(segment)?,
+            serde_json::Value::Array(items) => items.get(segment.parse::<usize>().ok()?)?,
+            _ => return None,
+        };
+    }
+
+    Some(current)
+}
+
+/// The text of an option value: strings as they are, anything else as JSON.
+pub(crate) fn option_text(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(text) => text.clone(),
+        serde_json::Value::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
+/// A list option, given either as an array or as a comma separated string
+/// (e.g. `columns="a, b"`). Empty entries are dropped.
+pub(crate) fn option_list(value: &serde_json::Value) -> Vec<String> {
+    let items: Vec<String> = match value {
+        serde_json::Value::Array(items) => items.iter().map(option_text).collect(),
+        other => option_text(other).split(',').map(String::from).collect(),
+    };
+    items
+        .into_iter()
+        .map(|item| String::from(item.trim()))
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+/// A boolean option, given as a literal (`autofit=true`) or a string.
+pub(crate) fn option_bool(value: &serde_json::Value) -> Option<bool> {
+    match value {
+        serde_json::Value::Bool(boolean) => Some(*boolean),
+        serde_json::Value::String(text) => text.trim().parse::<bool>().ok(),
+        _ => None,
+    }
+}
+
+/// A non-negative integer option, given as a number (`limit=20`) or a string.
+pub(crate) fn option_usize(value: &serde_json::Value) -> Option<usize> {
+    match value {
+        serde_json::Value::Number(number) => number.as_u64().map(|n| n as usize),
+        serde_json::Value::String(text) => text.trim().parse::<usize>().ok(),
+        _ => None,
+    }
+}
+
+/// Helpers that can be used in subexpressions:
+///
+/// - `(lookup object key)` - the member `key` of `object` (or item of an array).
+/// - `(array a b c)` - a list of the given values.
+/// - `(csv source delimiter=";" header=true infer=true)` - a table read from a
+///   CSV file (or from CSV text, if `source` spans several lines). Files are
+///   read through the [`FileAccess`] of the scope, so not at all by default.
+fn evaluate_subexpression(
+    call: &HelperCall,
+    scope: &HelperScope,
+) -> Result<serde_json::Value, TextkitDocxError> {
+    let params: Vec<serde_json::Value> = call
+        .params
+        .iter()
+        .map(|arg| arg.resolve(scope))
+        .collect::<Result<_, _>>()?;
+
+    match call.name.as_str() {
+        "lookup" => {
+            let value = match (params.first(), params.get(1)) {
+                (Some(serde_json::Value::Object(map)), Some(serde_json::Value::String(key))) => {
+                    map.get(key).cloned()
+                }
+                (Some(serde_json::Value::Array(items)), Some(serde_json::Value::Number(n))) => {
+                    n.as_u64().and_then(|i| items.get(i as usize)).cloned()
+                }
+                _ => None,
+            };
+            Ok(value.unwrap_or(serde_json::Value::Null))
+        }
+        "array" => Ok(serde_json::Value::Array(params)),
+        "csv" => {
+            let source = match params.first() {
+                Some(serde_json::Value::String(source)) => match CsvSource::guess(source) {
+                    CsvSource::Path(path) => {
+                        CsvSource::Path(scope.files.resolve(&path.to_string_lossy())?)
+                    }
+                    inline => inline,
+                },
+                _ => {
+                    return Err(TextkitDocxError::BadHelperCall(String::from(
+                        "csv needs a path or CSV text",
+                    )))
+                }
+            };
+            let hash_args = call.hash_values(scope)?;
+            let mut options = CsvOptions::default();
+            for (key, value) in hash_args.iter() {
+                let bad_option = || TextkitDocxError::BadHelperOption(format!("{}={}", key, value));
+                match key.as_str() {
+                    "delimiter" => {
+                        let delimiter = parse_delimiter(&option_text(value));
+                        options.delimiter = Some(delimiter.ok_or_else(bad_option)?);
+                    }
+                    "header" => options.has_header = option_bool(value).ok_or_else(bad_option)?,
+                    "infer" => options.infer_types = option_bool(value).ok_or_else(bad_option)?,
+                    _ => return Err(bad_option()),
+                }
+            }
+            read_csv_as_datakit_json(&source, &options)
+        }
+        _ => Err(TextkitDocxError::BadHelperCall(format!(
+            "unknown subexpression helper '{}'",
+            call.name
+        ))),
+    }
+}
+
+fn lex(text: &str) -> Result<Vec<Lexeme>, TextkitDocxError> {
+    let mut result: Vec<Lexeme> = Vec::new();
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => (),
+            '(' => result.push(Lexeme::Open),
+            ')' => result.push(Lexeme::Close),
+            '=' => result.push(Lexeme::Equals),
+            c if quote_kind(c).is_some() => {
+                let mut quoted = String::new();
+                loop {
+                    match chars.next() {
+                        Some(q) if quote_kind(q) == quote_kind(c) => break,
+                        Some(q) => quoted.push(q),
+                        None => return Err(TextkitDocxError::BadHelperCall(String::from(text))),
+                    }
+                }
+                result.push(Lexeme::Quoted(quoted));
+            }
+            c => {
+                let mut word = String::from(c);
+                while let Some(next) = chars.peek() {
+                    if next.is_whitespace() || "()=".contains(*next) || quote_kind(*next).is_some()
+                    {
+                        break;
+                    }
+                    word.push(*next);
+                    chars.next();
+                }
+                result.push(Lexeme::Word(word));
+            }
+        }
+    }
+
+    Ok(result)
+}
+
+fn parse_call(
+    text: &str,
+    lexemes: &[Lexeme],
+    position: &mut usize,
+) -> Result<HelperCall, TextkitDocxError> {
+    let bad_call = || TextkitDocxError::BadHelperCall(String::from(text));
+
+    let name = match lexemes.get(*position) {
+        Some(Lexeme::Word(name)) => name.clone(),
+        _ => return Err(bad_call()),
+    };
+    *position += 1;
+
+    let mut call = HelperCall {
+        name,
+        params: Vec::new(),
+        hash: BTreeMap::new(),
+    };
+
+    while let Some(lexeme) = lexemes.get(*position) {
+        if *lexeme == Lexeme::Close {
+            break;
+        }
+
+        if let (Lexeme::Word(key), Some(Lexeme::Equals)) = (lexeme, lexemes.get(*position + 1)) {
+            *position += 2;
+            let value = parse_arg(text, lexemes, position)?;
+            call.hash.insert(key.clone(), value);
+        } else if call.hash.is_empty() {
+            let param = parse_arg(text, lexemes, position)?;
+            call.params.push(param);
+        } else {
+            // Positional parameters must come before the hash arguments.
+            return Err(bad_call());
+        }
+    }
+
+    Ok(call)
+}
+
+fn parse_arg(
+    text: &str,
+    lexemes: &[Lexeme],
+    position: &mut usize,
+) -> Result<HelperArg, TextkitDocxError> {
+    let bad_call = || TextkitDocxError::BadHelperCall(String::from(text));
+
+    let lexeme = lexemes.get(*position).ok_or_else(bad_call)?;
+    *position += 1;
+
+    match lexeme {
+        Lexeme::Open => {
+            let call = parse_call(text, lexemes, position)?;
+            match lexemes.get(*position) {
+                Some(Lexeme::Close) => {
+                    *position += 1;
+                    Ok(HelperArg::SubExpr(call))
+                }
+                _ => Err(bad_call()),
+            }
+        }
+        Lexeme::Quoted(quoted) => Ok(HelperArg::Str(quoted.clone())),
+        Lexeme::Word(word) => Ok(match word.as_str() {
+            "true" => HelperArg::Bool(true),
+            "false" => HelperArg::Bool(false),
+            "null" | "undefined" => HelperArg::Null,
+            _ => {
+                if let Ok(int) = word.parse::<i64>() {
+                    HelperArg::Number(int.into())
+                } else if let Some(number) = word
+                    .parse::<f64>()
+                    .ok()
+                    .and_then(serde_json::Number::from_f64)
+                {
+                    HelperArg::Number(number)
+                } else {
+                    HelperArg::Path(word.clone())
+                }
+            }
+        }),
+        Lexeme::Close | Lexeme::Equals => Err(bad_call()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn evaluate(text: &str, files: &FileAccess) -> Result<serde_json::Value, TextkitDocxError> {
+        let data = serde_json::json!({});
+        let scope = HelperScope { data: &data, files };
+        HelperCall::parse(text)?
+            .param(0, &scope)
+            .map(Option::unwrap_or_default)
+    }
+
+    #[test]
+    fn csv_files_are_not_read_by_default() {
+        let result = evaluate(r#"table (csv "/etc/passwd")"#, &FileAccess::default());
+        assert!(matches!(result, Err(TextkitDocxError::FileAccess(_))));
+    }
+
+    #[test]
+    fn inline_csv_needs_no_file_access() {
+        let result = evaluate("table (csv \"a,b\n1,2\")", &FileAccess::default());
+        assert!(result.unwrap().is_object());
+    }
+
+    fn path(path: &str) -> HelperArg {
+        HelperArg::Path(String::from(path))
+    }
+
+    fn text(text: &str) -> HelperArg {
+        HelperArg::Str(String::from(text))
+    }
+
+    #[test]
+    fn positional_params_and_hash_args() {
+        let call =
+            HelperCall::parse("table results.summary rows/0 columns=\"a,b\" limit=20").unwrap();
+        assert_eq!(call.name, "table");
+        assert_eq!(call.params, vec![path("results.summary"), path("rows/0")]);
+        assert_eq!(call.hash.len(), 2);
+        assert_eq!(call.hash["columns"], text("a,b"));
+        assert_eq!(call.hash["limit"], HelperArg::Number(20.into()));
+    }
+
+    #[test]
+    fn literals() {
+        let call = HelperCall::parse("f 'single' -3 2.5 true false null undefined x = \"spaced\"")
+            .unwrap();
+        assert_eq!(
+            call.params,
+            vec![
+                text("single"),
+                HelperArg::Number((-3).into()),
+                HelperArg::Number(serde_json::Number::from_f64(2.5).unwrap()),
+                HelperArg::Bool(true),
+                HelperArg::Bool(false),
+                HelperArg::Null,
+                HelperArg::Null,
+            ]
+        );
+        assert_eq!(call.hash["x"], text("spaced"));
+    }
+
+    #[test]
+    fn typographic_quotes() {
+        // Word's autocorrect turns quotes into typographic ones, which need not
+        // match each other, but they still have to be of the same kind.
+        let call = HelperCall::parse("table t columns=“a, b” where=‘x == \"y\"’ sort=”c“").unwrap();
+        assert_eq!(call.hash["columns"], text("a, b"));
+        assert_eq!(call.hash["where"], text("x == \"y\""));
+        assert_eq!(call.hash["sort"], text("c"));
+    }
+
+    #[test]
+    fn subexpressions() {
+        let call =
+            HelperCall::parse("table (lookup tables (lookup names 0)) styling=(array 1 \"a\")")
+                .unwrap();
+        let inner = HelperCall {
+            name: String::from("lookup"),
+            params: vec![path("names"), HelperArg::Number(0.into())],
+            hash: BTreeMap::new(),
+        };
+        let outer = HelperCall {
+            name: String::from("lookup"),
+            params: vec![path("tables"), HelperArg::SubExpr(inner)],
+            hash: BTreeMap::new(),
+        };
+        assert_eq!(call.params, vec![HelperArg::SubExpr(outer)]);
+
+        let data = serde_json::json!({"names": ["b", "a"], "tables": {"a": 1, "b": 2}});
+        let scope = HelperScope {
+            data: &data,
+            files: &FileAccess::default(),
+        };
+        assert_eq!(call.param(0, &scope).unwrap(), Some(serde_json::json!(2)));
+        assert_eq!(
+            call.hash_values(&scope).unwrap()["styling"],
+            serde_json::json!([1, "a"])
+        );
+        assert_eq!(call.param(1, &scope).unwrap(), None);
+    }
+
+    #[test]
+    fn missing_paths_are_null() {
+        let data = serde_json::json!({"rows": [{"a": 1}]});
+        let scope = HelperScope {
+            data: &data,
+            files: &FileAccess::default(),
+        };
+        let call = HelperCall::parse("f rows.0.a rows.1.a rows.x this").unwrap();
+        let values: Vec<serde_json::Value> = (0..4)
+            .map(|n| call.param(n, &scope).unwrap().unwrap())
+            .collect();
+        assert_eq!(values[0], serde_json::json!(1));
+        assert_eq!(values[1], serde_json::Value::Null);
+        assert_eq!(values[2], serde_json::Value::Null);
+        assert_eq!(values[3], data);
+    }
+
+    #[test]
+    fn malformed_calls() {
+        for text in [
+            "",
+            "(table t)",
+            "table \"unterminated",
+            "table (lookup a b",
+            "table a)",
+            "table columns=",
+            "table =a",
+            "table columns=a t",
+            "table (unknown a)",
+        ]
+        .iter()
+        {
+            let result = HelperCall::parse(text).and_then(|call| {
+                let data = serde_json::json!({});
+                let scope = HelperScope {
+                    data: &data,
+                    files: &FileAccess::default(),
+                };
+                call.param(0, &scope)
+            });
+            assert!(
+                matches!(result, Err(TextkitDocxError::BadHelperCall(_))),
+                "{}",
+                text
+            );
+        }
+    }
+}
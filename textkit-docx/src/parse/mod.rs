@@ -1,10 +1,15 @@
 mod accumulator;
 mod compressor;
+mod helper_call;
 
 use crate::errors::TextkitDocxError;
+use crate::render::{is_wp_end_token, is_wp_start_token, matching_end_index};
 use crate::{PageDimensions, TemplateArea, Token, TokenType, NS_WP_ML, PAT_HB_CPX, PAT_HB_SMP};
 use accumulator::TemplateAccumulator;
 use compressor::compress_tokens;
+pub(crate) use helper_call::{
+    option_bool, option_list, option_text, option_usize, HelperCall, HelperScope,
+};
 use regex::Regex;
 use std::io::BufReader;
 use std::io::{Read, Seek};
@@ -245,6 +250,34 @@ pub(crate) fn find_template_areas(
     result
 }
 
+/// Find the start and end indices of the innermost `w:` element with the given
+/// name that encloses the token at `token_index`.
+pub(crate) fn find_enclosing_element(
+    token_vec: &[Token],
+    token_index: usize,
+    element_name: &str,
+) -> Option<(usize, usize)> {
+    let mut depth: usize = 0;
+    let mut start_index: Option<usize> = None;
+
+    for anchor in (0..token_index).rev() {
+        let token = &token_vec[anchor];
+        if is_wp_end_token(token, element_name) {
+            depth += 1;
+        } else if is_wp_start_token(token, element_name) {
+            if depth == 0 {
+                start_index = Some(anchor);
+                break;
+            }
+            depth -= 1;
+        }
+    }
+
+    let start_index = start_index?;
+    let end_index = matching_end_index(token_vec, start_index, element_name)?;
+    Some((start_index, end_index))
+}
+
 pub(crate) fn unzip_text_file<T: Read + Seek>(
     archive: &mut ZipArchive<T>,
     file_name: &str,
@@ -254,3 +287,13 @@ pub(crate) fn unzip_text_file<T: Read + Seek>(
     file.read_to_string(&mut contents)?;
     Ok(contents)
 }
+
+/// Map straight and typographic quotes to `"` or `'`. Word does not always pick
+/// the opening variant, so both variants of a kind open and close a value.
+pub(crate) fn quote_kind(c: char) -> Option<char> {
+    match c {
+        '"' | '\u{201C}' | '\u{201D}' => Some('"'),
+        '\'' | '\u{2018}' | '\u{2019}' => Some('\''),
+        _ => None,
+    }
+}
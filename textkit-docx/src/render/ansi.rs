@@ -0,0 +1,252 @@
+//! ANSI escape codes in terminal output (e.g. Python tracebacks in notebooks).
+//! Colours and bold (SGR codes) become run properties, every other escape
+//! sequence is dropped.
+//!
+
+use crate::render::code_block::OutputSpan;
+
+/// The eight standard and eight bright terminal colours, as Jupyter shows
+/// them.
+const ANSI_COLORS: [&str; 16] = [
+    "3E424D", "E75C58", "00A250", "DDB62B", "208FFB", "D160C4", "60C6C8", "C5C1B4", "282C36",
+    "B22B31", "007427", "B27D12", "0065CA", "A03196", "258F8F", "A1A6B2",
+];
+
+/// Lines of text with escape codes turned into coloured spans. Text after a
+/// carriage return starts the line over, as a progress bar in a terminal
+/// does.
+pub(crate) fn ansi_lines(text: &str) -> Vec<Vec<OutputSpan>> {
+    let chars: Vec<char> = text.trim_end_matches(&['\n', '\r'][..]).chars().collect();
+    let mut lines: Vec<Vec<OutputSpan>> = vec![Vec::new()];
+    let mut current = OutputSpan::default();
+    let mut overwrite = false;
+    let mut i: usize = 0;
+
+    let flush = |lines: &mut Vec<Vec<OutputSpan>>, current: &mut OutputSpan| {
+        if !current.text.is_empty() {
+            if let Some(line) = lines.last_mut() {
+                line.push(current.clone());
+            }
+            current.text.clear();
+        }
+    };
+
+    while i < chars.len() {
+        match chars[i] {
+            '\u{1B}' => {
+                flush(&mut lines, &mut current);
+                i = match chars.get(i + 1) {
+                    // Control sequence, e.g. `ESC[1;31m`.
+                    Some('[') => {
+                        let start = i + 2;
+                        let end = (start..chars.len())
+                            .find(|j| ('\u{40}'..='\u{7E}').contains(&chars[*j]))
+                            .unwrap_or(chars.len());
+                        if chars.get(end) == Some(&'m') {
+                            let parameters: String = chars[start..end].iter().collect();
+                            apply_sgr(&parameters, &mut current);
+                        }
+                        end + 1
+                    }
+                    // Operating system command, e.g. a window title, up to
+                    // BEL or `ESC\`.
+                    Some(']') => (i + 2..chars.len())
+                        .find(|j| chars[*j] == '\u{7}' || chars[*j] == '\u{1B}')
+                        .map_or(chars.len(), |j| {
+                            if chars[j] == '\u{1B}' {
+                                j + 2
+                            } else {
+                                j + 1
+                            }
+                        }),
+                    // Sequences with intermediate bytes, e.g. `ESC(B`, which
+                    // selects a character set.
+                    Some(c) if ('\u{20}'..='\u{2F}').contains(c) => (i + 2..chars.len())
+                        .find(|j| !('\u{20}'..='\u{2F}').contains(&chars[*j]))
+                        .map_or(chars.len(), |j| j + 1),
+                    Some(_) => i + 2,
+                    None => i + 1,
+                };
+            }
+            '\r' if chars.get(i + 1) == Some(&'\n') => i += 1,
+            '\r' => {
+                overwrite = true;
+                i += 1;
+            }
+            '\n' => {
+                flush(&mut lines, &mut current);
+                lines.push(Vec::new());
+                overwrite = false;
+                i += 1;
+            }
+            c if c.is_control() && c != '\t' => i += 1,
+            c => {
+                if overwrite {
+                    current.text.clear();
+                    if let Some(line) = lines.last_mut() {
+                        line.clear();
+                    }
+                    overwrite = false;
+                }
+                current.text.push(c);
+                i += 1;
+            }
+        }
+    }
+    flush(&mut lines, &mut current);
+
+    lines
+}
+
+/// Apply the parameters of a Select Graphic Rendition (`m`) sequence.
+fn apply_sgr(parameters: &str, span: &mut OutputSpan) {
+    let codes: Vec<usize> = parameters
+        .split(';')
+        .map(|code| code.parse().unwrap_or(0))
+        .collect();
+    let mut i: usize = 0;
+
+    while i < codes.len() {
+        match codes[i] {
+            0 => {
+                span.color = None;
+                span.bold = false;
+            }
+            1 => span.bold = true,
+            22 => span.bold = false,
+            code @ 30..=37 => span.color = Some(ANSI_COLORS[code - 30]),
+            code @ 90..=97 => span.color = Some(ANSI_COLORS[code - 90 + 8]),
+            39 => span.color = None,
+            // 256 colours (`38;5;n`) and true colour (`38;2;r;g;b`) are
+            // mapped to the nearest of the sixteen. Background colours are
+            // left out.
+            38 | 48 => {
+                let foreground = codes[i] == 38;
+                match codes.get(i + 1) {
+                    Some(5) => {
+                        if foreground {
+                            span.color = codes.get(i + 2).and_then(|n| palette_color(*n));
+                        }
+                        i += 2;
+                    }
+                    Some(2) => {
+                        if foreground {
+                            span.color =
+                                match (codes.get(i + 2), codes.get(i + 3), codes.get(i + 4)) {
+                                    (Some(r), Some(g), Some(b)) => Some(nearest_color(*r, *g, *b)),
+                                    _ => None,
+                                };
+                        }
+                        i += 4;
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+}
+
+/// Colour of the 256-colour palette: the sixteen, a 6x6x6 cube and greys.
+fn palette_color(n: usize) -> Option<&'static str> {
+    match n {
+        0..=15 => Some(ANSI_COLORS[n]),
+        16..=231 => {
+            let level = |value: usize| if value == 0 { 0 } else { 55 + value * 40 };
+            let n = n - 16;
+            Some(nearest_color(level(n / 36), level(n / 6 % 6), level(n % 6)))
+        }
+        232..=255 => {
+            let grey = 8 + (n - 232) * 10;
+            Some(nearest_color(grey, grey, grey))
+        }
+        _ => None,
+    }
+}
+
+fn nearest_color(r: usize, g: usize, b: usize) -> &'static str {
+    let distance = |color: &&str| {
+        let channel = |i: usize| usize::from_str_radix(&color[i..i + 2], 16).unwrap_or(0);
+        let d = |a: usize, b: usize| (a as i64 - b as i64).pow(2);
+        d(channel(0), r) + d(channel(2), g) + d(channel(4), b)
+    };
+    ANSI_COLORS
+        .iter()
+        .min_by_key(|color| distance(color))
+        .copied()
+        .unwrap_or(ANSI_COLORS[0])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn span(text: &str, color: Option<&'static str>, bold: bool) -> OutputSpan {
+        OutputSpan {
+            text: String::from(text),
+            color,
+            bold,
+        }
+    }
+
+    #[test]
+    fn colors_and_bold() {
+        assert_eq!(
+            ansi_lines("\u{1B}[0;31mValueError\u{1B}[0m: bad\n\u{1B}[1;92mok\u{1B}[22m!\u{1B}[39m"),
+            vec![
+                vec![
+                    span("ValueError", Some(ANSI_COLORS[1]), false),
+                    span(": bad", None, false)
+                ],
+                vec![
+                    span("ok", Some(ANSI_COLORS[10]), true),
+                    span("!", Some(ANSI_COLORS[10]), false)
+                ],
+            ]
+        );
+    }
+
+    #[test]
+    fn extended_colors_map_to_the_nearest() {
+        assert_eq!(
+            ansi_lines("\u{1B}[38;5;196mred\u{1B}[38;2;0;0;250mblue\u{1B}[48;5;21mbg"),
+            vec![vec![
+                span("red", Some(ANSI_COLORS[9]), false),
+                span("blue", Some(ANSI_COLORS[12]), false),
+                span("bg", Some(ANSI_COLORS[12]), false)
+            ]]
+        );
+    }
+
+    #[test]
+    fn other_sequences_are_dropped() {
+        assert_eq!(
+            ansi_lines("\u{1B}]0;title\u{7}a\u{1B}[2Kb\u{1B}(Bc\u{8}"),
+            vec![vec![
+                span("a", None, false),
+                span("b", None, false),
+                span("c", None, false)
+            ]]
+        );
+    }
+
+    #[test]
+    fn trailing_carriage_return_keeps_the_line() {
+        assert_eq!(
+            ansi_lines("\u{1B}[32m 50%\r\u{1B}[32m100%\r"),
+            vec![vec![span("100%", Some(ANSI_COLORS[2]), false)]]
+        );
+    }
+
+    #[test]
+    fn carriage_return_overwrites_the_line() {
+        assert_eq!(
+            ansi_lines("10%\r50%\r100%\ndone\r\n"),
+            vec![
+                vec![span("100%", None, false)],
+                vec![span("done", None, false)]
+            ]
+        );
+    }
+}
@@ -0,0 +1,246 @@
+//! Code blocks: monospace paragraphs in a bordered, shaded box, with
+//! syntax-highlighted runs and optionally line numbers. Output of code (e.g.
+//! of notebook cells) is set the same way, without the box.
+//!
+
+use crate::errors::TextkitDocxError;
+use crate::parse::option_bool;
+use crate::render::{
+    char_text_tokens, highlight::highlight, run_end_token, run_start_token, wp_empty_tokens,
+    wp_end_token, wp_start_token,
+};
+use crate::Token;
+use std::collections::BTreeMap;
+
+/// Font of code blocks.
+const CODE_FONT: &str = "Consolas";
+
+/// Font size of code blocks, in half points.
+const CODE_FONT_SIZE: &str = "18";
+
+/// Background colour and border colour of code blocks.
+const CODE_BLOCK_SHADING: &str = "F6F8FA";
+const CODE_BLOCK_BORDER: &str = "D0D7DE";
+
+/// Colour of line numbers.
+const LINE_NUMBER_COLOR: &str = "8C959F";
+
+/// Background colour of error output, as in Jupyter.
+const ERROR_OUTPUT_SHADING: &str = "FFDDDD";
+
+/// Tabs are expanded to this many spaces, so that indentation is preserved.
+const TAB_WIDTH: usize = 4;
+
+/// How code blocks are rendered.
+#[derive(Debug, Clone, Copy)]
+pub struct CodeBlockOptions {
+    /// Colour keywords, strings, comments, etc. when the language is known.
+    pub highlight: bool,
+
+    /// Put the line number in front of every line.
+    pub line_numbers: bool,
+}
+
+impl Default for CodeBlockOptions {
+    fn default() -> Self {
+        Self {
+            highlight: true,
+            line_numbers: false,
+        }
+    }
+}
+
+impl CodeBlockOptions {
+    /// Take the code block options (`highlight` and `line_numbers`) out of the
+    /// hash arguments of a placeholder.
+    pub(crate) fn take_from_hash_args(
+        &self,
+        hash_args: &mut BTreeMap<String, serde_json::Value>,
+    ) -> Result<Self, TextkitDocxError> {
+        let mut options = *self;
+
+        for key in ["highlight", "line_numbers"].iter() {
+            if let Some(value) = hash_args.remove(*key) {
+                let enabled = option_bool(&value).ok_or_else(|| {
+                    TextkitDocxError::BadHelperOption(format!("{}={}", key, value))
+                })?;
+                match *key {
+                    "highlight" => options.highlight = enabled,
+                    _ => options.line_numbers = enabled,
+                }
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+/// What a monospace block shows, which sets its border and shading.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum BlockKind {
+    /// Code, in a bordered, shaded box.
+    Code,
+    /// Output of code, e.g. of a notebook cell, without a box.
+    Output,
+    /// Error output (stderr, tracebacks), on a red background.
+    Error,
+}
+
+/// A piece of a line of output, with its colour and weight (e.g. from ANSI
+/// escape codes).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct OutputSpan {
+    pub text: String,
+    pub color: Option<&'static str>,
+    pub bold: bool,
+}
+
+/// One paragraph per line of code. Consecutive paragraphs with the same
+/// borders are drawn by Word as a single box.
+pub(crate) fn code_block_tokens(
+    code: &str,
+    language: Option<&str>,
+    options: &CodeBlockOptions,
+) -> Vec<Token> {
+    let mut result: Vec<Token> = Vec::new();
+
+    let code = code
+        .trim_end_matches('\n')
+        .replace('\t', &" ".repeat(TAB_WIDTH));
+    let language = if options.highlight { language } else { None };
+    let lines = highlight(&code, language);
+    let number_width = format!("{}", lines.len()).len();
+
+    for (line_i, spans) in lines.iter().enumerate() {
+        result.push(wp_start_token("p", &[]));
+        result.extend(code_paragraph_properties_tokens(BlockKind::Code));
+
+        if options.line_numbers {
+            let number = format!("{:>width$}  ", line_i + 1, width = number_width);
+            result.extend(code_run_tokens(&number, Some(LINE_NUMBER_COLOR), false));
+        }
+        for (kind, text) in spans.iter() {
+            result.extend(code_run_tokens(text, kind.color(), false));
+        }
+
+        result.push(wp_end_token("p"));
+    }
+
+    result
+}
+
+/// One monospace paragraph per line of output.
+pub(crate) fn output_block_tokens(lines: &[Vec<OutputSpan>], kind: BlockKind) -> Vec<Token> {
+    let mut result: Vec<Token> = Vec::new();
+
+    for spans in lines.iter() {
+        result.push(wp_start_token("p", &[]));
+        result.extend(code_paragraph_properties_tokens(kind));
+        for span in spans.iter() {
+            let text = span.text.replace('\t', &" ".repeat(TAB_WIDTH));
+            result.extend(code_run_tokens(&text, span.color, span.bold));
+        }
+        result.push(wp_end_token("p"));
+    }
+
+    result
+}
+
+/// A label above a code block, e.g. the `In [3]:` prompt of a notebook cell.
+pub(crate) fn code_label_tokens(label: &str) -> Vec<Token> {
+    let mut result: Vec<Token> = Vec::new();
+
+    result.push(wp_start_token("p", &[]));
+    result.push(wp_start_token("pPr", &[]));
+    result.extend(wp_empty_tokens("keepNext", &[]));
+    result.extend(wp_empty_tokens(
+        "spacing",
+        &[
+            ("before", "120"),
+            ("after", "40"),
+            ("line", "240"),
+            ("lineRule", "auto"),
+        ],
+    ));
+    result.push(wp_end_token("pPr"));
+    result.extend(code_run_tokens(label, Some(LINE_NUMBER_COLOR), false));
+    result.push(wp_end_token("p"));
+
+    result
+}
+
+fn code_paragraph_properties_tokens(kind: BlockKind) -> Vec<Token> {
+    let mut result: Vec<Token> = Vec::new();
+
+    // The children of `w:pPr` have to be in the order of the schema.
+    result.push(wp_start_token("pPr", &[]));
+    if kind == BlockKind::Code {
+        result.push(wp_start_token("pBdr", &[]));
+        for side in ["top", "left", "bottom", "right"].iter() {
+            result.extend(wp_empty_tokens(
+                side,
+                &[
+                    ("val", "single"),
+                    ("sz", "4"),
+                    ("space", "4"),
+                    ("color", CODE_BLOCK_BORDER),
+                ],
+            ));
+        }
+        result.push(wp_end_token("pBdr"));
+    }
+    let shading = match kind {
+        BlockKind::Code => Some(CODE_BLOCK_SHADING),
+        BlockKind::Output => None,
+        BlockKind::Error => Some(ERROR_OUTPUT_SHADING),
+    };
+    if let Some(shading) = shading {
+        result.extend(wp_empty_tokens(
+            "shd",
+            &[("val", "clear"), ("color", "auto"), ("fill", shading)],
+        ));
+    }
+    result.extend(wp_empty_tokens(
+        "spacing",
+        &[
+            ("before", "0"),
+            ("after", "0"),
+            ("line", "240"),
+            ("lineRule", "auto"),
+        ],
+    ));
+    result.extend(wp_empty_tokens("ind", &[("left", "113"), ("right", "113")]));
+    result.push(wp_end_token("pPr"));
+
+    result
+}
+
+fn code_run_tokens(text: &str, color: Option<&str>, bold: bool) -> Vec<Token> {
+    let mut result: Vec<Token> = Vec::new();
+
+    result.push(run_start_token());
+    result.push(wp_start_token("rPr", &[]));
+    result.extend(wp_empty_tokens(
+        "rFonts",
+        &[
+            ("ascii", CODE_FONT),
+            ("hAnsi", CODE_FONT),
+            ("cs", CODE_FONT),
+        ],
+    ));
+    if bold {
+        result.extend(wp_empty_tokens("b", &[]));
+        result.extend(wp_empty_tokens("bCs", &[]));
+    }
+    result.extend(wp_empty_tokens("noProof", &[]));
+    if let Some(color) = color {
+        result.extend(wp_empty_tokens("color", &[("val", color)]));
+    }
+    result.extend(wp_empty_tokens("sz", &[("val", CODE_FONT_SIZE)]));
+    result.extend(wp_empty_tokens("szCs", &[("val", CODE_FONT_SIZE)]));
+    result.push(wp_end_token("rPr"));
+    result.extend(char_text_tokens(text, true));
+    result.push(run_end_token());
+
+    result
+}
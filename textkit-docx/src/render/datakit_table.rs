@@ -1,11 +1,287 @@
-use crate::render::{end_tag_event, owned_attribute, paragraph_tokens, start_tag_event};
+use crate::errors::TextkitDocxError;
+use crate::parse::{option_bool, option_list, option_text, option_usize};
+use crate::render::{
+    char_text_tokens, end_tag_event,
+    format::{format_value, ValueFormat},
+    is_wp_start_token, matching_end_index, owned_attribute, paragraph_tokens, run_end_token,
+    run_start_token, start_tag_event,
+    table_data::TableData,
+    table_style::{group_header_spans, Condition, TableStyling, VerticalMerge},
+    wp_empty_tokens, wp_end_token, wp_start_token,
+};
 use crate::NS_WP_ML;
 use crate::{PageDimensions, Token, TokenType};
-use datakit::{table::Table, value::definitions::*, value::primitives::*};
+use std::collections::BTreeMap;
 
-pub(crate) fn datakit_table_to_tokens(table: &Table, dims: &PageDimensions) -> Vec<Token> {
+/// Columns narrower than this (in characters of content) are treated as
+/// if they had this much content when distributing the table width.
+const MIN_COLUMN_WEIGHT: usize = 4;
+
+/// Caps the weight of a single column, so that one column with long
+/// free text does not squeeze all other columns to nothing.
+const MAX_COLUMN_WEIGHT: usize = 40;
+
+/// Explicit width of a single table column.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ColumnWidth {
+    /// Width in twentieths of a point (the `dxa` unit of DOCX).
+    Twips(i32),
+    /// Width as a percentage of the usable page width.
+    Percent(f32),
+}
+
+/// Horizontal alignment of the contents of a column.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColumnAlignment {
+    Left,
+    Center,
+    Right,
+}
+
+impl ColumnAlignment {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "left" => Some(Self::Left),
+            "center" | "centre" => Some(Self::Center),
+            "right" => Some(Self::Right),
+            _ => None,
+        }
+    }
+
+    /// Value of the paragraph justification (`w:jc`).
+    fn jc_value(self) -> &'static str {
+        match self {
+            Self::Left => "left",
+            Self::Center => "center",
+            Self::Right => "right",
+        }
+    }
+}
+
+/// Options controlling how a table is laid out in the document.
+#[derive(Debug, Clone, Default)]
+pub struct TableOptions {
+    /// Explicit widths per column. Missing entries (or `None`s) get a share of
+    /// the remaining page width proportional to the length of their contents.
+    pub column_widths: Vec<Option<ColumnWidth>>,
+
+    /// Let Word resize the columns to fit their contents (`w:tblLayout` of type
+    /// `autofit`) instead of using fixed column widths.
+    pub autofit: bool,
+
+    /// How values are formatted unless the column has its own format.
+    pub format: ValueFormat,
+
+    /// Formats for individual columns, by column index.
+    pub column_formats: BTreeMap<usize, ValueFormat>,
+
+    /// Formats for individual columns, by column name (original or renamed)
+    /// or 1-based number. They take precedence over `column_formats`.
+    pub named_column_formats: BTreeMap<String, ValueFormat>,
+
+    /// Only render these columns (by name or 1-based number), in this order.
+    pub columns: Option<Vec<String>>,
+
+    /// Alignments for individual columns, by column index. Other columns are
+    /// aligned like normal paragraphs.
+    pub column_alignments: BTreeMap<usize, ColumnAlignment>,
+
+    /// Column names to show in the header instead of the original ones.
+    pub rename: BTreeMap<String, String>,
+
+    /// Do not render a header row, even if the columns have names.
+    pub omit_header: bool,
+
+    /// Only render the rows for which all of these conditions hold.
+    pub filter: Vec<Condition>,
+
+    /// Sort the rows by these columns (by name or 1-based number), each either
+    /// ascending or descending (`true`).
+    pub sort: Vec<(String, bool)>,
+
+    /// Render at most this many rows (not counting the totals row).
+    pub limit: Option<usize>,
+
+    /// Append a row with the sums of these columns (by name or 1-based number).
+    pub totals: Vec<String>,
+}
+
+impl TableOptions {
+    /// The value format to use for the given column.
+    pub fn column_format(&self, col_i: usize) -> &ValueFormat {
+        self.column_formats.get(&col_i).unwrap_or(&self.format)
+    }
+
+    /// Paragraph justification for the given column, if it has an alignment.
+    fn column_jc(&self, col_i: usize) -> Option<&'static str> {
+        self.column_alignments
+            .get(&col_i)
+            .map(|alignment| alignment.jc_value())
+    }
+
+    /// A copy of these options, overridden by the hash arguments of a table
+    /// helper placeholder, e.g.
+    /// `{{table my_table columns="a,b" sort="score desc" limit=20 where="status == 'open'" totals="amount"}}`.
+    ///
+    /// `rename="amount:Amount (EUR), n:Count"` changes the column names shown
+    /// in the header.
+    ///
+    /// The fields of [`ValueFormat`] (`decimals`, `thousands_separator`,
+    /// `percent`, `date_pattern`, `null_text` etc.) set the format of all
+    /// columns. `column_formats` sets formats for individual columns, given as
+    /// an object (usually from the data, or as JSON text) of column names or
+    /// numbers to objects with the same fields, e.g.
+    /// `{"share": {"percent": true, "decimals": 1}}`.
+    pub(crate) fn with_hash_args(
+        &self,
+        hash_args: &BTreeMap<String, serde_json::Value>,
+    ) -> Result<Self, TextkitDocxError> {
+        let mut options = self.clone();
+        let mut column_formats = None;
+
+        for (key, value) in hash_args.iter() {
+            let bad_option = || TextkitDocxError::BadHelperOption(format!("{}={}", key, value));
+
+            match key.as_str() {
+                "columns" => options.columns = Some(option_list(value)),
+                "sort" => {
+                    options.sort = option_list(value)
+                        .iter()
+                        .map(|key| {
+                            let lowercase = key.to_lowercase();
+                            if lowercase.ends_with(" desc") {
+                                (String::from(key[..key.len() - 5].trim()), true)
+                            } else if lowercase.ends_with(" asc") {
+                                (String::from(key[..key.len() - 4].trim()), false)
+                            } else {
+                                (key.clone(), false)
+                            }
+                        })
+                        .collect()
+                }
+                "limit" => options.limit = Some(option_usize(value).ok_or_else(bad_option)?),
+                "where" => {
+                    let conditions =
+                        Condition::parse_all(&option_text(value)).ok_or_else(bad_option)?;
+                    options.filter.extend(conditions);
+                }
+                "totals" => options.totals = option_list(value),
+                "rename" => {
+                    // Either an object from the data, or `"old:new, ..."`.
+                    let renames: Vec<(String, String)> = match value {
+                        serde_json::Value::Object(renames) => renames
+                            .iter()
+                            .map(|(old, new)| (old.clone(), option_text(new)))
+                            .collect(),
+                        other => option_list(other)
+                            .iter()
+                            .map(|rename| {
+                                let (old, new) = rename.split_at(rename.find(':')?);
+                                Some((String::from(old.trim()), String::from(new[1..].trim())))
+                            })
+                            .collect::<Option<_>>()
+                            .ok_or_else(bad_option)?,
+                    };
+                    options.rename.extend(renames);
+                }
+                "align" => {
+                    for (col_i, name) in option_list(value).iter().enumerate() {
+                        // `-` leaves a column as it is.
+                        if name == "-" {
+                            continue;
+                        }
+                        let alignment = ColumnAlignment::from_name(name).ok_or_else(bad_option)?;
+                        options.column_alignments.insert(col_i, alignment);
+                    }
+                }
+                "header" => options.omit_header = !option_bool(value).ok_or_else(bad_option)?,
+                "autofit" => options.autofit = option_bool(value).ok_or_else(bad_option)?,
+                // Applied last, so that they start out from the table format.
+                "column_formats" => column_formats = Some(value),
+                // Handled by the template, as it is not a layout option.
+                "styling" => (),
+                format_option => {
+                    if !options.format.set_option(format_option, value) {
+                        return Err(bad_option());
+                    }
+                }
+            }
+        }
+
+        if let Some(value) = column_formats {
+            let bad_option =
+                || TextkitDocxError::BadHelperOption(format!("column_formats={}", value));
+            let formats = match value {
+                serde_json::Value::String(text) => {
+                    serde_json::from_str(text).map_err(|_| bad_option())?
+                }
+                other => other.clone(),
+            };
+            let formats = match formats {
+                serde_json::Value::Object(formats) => formats,
+                _ => return Err(bad_option()),
+            };
+            for (column, settings) in formats.iter() {
+                let settings = settings.as_object().ok_or_else(bad_option)?;
+                let mut format = options.format.clone();
+                for (name, setting) in settings.iter() {
+                    if !format.set_option(name, setting) {
+                        return Err(bad_option());
+                    }
+                }
+                options.named_column_formats.insert(column.clone(), format);
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+pub(crate) fn datakit_table_to_tokens(
+    table_data: &TableData,
+    styling: &TableStyling,
+    dims: &PageDimensions,
+    options: &TableOptions,
+) -> Vec<Token> {
     let mut result: Vec<Token> = Vec::new();
 
+    let table = prepare_table(table_data, options);
+    let options = &resolve_column_formats(&table, options);
+    let has_header = !options.omit_header && !table.headers.is_empty();
+
+    // A `w:tbl` needs at least one row and one column to be valid.
+    if table.no_of_cols() == 0 || (table.no_of_rows() == 0 && !has_header) {
+        return result;
+    }
+
+    let no_of_cols = table.no_of_cols();
+    let cell_texts: Vec<Vec<String>> = (0..no_of_cols)
+        .map(|col_i| {
+            let format = options.column_format(col_i);
+            let mut texts: Vec<String> = (0..table.no_of_rows())
+                .map(|row_i| format_value(table.cell(col_i, row_i), format))
+                .collect();
+            // The header takes up room as well, so it counts towards the
+            // column widths.
+            if has_header {
+                texts.push(table.headers[col_i].clone());
+            }
+            texts
+        })
+        .collect();
+    let col_widths = column_widths(&cell_texts, dims, options);
+    let table_width: i32 = col_widths.iter().sum();
+    // Autofit tables leave the widths to Word, except the explicit ones.
+    let pinned_widths: Vec<Option<i32>> = col_widths
+        .iter()
+        .enumerate()
+        .map(|(col_i, width)| match options.column_widths.get(col_i) {
+            Some(Some(_)) => Some(*width),
+            _ if options.autofit => None,
+            _ => Some(*width),
+        })
+        .collect();
+
     // <w:tbl>
     result.push(Token {
         token_type: TokenType::Normal,
@@ -60,41 +336,21 @@ pub(crate) fn datakit_table_to_tokens(table: &Table, dims: &PageDimensions) -> V
         token_text: None,
     });
 
-    // <w:tblW w:w="0" w:type="auto" />
-    result.push(Token {
-        token_type: TokenType::Normal,
-        // xml_reader_event: start_tag_event("tblW", Some(&[("w", "w", "0"), ("w", "type", "auto")])),
-        xml_reader_event: start_tag_event(
-            &Some(String::from("w")),
-            &Some(String::from(NS_WP_ML)),
-            &String::from("tblW"),
-            Some(&vec![
-                owned_attribute(
-                    &Some(String::from("w")),
-                    &Some(String::from(NS_WP_ML)),
-                    &String::from("w"),
-                    &String::from("0"),
-                ),
-                owned_attribute(
-                    &Some(String::from("w")),
-                    &Some(String::from(NS_WP_ML)),
-                    &String::from("type"),
-                    &String::from("auto"),
-                ),
-            ]),
-        ),
-        token_text: None,
-    });
-    result.push(Token {
-        token_type: TokenType::Normal,
-        // xml_reader_event: end_tag_event("tblW"),
-        xml_reader_event: end_tag_event(
-            &Some(String::from("w")),
-            &Some(String::from(NS_WP_ML)),
-            &String::from("tblW"),
-        ),
-        token_text: None,
-    });
+    // <w:tblW w:w="<TABLE_WIDTH>" w:type="dxa" /> for fixed layouts and
+    // <w:tblW w:w="0" w:type="auto" /> for autofit.
+    let table_width_str = format!("{}", table_width);
+    if options.autofit {
+        result.extend(wp_empty_tokens("tblW", &[("w", "0"), ("type", "auto")]));
+    } else {
+        result.extend(wp_empty_tokens(
+            "tblW",
+            &[("w", &table_width_str), ("type", "dxa")],
+        ));
+    }
+
+    // <w:tblLayout w:type="fixed|autofit" />
+    let layout_type = if options.autofit { "autofit" } else { "fixed" };
+    result.extend(wp_empty_tokens("tblLayout", &[("type", layout_type)]));
 
     // <w:tblLook
     //     w:val="04A0"
@@ -205,40 +461,14 @@ pub(crate) fn datakit_table_to_tokens(table: &Table, dims: &PageDimensions) -> V
         token_text: None,
     });
 
-    // Now we need to figure out how many columns there are and what
-    // the individual column width is (assuming equal widths).
-    let no_of_cols = table.columns().len();
-    let col_width = (dims.width - dims.m_left - dims.m_right) / 2 + 15;
-    let col_width_str = format!("{}", col_width);
-
-    for _ in 0..no_of_cols {
-        // <w:gridCol w:w="<COL_WIDTH>"
-        result.push(Token {
-            token_type: TokenType::Normal,
-            // xml_reader_event: start_tag_event("gridCol", Some(&[("w", "w", &col_width_str)])),
-            xml_reader_event: start_tag_event(
-                &Some(String::from("w")),
-                &Some(String::from(NS_WP_ML)),
-                &String::from("gridCol"),
-                Some(&vec![owned_attribute(
-                    &Some(String::from("w")),
-                    &Some(String::from(NS_WP_ML)),
-                    &String::from("w"),
-                    &col_width_str,
-                )]),
-            ),
-            token_text: None,
-        });
-        result.push(Token {
-            token_type: TokenType::Normal,
-            // xml_reader_event: end_tag_event("gridCol"),
-            xml_reader_event: end_tag_event(
-                &Some(String::from("w")),
-                &Some(String::from(NS_WP_ML)),
-                &String::from("gridCol"),
-            ),
-            token_text: None,
-        });
+    // <w:gridCol w:w="<COL_WIDTH>" /> (or without a width for autofit)
+    for col_width in pinned_widths.iter() {
+        let col_width_str = col_width.map(|width| format!("{}", width));
+        let attrs: Vec<(&str, &str)> = col_width_str
+            .iter()
+            .map(|width| ("w", width.as_str()))
+            .collect();
+        result.extend(wp_empty_tokens("gridCol", &attrs));
     }
 
     // <w:tblGrid>
@@ -253,8 +483,61 @@ pub(crate) fn datakit_table_to_tokens(table: &Table, dims: &PageDimensions) -> V
         token_text: None,
     });
 
+    // Group headers, with equal adjacent labels merged into one cell.
+    if has_header {
+        for labels in styling.group_headers.iter() {
+            result.push(wp_start_token("tr", &[]));
+            result.push(wp_start_token("trPr", &[]));
+            result.extend(wp_empty_tokens("tblHeader", &[]));
+            result.push(wp_end_token("trPr"));
+
+            let mut col_i: usize = 0;
+            for (label, span) in group_header_spans(labels, no_of_cols) {
+                let span_width: Option<i32> =
+                    pinned_widths[col_i..col_i + span].iter().copied().sum();
+                let span_str = format!("{}", span);
+                result.push(wp_start_token("tc", &[]));
+                result.push(wp_start_token("tcPr", &[]));
+                result.extend(cell_width_tokens(span_width));
+                if span > 1 {
+                    result.extend(wp_empty_tokens("gridSpan", &[("val", &span_str)]));
+                }
+                result.push(wp_end_token("tcPr"));
+                result.extend(styled_paragraph_tokens(&label, true, false, Some("center")));
+                result.push(wp_end_token("tc"));
+                col_i += span;
+            }
+
+            result.push(wp_end_token("tr"));
+        }
+    }
+
+    // The header row, repeated on every page the table spans.
+    if has_header {
+        result.push(wp_start_token("tr", &[]));
+        result.push(wp_start_token("trPr", &[]));
+        result.extend(wp_empty_tokens("tblHeader", &[]));
+        result.push(wp_end_token("trPr"));
+
+        for (col_i, header) in table.headers.iter().enumerate() {
+            result.push(wp_start_token("tc", &[]));
+            result.push(wp_start_token("tcPr", &[]));
+            result.extend(cell_width_tokens(pinned_widths[col_i]));
+            result.push(wp_end_token("tcPr"));
+            result.extend(styled_paragraph_tokens(
+                header,
+                true,
+                false,
+                options.column_jc(col_i),
+            ));
+            result.push(wp_end_token("tc"));
+        }
+
+        result.push(wp_end_token("tr"));
+    }
+
     // Now we need to populate the contents of the the table
-    for row_i in 0..table.len() {
+    for row_i in 0..table.no_of_rows() {
         // <w:tr> - we deliberately omitting any kind of id attributes (like w:rsidR).
         // That's not very compliant as far as I know, but MS Word handles it pretty
         // well, so IDs are a TODO for future versions.
@@ -270,8 +553,12 @@ pub(crate) fn datakit_table_to_tokens(table: &Table, dims: &PageDimensions) -> V
             token_text: None,
         });
 
+        let (bold, italic) = styling.row_emphasis(&table, row_i);
+
         // Populate all table cells for the current row.
         for col_i in 0..no_of_cols {
+            let vertical_merge = styling.vertical_merge(&table, &cell_texts, col_i, row_i);
+
             // <w:tc>
             result.push(Token {
                 token_type: TokenType::Normal,
@@ -299,43 +586,25 @@ pub(crate) fn datakit_table_to_tokens(table: &Table, dims: &PageDimensions) -> V
             });
 
             // <w:tcW w:w="<COL_WIDTH>" w:type="dxa" />
-            result.push(Token {
-                token_type: TokenType::Normal,
-                // xml_reader_event: start_tag_event(
-                //     "tcW",
-                //     Some(&[("w", "w", &col_width_str), ("w", "type", "dxa")]),
-                // ),
-                xml_reader_event: start_tag_event(
-                    &Some(String::from("w")),
-                    &Some(String::from(NS_WP_ML)),
-                    &String::from("tcW"),
-                    Some(&vec![
-                        owned_attribute(
-                            &Some(String::from("w")),
-                            &Some(String::from(NS_WP_ML)),
-                            &String::from("w"),
-                            &col_width_str,
-                        ),
-                        owned_attribute(
-                            &Some(String::from("w")),
-                            &Some(String::from(NS_WP_ML)),
-                            &String::from("type"),
-                            &String::from("dxa"),
-                        ),
-                    ]),
-                ),
-                token_text: None,
-            });
-            result.push(Token {
-                token_type: TokenType::Normal,
-                // xml_reader_event: end_tag_event("tcW"),
-                xml_reader_event: end_tag_event(
-                    &Some(String::from("w")),
-                    &Some(String::from(NS_WP_ML)),
-                    &String::from("tcW"),
-                ),
-                token_text: None,
-            });
+            result.extend(cell_width_tokens(pinned_widths[col_i]));
+
+            // <w:vMerge w:val="restart" /> starts a vertically merged cell,
+            // <w:vMerge /> continues it.
+            match vertical_merge {
+                VerticalMerge::Restart => {
+                    result.extend(wp_empty_tokens("vMerge", &[("val", "restart")]));
+                }
+                VerticalMerge::Continue => result.extend(wp_empty_tokens("vMerge", &[])),
+                VerticalMerge::None => (),
+            }
+
+            // <w:shd w:val="clear" w:color="auto" w:fill="<FILL>" />
+            if let Some(fill) = styling.cell_fill(&table, col_i, row_i) {
+                result.extend(wp_empty_tokens(
+                    "shd",
+                    &[("val", "clear"), ("color", "auto"), ("fill", fill)],
+                ));
+            }
 
             // </w:tcPr>
             result.push(Token {
@@ -349,36 +618,18 @@ pub(crate) fn datakit_table_to_tokens(table: &Table, dims: &PageDimensions) -> V
                 token_text: None,
             });
 
-            // TODO fill this thing with data from the value
-            let cell_value = &table.columns()[col_i][row_i];
-
-            match cell_value {
-                Value::Text(text) => {
-                    let paragraph_tokens = paragraph_tokens(text);
-                    result.extend(paragraph_tokens);
-                }
-                Value::Number(Numeric::Integer(int)) => {
-                    let int_str = format!("{}", int);
-                    let paragraph_tokens = paragraph_tokens(&int_str);
-                    result.extend(paragraph_tokens);
-                }
-                Value::Number(Numeric::Real(real)) => {
-                    let real_str = format!("{:.3}", real);
-                    let paragraph_tokens = paragraph_tokens(&real_str);
-                    result.extend(paragraph_tokens);
-                }
-                Value::Boolean(boolean) => {
-                    let bool_str = if *boolean { "Yes" } else { "No" };
-                    let paragraph_tokens = paragraph_tokens(&bool_str);
-                    result.extend(paragraph_tokens);
-                }
-                Value::DateTime(dt) => {
-                    let dt_str = format!("{}", dt);
-                    let paragraph_tokens = paragraph_tokens(&dt_str);
-                    result.extend(paragraph_tokens);
-                }
-                // TODO implement the rest of value types
-                _ => (),
+            // N.B! Word considers a `w:tc` without a paragraph corrupt, so even
+            // empty values (and continued merges) need to produce one.
+            let text = if vertical_merge == VerticalMerge::Continue {
+                ""
+            } else {
+                &cell_texts[col_i][row_i]
+            };
+            let alignment = options.column_jc(col_i);
+            if bold || italic || alignment.is_some() {
+                result.extend(styled_paragraph_tokens(text, bold, italic, alignment));
+            } else {
+                result.extend(paragraph_tokens(text));
             }
 
             result.push(Token {
@@ -420,3 +671,592 @@ pub(crate) fn datakit_table_to_tokens(table: &Table, dims: &PageDimensions) -> V
 
     result
 }
+
+/// Fill a table that was designed in the template with data. The row with the
+/// placeholder (`prototype_row`, from `<w:tr>` to `</w:tr>`) is repeated for
+/// every row of data, with the text of its n-th cell replaced by the value of
+/// the n-th column. Cell, paragraph and run properties of the prototype are
+/// retained, so banding, borders, fonts, etc. come from the template.
+///
+/// Cells without a column of data are left empty. Data with more columns than
+/// the prototype has cells is an error, as the table would not fit the grid of
+/// the template.
+pub(crate) fn prototype_row_to_tokens(
+    prototype_row: &[Token],
+    table_data: &TableData,
+    options: &TableOptions,
+) -> Result<Vec<Token>, TextkitDocxError> {
+    let mut result: Vec<Token> = Vec::new();
+    let table = prepare_table(table_data, options);
+    let options = &resolve_column_formats(&table, options);
+
+    // Split the row into the part before the cells (`<w:tr>`, `<w:trPr>`), the
+    // cells themselves and whatever comes after them (`</w:tr>`).
+    let mut cells: Vec<&[Token]> = Vec::new();
+    let mut row_prequel_end: Option<usize> = None;
+    let mut row_sequel_start = prototype_row.len();
+    let mut index: usize = 0;
+    while index < prototype_row.len() {
+        if is_wp_start_token(&prototype_row[index], "tc") {
+            if let Some(end) = matching_end_index(prototype_row, index, "tc") {
+                row_prequel_end.get_or_insert(index);
+                cells.push(&prototype_row[index..=end]);
+                row_sequel_start = end + 1;
+                index = end + 1;
+                continue;
+            }
+        }
+        index += 1;
+    }
+    let row_prequel_end = match row_prequel_end {
+        Some(row_prequel_end) => row_prequel_end,
+        None => {
+            return Err(TextkitDocxError::Malformed(String::from(
+                "table row without cells",
+            )))
+        }
+    };
+    if table.no_of_cols() > cells.len() {
+        return Err(TextkitDocxError::BadHelperCall(format!(
+            "tablerows: {} columns of data, but only {} cells in the table row",
+            table.no_of_cols(),
+            cells.len()
+        )));
+    }
+    let row_prequel = &prototype_row[..row_prequel_end];
+    let row_sequel = &prototype_row[row_sequel_start..];
+
+    for row_i in 0..table.no_of_rows() {
+        result.extend(Vec::from(row_prequel));
+        for (col_i, cell) in cells.iter().enumerate() {
+            let text = if col_i < table.no_of_cols() {
+                format_value(table.cell(col_i, row_i), options.column_format(col_i))
+            } else {
+                String::new()
+            };
+            result.extend(prototype_cell_to_tokens(cell, &text));
+        }
+        result.extend(Vec::from(row_sequel));
+    }
+
+    Ok(result)
+}
+
+/// Re-create a table cell (`<w:tc>` to `</w:tc>`) from the template with new
+/// text, keeping the cell properties, as well as the paragraph and run properties
+/// of the first paragraph and run in the cell.
+fn prototype_cell_to_tokens(cell: &[Token], text: &str) -> Vec<Token> {
+    let mut result: Vec<Token> = Vec::new();
+
+    let p_start = cell.iter().position(|token| is_wp_start_token(token, "p"));
+    let cell_sequel = vec![wp_end_token("tc")];
+
+    match p_start {
+        Some(p_start) => {
+            let p_end = matching_end_index(cell, p_start, "p").unwrap_or(cell.len() - 1);
+            let paragraph = &cell[p_start..=p_end];
+
+            // <w:tc> and <w:tcPr>
+            result.extend(Vec::from(&cell[..p_start]));
+
+            // <w:p> and <w:pPr>
+            result.push(paragraph[0].clone());
+            if paragraph.len() > 1 && is_wp_start_token(&paragraph[1], "pPr") {
+                if let Some(ppr_end) = matching_end_index(paragraph, 1, "pPr") {
+                    result.extend(Vec::from(&paragraph[1..=ppr_end]));
+                }
+            }
+
+            result.push(run_start_token());
+            // N.B! Not simply the first `w:rPr` of the paragraph - the paragraph
+            // mark has one inside `w:pPr` too.
+            let run_start = paragraph
+                .iter()
+                .position(|token| is_wp_start_token(token, "r"));
+            if let Some(run_start) = run_start {
+                let rpr_start = run_start + 1;
+                if rpr_start < paragraph.len() && is_wp_start_token(&paragraph[rpr_start], "rPr") {
+                    if let Some(rpr_end) = matching_end_index(paragraph, rpr_start, "rPr") {
+                        result.extend(Vec::from(&paragraph[rpr_start..=rpr_end]));
+                    }
+                }
+            }
+            result.extend(char_text_tokens(text, true));
+            result.push(run_end_token());
+            result.push(wp_end_token("p"));
+        }
+        None => {
+            result.extend(Vec::from(&cell[..cell.len() - 1]));
+            result.extend(paragraph_tokens(text));
+        }
+    }
+
+    result.extend(cell_sequel);
+    result
+}
+
+/// Apply filtering, sorting, limit, totals, column selection and renaming (in
+/// that order) to a copy of the table. Conditions, sort keys and totals refer
+/// to the original column names.
+fn prepare_table(table_data: &TableData, options: &TableOptions) -> TableData {
+    let mut table = table_data.clone();
+    if !options.filter.is_empty() {
+        table.filter_rows(|table, row_i| {
+            options
+                .filter
+                .iter()
+                .all(|condition| condition.matches(table, row_i))
+        });
+    }
+    if !options.sort.is_empty() {
+        let keys: Vec<(usize, bool)> = options
+            .sort
+            .iter()
+            .filter_map(|(name, descending)| Some((table.column_index(name)?, *descending)))
+            .collect();
+        table.sort_rows(&keys);
+    }
+    if let Some(limit) = options.limit {
+        table.truncate_rows(limit);
+    }
+    if !options.totals.is_empty() {
+        let columns: Vec<usize> = options
+            .totals
+            .iter()
+            .filter_map(|name| table.column_index(name))
+            .collect();
+        table.append_totals_row(&columns, "Total");
+    }
+    if let Some(columns) = &options.columns {
+        table.select_columns(columns);
+    }
+    table.rename_columns(&options.rename);
+    table
+}
+
+/// The options with the formats given by column name turned into formats by
+/// column index of the prepared table.
+fn resolve_column_formats(table: &TableData, options: &TableOptions) -> TableOptions {
+    let mut resolved = options.clone();
+    for (name, format) in options.named_column_formats.iter() {
+        let name = options.rename.get(name).unwrap_or(name);
+        if let Some(col_i) = table.column_index(name) {
+            resolved.column_formats.insert(col_i, format.clone());
+        }
+    }
+    resolved
+}
+
+/// A single-run paragraph with optionally bold and/or italic text and
+/// paragraph alignment (`w:jc`), used for header and emphasized cells.
+fn styled_paragraph_tokens(
+    text: &str,
+    bold: bool,
+    italic: bool,
+    alignment: Option<&str>,
+) -> Vec<Token> {
+    let mut result: Vec<Token> = Vec::new();
+
+    result.push(wp_start_token("p", &[]));
+    if let Some(alignment) = alignment {
+        result.push(wp_start_token("pPr", &[]));
+        result.extend(wp_empty_tokens("jc", &[("val", alignment)]));
+        result.push(wp_end_token("pPr"));
+    }
+    result.push(run_start_token());
+    result.push(wp_start_token("rPr", &[]));
+    if bold {
+        result.extend(wp_empty_tokens("b", &[]));
+        result.extend(wp_empty_tokens("bCs", &[]));
+    }
+    if italic {
+        result.extend(wp_empty_tokens("i", &[]));
+        result.extend(wp_empty_tokens("iCs", &[]));
+    }
+    result.push(wp_end_token("rPr"));
+    result.extend(char_text_tokens(text, true));
+    result.push(run_end_token());
+    result.push(wp_end_token("p"));
+
+    result
+}
+
+/// `<w:tcW w:w="<WIDTH>" w:type="dxa" />`, or `<w:tcW w:w="0" w:type="auto" />`
+/// when Word should decide the width.
+fn cell_width_tokens(width: Option<i32>) -> Vec<Token> {
+    match width {
+        Some(width) => wp_empty_tokens("tcW", &[("w", &width.to_string()), ("type", "dxa")]),
+        None => wp_empty_tokens("tcW", &[("w", "0"), ("type", "auto")]),
+    }
+}
+
+/// Figure out the width of every column (in twips), splitting the usable
+/// width of the page between columns without an explicit width according
+/// to the length of their contents.
+pub(crate) fn column_widths(
+    cell_texts: &[Vec<String>],
+    dims: &PageDimensions,
+    options: &TableOptions,
+) -> Vec<i32> {
+    let usable_width = dims.width - dims.m_left - dims.m_right - dims.gutter;
+    let no_of_cols = cell_texts.len();
+
+    let explicit: Vec<Option<i32>> = (0..no_of_cols)
+        .map(|col_i| match options.column_widths.get(col_i) {
+            Some(Some(ColumnWidth::Twips(twips))) => Some(*twips),
+            Some(Some(ColumnWidth::Percent(pct))) => {
+                Some((usable_width as f32 * pct / 100.0).round() as i32)
+            }
+            _ => None,
+        })
+        .collect();
+
+    let weights: Vec<usize> = cell_texts
+        .iter()
+        .map(|column| {
+            column
+                .iter()
+                .map(|text| longest_line_length(text))
+                .max()
+                .unwrap_or(0)
+                .clamp(MIN_COLUMN_WEIGHT, MAX_COLUMN_WEIGHT)
+        })
+        .collect();
+
+    let explicit_total: i32 = explicit.iter().filter_map(|w| *w).sum();
+    let remaining_width = (usable_width - explicit_total).max(0);
+    let remaining_weight: usize = explicit
+        .iter()
+        .zip(weights.iter())
+        .filter(|(width, _)| width.is_none())
+        .map(|(_, weight)| weight)
+        .sum();
+
+    let mut result: Vec<i32> = explicit
+        .iter()
+        .zip(weights.iter())
+        .map(|(width, weight)| match width {
+            Some(w) => *w,
+            None => {
+                if remaining_weight == 0 {
+                    0
+                } else {
+                    remaining_width * (*weight as i32) / (remaining_weight as i32)
+                }
+            }
+        })
+        .collect();
+
+    // Hand whatever got lost to integer division to the last column without
+    // an explicit width, so that the table spans the usable width exactly.
+    if let Some(last_flexible) = explicit.iter().rposition(|width| width.is_none()) {
+        let distributed: i32 = result.iter().sum();
+        result[last_flexible] += (explicit_total + remaining_width - distributed).max(0);
+    }
+
+    result
+}
+
+fn longest_line_length(text: &str) -> usize {
+    text.lines()
+        .map(|line| line.chars().count())
+        .max()
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::testing::{a4_dimensions, xml};
+    use datakit::{value::definitions::*, value::primitives::*};
+
+    fn texts(columns: &[&[&str]]) -> Vec<Vec<String>> {
+        columns
+            .iter()
+            .map(|column| column.iter().map(|text| String::from(*text)).collect())
+            .collect()
+    }
+
+    #[test]
+    fn widths_follow_the_contents() {
+        let long_text = "x".repeat(100);
+        let cell_texts = texts(&[&["a", "b"], &[&long_text, "c"]]);
+        let widths = column_widths(&cell_texts, &a4_dimensions(), &TableOptions::default());
+        // 9026 twips of usable width, split 4:40 (the minimum and maximum
+        // weights); the rounding remainder goes to the last column.
+        assert_eq!(widths, vec![820, 8206]);
+    }
+
+    #[test]
+    fn longest_line_of_multiline_cells() {
+        let cell_texts = texts(&[&["abcdefgh\nab"], &["abcdefgh"]]);
+        let widths = column_widths(&cell_texts, &a4_dimensions(), &TableOptions::default());
+        assert_eq!(widths, vec![4513, 4513]);
+    }
+
+    #[test]
+    fn explicit_widths() {
+        let options = TableOptions {
+            column_widths: vec![
+                Some(ColumnWidth::Twips(2000)),
+                Some(ColumnWidth::Percent(50.0)),
+            ],
+            ..TableOptions::default()
+        };
+        let cell_texts = texts(&[&["a"], &["b"], &["c"]]);
+        let widths = column_widths(&cell_texts, &a4_dimensions(), &options);
+        assert_eq!(widths, vec![2000, 4513, 2513]);
+        assert_eq!(widths.iter().sum::<i32>(), 9026);
+    }
+
+    #[test]
+    fn autofit_does_not_pin_widths() {
+        let table = TableData {
+            headers: vec![String::from("name"), String::from("note")],
+            columns: vec![
+                vec![Some(Value::Text(String::from("a")))],
+                vec![Some(Value::Text(String::from("b")))],
+            ],
+        };
+        let options = TableOptions {
+            autofit: true,
+            column_widths: vec![Some(ColumnWidth::Twips(2000))],
+            ..TableOptions::default()
+        };
+        let tokens =
+            datakit_table_to_tokens(&table, &TableStyling::default(), &a4_dimensions(), &options);
+        let xml = xml(&tokens);
+        assert!(xml.contains(r#"<w:tblW w:w="0" w:type="auto">"#));
+        assert!(xml.contains(r#"<w:tblLayout w:type="autofit">"#));
+        assert!(xml.contains(
+            r#"<w:tblGrid><w:gridCol w:w="2000"></w:gridCol><w:gridCol></w:gridCol></w:tblGrid>"#
+        ));
+        assert_eq!(xml.matches(r#"<w:tcW w:w="2000" w:type="dxa">"#).count(), 2);
+        assert_eq!(xml.matches(r#"<w:tcW w:w="0" w:type="auto">"#).count(), 2);
+    }
+
+    #[test]
+    fn fixed_layout_pins_widths() {
+        let table = TableData {
+            headers: vec![],
+            columns: vec![vec![Some(Value::Text(String::from("a")))]],
+        };
+        let tokens = datakit_table_to_tokens(
+            &table,
+            &TableStyling::default(),
+            &a4_dimensions(),
+            &TableOptions::default(),
+        );
+        let xml = xml(&tokens);
+        assert!(xml.contains(r#"<w:tblW w:w="9026" w:type="dxa">"#));
+        assert!(xml.contains(r#"<w:tblLayout w:type="fixed">"#));
+        assert!(xml.contains(r#"<w:gridCol w:w="9026">"#));
+        assert!(xml.contains(r#"<w:tcW w:w="9026" w:type="dxa">"#));
+    }
+
+    fn hash_args(args: serde_json::Value) -> BTreeMap<String, serde_json::Value> {
+        args.as_object()
+            .unwrap()
+            .iter()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn format_hash_args() {
+        let args = hash_args(serde_json::json!({
+            "decimals": 1,
+            "thousands_separator": "'",
+            "null_text": "-",
+            "column_formats": {"share": {"percent": true}, "2": {"decimals": 0}},
+        }));
+        let options = TableOptions::default().with_hash_args(&args).unwrap();
+        assert_eq!(options.format.decimals, 1);
+        assert_eq!(options.format.thousands_separator, Some('\''));
+        assert_eq!(options.format.null_text, "-");
+        // Column formats start out from the table format.
+        let share = &options.named_column_formats["share"];
+        assert!(share.percent);
+        assert_eq!(share.decimals, 1);
+        assert_eq!(options.named_column_formats["2"].decimals, 0);
+
+        let as_text = hash_args(serde_json::json!({
+            "column_formats": r#"{"share": {"date_pattern": "%Y"}}"#,
+        }));
+        let options = TableOptions::default().with_hash_args(&as_text).unwrap();
+        assert_eq!(
+            options.named_column_formats["share"]
+                .date_pattern
+                .as_deref(),
+            Some("%Y")
+        );
+    }
+
+    #[test]
+    fn bad_format_hash_args() {
+        for args in [
+            serde_json::json!({"date_pattern": "%Q"}),
+            serde_json::json!({"percent": "maybe"}),
+            serde_json::json!({"colour": "red"}),
+            serde_json::json!({"column_formats": "share"}),
+            serde_json::json!({"column_formats": {"share": {"decimals": "many"}}}),
+        ]
+        .iter()
+        {
+            let result = TableOptions::default().with_hash_args(&hash_args(args.clone()));
+            assert!(
+                matches!(result, Err(TextkitDocxError::BadHelperOption(_))),
+                "{}",
+                args
+            );
+        }
+    }
+
+    #[test]
+    fn column_formats_by_name() {
+        let table = TableData {
+            headers: vec![String::from("name"), String::from("share")],
+            columns: vec![
+                vec![Some(Value::Text(String::from("a")))],
+                vec![Some(Value::Number(Numeric::Real(0.25)))],
+            ],
+        };
+        let args = hash_args(serde_json::json!({
+            "column_formats": {"share": {"percent": true, "decimals": 0}},
+        }));
+        let mut options = TableOptions::default().with_hash_args(&args).unwrap();
+        options
+            .rename
+            .insert(String::from("share"), String::from("Share"));
+        let tokens =
+            datakit_table_to_tokens(&table, &TableStyling::default(), &a4_dimensions(), &options);
+        let xml = xml(&tokens);
+        assert!(xml.contains(">Share</w:t>"));
+        assert!(xml.contains(">25%</w:t>"));
+    }
+
+    #[test]
+    fn rename_hash_arg() {
+        let args = hash_args(serde_json::json!({"rename": "amount: Amount (EUR), n:Count"}));
+        let options = TableOptions::default().with_hash_args(&args).unwrap();
+        assert_eq!(options.rename["amount"], "Amount (EUR)");
+        assert_eq!(options.rename["n"], "Count");
+
+        let args = hash_args(serde_json::json!({"rename": {"amount": "Amount"}}));
+        let options = TableOptions::default().with_hash_args(&args).unwrap();
+        assert_eq!(options.rename["amount"], "Amount");
+
+        let args = hash_args(serde_json::json!({"rename": "amount"}));
+        assert!(TableOptions::default().with_hash_args(&args).is_err());
+    }
+
+    /// `<w:tr>` with a bold, right-aligned and shaded first cell holding the
+    /// placeholder and `no_of_other_cells` plain cells.
+    fn prototype_row(no_of_other_cells: usize) -> Vec<Token> {
+        let mut row = vec![wp_start_token("tr", &[])];
+        row.push(wp_start_token("trPr", &[]));
+        row.extend(wp_empty_tokens("cantSplit", &[]));
+        row.push(wp_end_token("trPr"));
+
+        row.push(wp_start_token("tc", &[]));
+        row.push(wp_start_token("tcPr", &[]));
+        row.extend(wp_empty_tokens(
+            "shd",
+            &[("val", "clear"), ("fill", "EEEEEE")],
+        ));
+        row.push(wp_end_token("tcPr"));
+        row.push(wp_start_token("p", &[]));
+        row.push(wp_start_token("pPr", &[]));
+        row.extend(wp_empty_tokens("jc", &[("val", "right")]));
+        row.push(wp_end_token("pPr"));
+        row.push(run_start_token());
+        row.push(wp_start_token("rPr", &[]));
+        row.extend(wp_empty_tokens("b", &[]));
+        row.push(wp_end_token("rPr"));
+        row.extend(char_text_tokens("{{tablerows data}}", false));
+        row.push(run_end_token());
+        row.push(wp_end_token("p"));
+        row.push(wp_end_token("tc"));
+
+        for _ in 0..no_of_other_cells {
+            row.push(wp_start_token("tc", &[]));
+            row.extend(paragraph_tokens(""));
+            row.push(wp_end_token("tc"));
+        }
+
+        row.push(wp_end_token("tr"));
+        row
+    }
+
+    fn two_column_table() -> TableData {
+        TableData {
+            headers: vec![String::from("name"), String::from("score")],
+            columns: vec![
+                vec![
+                    Some(Value::Text(String::from("a"))),
+                    Some(Value::Text(String::from("b"))),
+                ],
+                vec![Some(Value::Number(Numeric::Integer(1))), None],
+            ],
+        }
+    }
+
+    #[test]
+    fn prototype_row_filling() {
+        let tokens = prototype_row_to_tokens(
+            &prototype_row(2),
+            &two_column_table(),
+            &TableOptions::default(),
+        )
+        .unwrap();
+        let xml = xml(&tokens);
+
+        assert_eq!(xml.matches("<w:tr>").count(), 2);
+        assert_eq!(xml.matches("<w:cantSplit>").count(), 2);
+        assert_eq!(xml.matches("<w:tc>").count(), 6);
+        // Properties of the cell, paragraph and run are kept, the text is not.
+        assert_eq!(
+            xml.matches(r#"<w:shd w:val="clear" w:fill="EEEEEE">"#)
+                .count(),
+            2
+        );
+        assert!(xml.contains(
+            r#"<w:p><w:pPr><w:jc w:val="right"></w:jc></w:pPr><w:r><w:rPr><w:b></w:b></w:rPr><w:t xml:space="preserve">a</w:t>"#
+        ));
+        assert!(xml.contains(">b</w:t>"));
+        assert!(xml.contains(">1</w:t>"));
+        assert!(!xml.contains("tablerows"));
+        // The third cell has no data.
+        assert!(xml.ends_with(
+            r#"<w:tc><w:p><w:r><w:t xml:space="preserve"></w:t></w:r></w:p></w:tc></w:tr>"#
+        ));
+    }
+
+    #[test]
+    fn prototype_row_options() {
+        let args = hash_args(serde_json::json!({"sort": "name desc", "columns": "name"}));
+        let options = TableOptions::default().with_hash_args(&args).unwrap();
+        let tokens =
+            prototype_row_to_tokens(&prototype_row(0), &two_column_table(), &options).unwrap();
+        let xml = xml(&tokens);
+        assert_eq!(xml.matches("<w:tc>").count(), 2);
+        assert!(xml.find(">b</w:t>").unwrap() < xml.find(">a</w:t>").unwrap());
+    }
+
+    #[test]
+    fn prototype_row_too_narrow() {
+        let result = prototype_row_to_tokens(
+            &prototype_row(0),
+            &two_column_table(),
+            &TableOptions::default(),
+        );
+        assert!(matches!(result, Err(TextkitDocxError::BadHelperCall(_))));
+
+        let empty_row = [wp_start_token("tr", &[]), wp_end_token("tr")];
+        for row in [&empty_row[..], &[]].iter() {
+            let result =
+                prototype_row_to_tokens(row, &two_column_table(), &TableOptions::default());
+            assert!(matches!(result, Err(TextkitDocxError::Malformed(_))));
+        }
+    }
+}
@@ -0,0 +1,220 @@
+//! Descriptive statistics of the columns of a table, like `DataFrame.describe()`
+//! in pandas.
+//!
+
+use crate::render::format::{format_value, ValueFormat};
+use crate::render::table_data::{compare_values, value_as_f64, TableData};
+use datakit::{value::definitions::*, value::primitives::*};
+use std::cmp::Ordering;
+use std::collections::HashSet;
+
+/// Statistics of numeric columns, in the order they appear in the summary.
+const NUMERIC_STATS: [&str; 7] = ["mean", "std", "min", "25%", "50%", "75%", "max"];
+
+/// Summarize a table with one row per column: the number of values and, for
+/// numeric columns, mean, standard deviation, minimum, quartiles and maximum.
+/// Non-numeric columns get the number of distinct values instead. Columns of
+/// statistics that apply to none of the described columns are left out.
+pub fn describe_table(table: &TableData) -> TableData {
+    let no_of_rows = table.no_of_rows();
+
+    let mut names: Vec<Option<Value>> = Vec::new();
+    let mut counts: Vec<Option<Value>> = Vec::new();
+    let mut distincts: Vec<Option<Value>> = Vec::new();
+    let mut numeric_stats: Vec<Vec<Option<Value>>> = vec![Vec::new(); NUMERIC_STATS.len()];
+
+    for col_i in 0..table.no_of_cols() {
+        let cells: Vec<&Value> = (0..no_of_rows)
+            .filter_map(|row_i| table.cell(col_i, row_i))
+            .collect();
+        // The type of a column is that of its values, NaNs included. Like in
+        // pandas, NaNs are not counted, nor are they part of the statistics.
+        let is_numeric =
+            !cells.is_empty() && cells.iter().all(|value| matches!(value, Value::Number(_)));
+        let values: Vec<&Value> = cells
+            .into_iter()
+            .filter(|value| !matches!(value, Value::Number(Numeric::Real(real)) if real.is_nan()))
+            .collect();
+        let numbers: Vec<f64> = values
+            .iter()
+            .filter_map(|value| value_as_f64(value))
+            .collect();
+
+        let name = table
+            .headers
+            .get(col_i)
+            .cloned()
+            .unwrap_or_else(|| format!("{}", col_i + 1));
+        names.push(Some(Value::Text(name)));
+        counts.push(Some(Value::Number(Numeric::Integer(values.len() as i64))));
+
+        if is_numeric {
+            distincts.push(None);
+            let stats = numeric_summary(&values, &numbers);
+            for (stat_i, stat) in stats.into_iter().enumerate() {
+                numeric_stats[stat_i].push(stat);
+            }
+        } else {
+            let format = ValueFormat::default();
+            let distinct: HashSet<String> = values
+                .iter()
+                .map(|value| format_value(Some(value), &format))
+                .collect();
+            distincts.push(Some(Value::Number(Numeric::Integer(distinct.len() as i64))));
+            for stat_column in numeric_stats.iter_mut() {
+                stat_column.push(None);
+            }
+        }
+    }
+
+    let mut headers: Vec<String> = vec![String::from("Column"), String::from("count")];
+    let mut columns: Vec<Vec<Option<Value>>> = vec![names, counts];
+
+    if distincts.iter().any(|distinct| distinct.is_some()) {
+        headers.push(String::from("distinct"));
+        columns.push(distincts);
+    }
+    if numeric_stats[0].iter().any(|mean| mean.is_some()) {
+        headers.extend(NUMERIC_STATS.iter().map(|stat| String::from(*stat)));
+        columns.extend(numeric_stats);
+    }
+
+    TableData { headers, columns }
+}
+
+/// Mean, sample standard deviation, minimum, quartiles and maximum of a
+/// numeric column. Minimum and maximum keep the type of the original values.
+/// All of them are missing if there are no numbers.
+fn numeric_summary(values: &[&Value], numbers: &[f64]) -> Vec<Option<Value>> {
+    let real = |x: f64| Some(Value::Number(Numeric::Real(x)));
+    if numbers.is_empty() {
+        return vec![None; NUMERIC_STATS.len()];
+    }
+
+    let n = numbers.len() as f64;
+    let mean = numbers.iter().sum::<f64>() / n;
+    let std = if numbers.len() > 1 {
+        real((numbers.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (n - 1.0)).sqrt())
+    } else {
+        None
+    };
+
+    let mut sorted = numbers.to_vec();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+
+    let min = values.iter().min_by(|a, b| compare_values(a, b));
+    let max = values.iter().max_by(|a, b| compare_values(a, b));
+
+    vec![
+        real(mean),
+        std,
+        min.map(|value| (*value).clone()),
+        real(quantile(&sorted, 0.25)),
+        real(quantile(&sorted, 0.5)),
+        real(quantile(&sorted, 0.75)),
+        max.map(|value| (*value).clone()),
+    ]
+}
+
+/// Quantile of sorted numbers, interpolating linearly between the two
+/// closest ranks (like the default of pandas and numpy).
+fn quantile(sorted: &[f64], q: f64) -> f64 {
+    let position = q * (sorted.len() - 1) as f64;
+    let lower = position.floor() as usize;
+    let upper = position.ceil() as usize;
+    sorted[lower] + (sorted[upper] - sorted[lower]) * (position - lower as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn integer(int: i64) -> Option<Value> {
+        Some(Value::Number(Numeric::Integer(int)))
+    }
+
+    fn real(real: f64) -> Option<Value> {
+        Some(Value::Number(Numeric::Real(real)))
+    }
+
+    fn text(text: &str) -> Option<Value> {
+        Some(Value::Text(String::from(text)))
+    }
+
+    /// The statistics of a single column, by name.
+    fn describe_column(column: Vec<Option<Value>>) -> BTreeMap<String, Option<Value>> {
+        let table = TableData {
+            headers: vec![String::from("x")],
+            columns: vec![column],
+        };
+        let summary = describe_table(&table);
+        summary
+            .headers
+            .iter()
+            .cloned()
+            .zip(summary.columns.iter().map(|column| column[0].clone()))
+            .collect()
+    }
+
+    #[test]
+    fn numeric_column() {
+        let stats = describe_column(vec![integer(4), integer(1), None, integer(3), integer(2)]);
+        assert_eq!(stats["Column"], text("x"));
+        assert_eq!(stats["count"], integer(4));
+        assert!(!stats.contains_key("distinct"));
+        assert_eq!(stats["mean"], real(2.5));
+        assert_eq!(stats["std"], real((5.0f64 / 3.0).sqrt()));
+        assert_eq!(stats["min"], integer(1));
+        assert_eq!(stats["25%"], real(1.75));
+        assert_eq!(stats["50%"], real(2.5));
+        assert_eq!(stats["75%"], real(3.25));
+        assert_eq!(stats["max"], integer(4));
+    }
+
+    #[test]
+    fn nan_is_left_out() {
+        let stats = describe_column(vec![real(1.0), real(f64::NAN), real(3.0)]);
+        assert_eq!(stats["count"], integer(2));
+        assert_eq!(stats["mean"], real(2.0));
+        assert_eq!(stats["min"], real(1.0));
+        assert_eq!(stats["max"], real(3.0));
+
+        // Only NaNs: still a numeric column, without statistics.
+        let stats = describe_column(vec![real(f64::NAN), None]);
+        assert_eq!(stats["count"], integer(0));
+        assert!(!stats.contains_key("distinct"));
+        assert!(!stats.contains_key("mean"));
+    }
+
+    #[test]
+    fn mixed_column() {
+        let stats = describe_column(vec![integer(1), text("a"), text("a"), real(f64::NAN)]);
+        assert_eq!(stats["count"], integer(3));
+        assert_eq!(stats["distinct"], integer(2));
+        assert!(!stats.contains_key("mean"));
+    }
+
+    #[test]
+    fn all_null_column() {
+        let stats = describe_column(vec![None, None]);
+        assert_eq!(stats["count"], integer(0));
+        assert_eq!(stats["distinct"], integer(0));
+        assert!(!stats.contains_key("mean"));
+    }
+
+    #[test]
+    fn mixed_table() {
+        let table = TableData {
+            headers: vec![String::from("n"), String::from("s")],
+            columns: vec![vec![integer(1), integer(2)], vec![text("a"), None]],
+        };
+        let summary = describe_table(&table);
+        assert_eq!(
+            summary.headers,
+            vec!["Column", "count", "distinct", "mean", "std", "min", "25%", "50%", "75%", "max"]
+        );
+        assert_eq!(summary.columns[2], vec![None, integer(1)]);
+        assert_eq!(summary.columns[3], vec![real(1.5), None]);
+    }
+}
@@ -0,0 +1,91 @@
+//! Footnotes (`word/footnotes.xml`). Footnotes rendered into the document are
+//! appended to the template's footnotes part, or to a new one.
+//!
+
+use crate::errors::TextkitDocxError;
+use crate::parse::xml_to_token_vec;
+use crate::render::{
+    is_wp_end_token, is_wp_start_token, wp_attribute, wp_end_token, wp_start_token,
+    write_token_vector_to_string,
+};
+use crate::Token;
+
+/// Relationship type of the footnotes part.
+pub(crate) const REL_TYPE_FOOTNOTES: &str =
+    "http://schemas.openxmlformats.org/officeDocument/2006/relationships/footnotes";
+
+/// Content type of the footnotes part.
+pub(crate) const CONTENT_TYPE_FOOTNOTES: &str =
+    "application/vnd.openxmlformats-officedocument.wordprocessingml.footnotes+xml";
+
+/// Footnotes part used when the template has none, with the separator
+/// footnotes Word draws above the footnotes of a page.
+const EMPTY_FOOTNOTES_XML: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<w:footnotes xmlns:w="http://schemas.openxmlformats.org/wordprocessingml/2006/main"><w:footnote w:type="separator" w:id="-1"><w:p><w:pPr><w:spacing w:after="0" w:line="240" w:lineRule="auto"/></w:pPr><w:r><w:separator/></w:r></w:p></w:footnote><w:footnote w:type="continuationSeparator" w:id="0"><w:p><w:pPr><w:spacing w:after="0" w:line="240" w:lineRule="auto"/></w:pPr><w:r><w:continuationSeparator/></w:r></w:p></w:footnote></w:footnotes>"#;
+
+/// The footnotes of a document, and the ones added while rendering.
+#[derive(Debug)]
+pub(crate) struct Footnotes {
+    tokens: Vec<Token>,
+    next_id: usize,
+    new_footnotes: Vec<Token>,
+}
+
+impl Footnotes {
+    /// Read the footnotes of a template, if it has any.
+    pub(crate) fn from_xml(footnotes_xml: Option<&str>) -> Result<Self, TextkitDocxError> {
+        let tokens = xml_to_token_vec(footnotes_xml.unwrap_or(EMPTY_FOOTNOTES_XML))?;
+
+        // Ids -1 and 0 are usually taken by the separators, so footnotes
+        // proper start at 1.
+        let next_id = tokens
+            .iter()
+            .filter(|token| is_wp_start_token(token, "footnote"))
+            .filter_map(|token| wp_attribute(token, "id").and_then(|id| id.parse::<i64>().ok()))
+            .map(|id| (id + 1).max(1) as usize)
+            .max()
+            .unwrap_or(1);
+
+        Ok(Self {
+            tokens,
+            next_id,
+            new_footnotes: Vec::new(),
+        })
+    }
+
+    /// Reserve the id of a new footnote.
+    pub(crate) fn reserve_id(&mut self) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    /// Add a footnote with the given (reserved) id and paragraphs.
+    pub(crate) fn add(&mut self, id: usize, paragraphs: Vec<Token>) {
+        self.new_footnotes
+            .push(wp_start_token("footnote", &[("id", &format!("{}", id))]));
+        self.new_footnotes.extend(paragraphs);
+        self.new_footnotes.push(wp_end_token("footnote"));
+    }
+
+    /// Were any footnotes added while rendering?
+    pub(crate) fn is_modified(&self) -> bool {
+        !self.new_footnotes.is_empty()
+    }
+
+    /// The footnotes part with the added footnotes at the end.
+    pub(crate) fn to_xml(&self) -> Result<String, TextkitDocxError> {
+        let end_index = self
+            .tokens
+            .iter()
+            .position(|token| is_wp_end_token(token, "footnotes"))
+            .ok_or(TextkitDocxError::FailedWriteXml)?;
+
+        let mut result: Vec<Token> = Vec::new();
+        result.extend_from_slice(&self.tokens[..end_index]);
+        result.extend(self.new_footnotes.iter().cloned());
+        result.extend_from_slice(&self.tokens[end_index..]);
+
+        write_token_vector_to_string(&result)
+    }
+}
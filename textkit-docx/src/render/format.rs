@@ -0,0 +1,316 @@
+//! Formatting of datakit values as text for the document.
+//!
+
+use crate::parse::{option_bool, option_text, option_usize};
+use chrono::format::{Item, StrftimeItems};
+use datakit::{value::definitions::*, value::primitives::*};
+use std::fmt::Write;
+
+/// Policy for turning values into the text that ends up in a table cell.
+#[derive(Debug, Clone)]
+pub struct ValueFormat {
+    /// Number of decimals for real numbers (and percentages).
+    pub decimals: usize,
+
+    /// Character used to group thousands, e.g. `Some(',')` gives `1,234,567`.
+    pub thousands_separator: Option<char>,
+
+    /// Character separating the integer part from the decimals.
+    pub decimal_separator: char,
+
+    /// Multiply numbers by 100 and append a `%` sign.
+    pub percent: bool,
+
+    /// Use scientific notation (`1.234E+05`) for numbers.
+    pub scientific: bool,
+
+    /// `strftime`-like pattern for dates and times, e.g. `"%Y-%m-%d"`. If
+    /// `None`, the default representation of the value is used.
+    pub date_pattern: Option<String>,
+
+    /// Label for `true` booleans.
+    pub true_label: String,
+
+    /// Label for `false` booleans.
+    pub false_label: String,
+
+    /// Text for missing values.
+    pub null_text: String,
+}
+
+impl Default for ValueFormat {
+    fn default() -> Self {
+        Self {
+            decimals: 3,
+            thousands_separator: None,
+            decimal_separator: '.',
+            percent: false,
+            scientific: false,
+            date_pattern: None,
+            true_label: String::from("Yes"),
+            false_label: String::from("No"),
+            null_text: String::new(),
+        }
+    }
+}
+
+impl ValueFormat {
+    /// Change the setting with the given name (the name of the field, e.g.
+    /// `decimals` or `null_text`) to the given option value. Returns `false`
+    /// for unknown names and invalid values, including date patterns with
+    /// unknown specifiers. An empty `thousands_separator` turns grouping off.
+    pub(crate) fn set_option(&mut self, name: &str, value: &serde_json::Value) -> bool {
+        let single_char = |text: &str| {
+            let mut chars = text.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => Some(c),
+                _ => None,
+            }
+        };
+
+        match name {
+            "decimals" => match option_usize(value) {
+                Some(decimals) => self.decimals = decimals,
+                None => return false,
+            },
+            "thousands_separator" => match option_text(value).as_str() {
+                "" => self.thousands_separator = None,
+                text => match single_char(text) {
+                    Some(c) => self.thousands_separator = Some(c),
+                    None => return false,
+                },
+            },
+            "decimal_separator" => match single_char(&option_text(value)) {
+                Some(c) => self.decimal_separator = c,
+                None => return false,
+            },
+            "percent" => match option_bool(value) {
+                Some(percent) => self.percent = percent,
+                None => return false,
+            },
+            "scientific" => match option_bool(value) {
+                Some(scientific) => self.scientific = scientific,
+                None => return false,
+            },
+            "date_pattern" => {
+                let pattern = option_text(value);
+                if StrftimeItems::new(&pattern).any(|item| item == Item::Error) {
+                    return false;
+                }
+                self.date_pattern = Some(pattern);
+            }
+            "true_label" => self.true_label = option_text(value),
+            "false_label" => self.false_label = option_text(value),
+            "null_text" => self.null_text = option_text(value),
+            _ => return false,
+        }
+
+        true
+    }
+}
+
+/// Text representation of a (possibly missing) value according to the given format.
+pub(crate) fn format_value(value: Option<&Value>, format: &ValueFormat) -> String {
+    let value = match value {
+        Some(value) => value,
+        None => return format.null_text.clone(),
+    };
+
+    match value {
+        Value::Text(text) => text.clone(),
+        Value::Number(Numeric::Integer(int)) => {
+            if format.percent || format.scientific {
+                format_real(*int as f64, format)
+            } else {
+                let digits = group_thousands(&format!("{}", int.abs()), format.thousands_separator);
+                if *int < 0 {
+                    format!("-{}", digits)
+                } else {
+                    digits
+                }
+            }
+        }
+        Value::Number(Numeric::Real(real)) => format_real(*real, format),
+        Value::Boolean(boolean) => {
+            if *boolean {
+                format.true_label.clone()
+            } else {
+                format.false_label.clone()
+            }
+        }
+        Value::DateTime(dt) => {
+            // N.B! Formatting with an invalid pattern fails (`format!` would
+            // panic), in which case the default representation is used.
+            let mut text = String::new();
+            match &format.date_pattern {
+                Some(pattern) if write!(text, "{}", dt.format(pattern)).is_ok() => text,
+                _ => format!("{}", dt),
+            }
+        }
+        Value::NotAvailable => format.null_text.clone(),
+    }
+}
+
+fn format_real(real: f64, format: &ValueFormat) -> String {
+    if real.is_nan() {
+        return format.null_text.clone();
+    }
+    if real.is_infinite() {
+        return String::from(if real < 0.0 { "-∞" } else { "∞" });
+    }
+
+    // `+ 0.0` turns negative zero into zero, which has no sign.
+    let value = if format.percent { real * 100.0 } else { real } + 0.0;
+    let suffix = if format.percent { "%" } else { "" };
+
+    if format.scientific {
+        // Rust formats exponents as `1.5e3`, which is not what people are used
+        // to seeing in reports, hence the conversion to `1.5E+03`.
+        let formatted = format!("{:.*e}", format.decimals, value);
+        let (mantissa, exponent) = formatted.split_at(formatted.find('e').unwrap());
+        let exponent: i32 = exponent[1..].parse().unwrap_or(0);
+        let sign = if exponent < 0 { '-' } else { '+' };
+        let mantissa = mantissa.replace('.', &format.decimal_separator.to_string());
+        return format!("{}E{}{:02}{}", mantissa, sign, exponent.abs(), suffix);
+    }
+
+    let formatted = format!("{:.*}", format.decimals, value.abs());
+    let (int_part, frac_part) = match formatted.find('.') {
+        Some(i) => (&formatted[..i], Some(&formatted[i + 1..])),
+        None => (&formatted[..], None),
+    };
+
+    let mut result = String::new();
+    if value < 0.0 && formatted.chars().any(|c| c != '0' && c != '.') {
+        result.push('-');
+    }
+    result.push_str(&group_thousands(int_part, format.thousands_separator));
+    if let Some(frac) = frac_part {
+        result.push(format.decimal_separator);
+        result.push_str(frac);
+    }
+    result.push_str(suffix);
+
+    result
+}
+
+/// Insert a separator between every group of three digits (counting from the right).
+fn group_thousands(digits: &str, separator: Option<char>) -> String {
+    match separator {
+        None => String::from(digits),
+        Some(sep) => {
+            let mut result = String::with_capacity(digits.len() + digits.len() / 3);
+            // The first group is the one that may have less than three digits.
+            let first_group = digits.len() % 3;
+            for (i, c) in digits.chars().enumerate() {
+                if i > 0 && i % 3 == first_group {
+                    result.push(sep);
+                }
+                result.push(c);
+            }
+            result
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn real(real: f64, format: &ValueFormat) -> String {
+        format_value(Some(&Value::Number(Numeric::Real(real))), format)
+    }
+
+    #[test]
+    fn nan_is_missing() {
+        let format = ValueFormat {
+            null_text: String::from("n/a"),
+            ..ValueFormat::default()
+        };
+        assert_eq!(real(f64::NAN, &format), "n/a");
+    }
+
+    #[test]
+    fn infinity() {
+        let scientific = ValueFormat {
+            scientific: true,
+            ..ValueFormat::default()
+        };
+        for format in [ValueFormat::default(), scientific].iter() {
+            assert_eq!(real(f64::INFINITY, format), "∞");
+            assert_eq!(real(f64::NEG_INFINITY, format), "-∞");
+        }
+    }
+
+    #[test]
+    fn negative_zero_has_no_sign() {
+        let format = ValueFormat::default();
+        assert_eq!(real(-0.0, &format), "0.000");
+        assert_eq!(real(-0.0001, &format), "0.000");
+        let scientific = ValueFormat {
+            scientific: true,
+            decimals: 2,
+            ..ValueFormat::default()
+        };
+        assert_eq!(real(-0.0, &scientific), "0.00E+00");
+        assert_eq!(real(-1234.5, &scientific), "-1.23E+03");
+    }
+
+    #[test]
+    fn thousands_grouping() {
+        let format = ValueFormat {
+            thousands_separator: Some(','),
+            decimals: 2,
+            ..ValueFormat::default()
+        };
+        assert_eq!(real(1234567.891, &format), "1,234,567.89");
+        assert_eq!(real(-999.5, &format), "-999.50");
+        assert_eq!(real(123456.0, &format), "123,456.00");
+        let integer = Value::Number(Numeric::Integer(-1234567));
+        assert_eq!(format_value(Some(&integer), &format), "-1,234,567");
+    }
+
+    #[test]
+    fn date_patterns() {
+        let date = Value::DateTime("2020-03-04T05:06:07Z".parse().unwrap());
+        let mut format = ValueFormat::default();
+        assert!(format.set_option("date_pattern", &serde_json::json!("%d.%m.%Y")));
+        assert_eq!(format_value(Some(&date), &format), "04.03.2020");
+
+        // Invalid patterns are refused, and do not panic if set directly.
+        assert!(!format.set_option("date_pattern", &serde_json::json!("%Q")));
+        assert_eq!(format.date_pattern.as_deref(), Some("%d.%m.%Y"));
+        format.date_pattern = Some(String::from("%Q"));
+        assert_eq!(
+            format_value(Some(&date), &format),
+            format_value(Some(&date), &ValueFormat::default())
+        );
+    }
+
+    #[test]
+    fn options_by_name() {
+        let mut format = ValueFormat::default();
+        for (name, value) in [
+            ("decimals", serde_json::json!(1)),
+            ("thousands_separator", serde_json::json!(" ")),
+            ("decimal_separator", serde_json::json!(",")),
+            ("percent", serde_json::json!("true")),
+            ("true_label", serde_json::json!("✓")),
+            ("null_text", serde_json::json!("n/a")),
+        ]
+        .iter()
+        {
+            assert!(format.set_option(name, value), "{}", name);
+        }
+        assert_eq!(real(12.3456, &format), "1 234,6%");
+        assert_eq!(format_value(Some(&Value::Boolean(true)), &format), "✓");
+        assert_eq!(format_value(Some(&Value::NotAvailable), &format), "n/a");
+        assert_eq!(format_value(None, &format), "n/a");
+
+        assert!(format.set_option("thousands_separator", &serde_json::json!("")));
+        assert_eq!(format.thousands_separator, None);
+        assert!(!format.set_option("decimal_separator", &serde_json::json!("..")));
+        assert!(!format.set_option("decimals", &serde_json::json!(-1)));
+        assert!(!format.set_option("colour", &serde_json::json!("red")));
+    }
+}
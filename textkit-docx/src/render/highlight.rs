@@ -0,0 +1,449 @@
+//! A small syntax highlighter for code blocks. It knows the keywords, comments
+//! and strings of the languages that show up in reports, which is enough to
+//! colour code the way editors do, without pulling in a full grammar engine.
+//!
+
+/// What a piece of code is, as far as colouring goes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum SpanKind {
+    Plain,
+    Keyword,
+    Builtin,
+    String,
+    Number,
+    Comment,
+}
+
+impl SpanKind {
+    /// Text colour (`w:color`) of the kind, `None` for the default colour.
+    pub(crate) fn color(self) -> Option<&'static str> {
+        match self {
+            Self::Plain => None,
+            Self::Keyword => Some("0000FF"),
+            Self::Builtin => Some("267F99"),
+            Self::String => Some("A31515"),
+            Self::Number => Some("098658"),
+            Self::Comment => Some("008000"),
+        }
+    }
+}
+
+/// A piece of a line of code.
+pub(crate) type Span = (SpanKind, String);
+
+struct Language {
+    keywords: &'static [&'static str],
+    builtins: &'static [&'static str],
+    case_insensitive: bool,
+    line_comments: &'static [&'static str],
+    block_comment: Option<(&'static str, &'static str)>,
+    /// String delimiters, longest first (e.g. `"""` before `"`).
+    string_delimiters: &'static [&'static str],
+}
+
+const PYTHON: Language = Language {
+    keywords: &[
+        "False", "None", "True", "and", "as", "assert", "async", "await", "break", "class",
+        "continue", "def", "del", "elif", "else", "except", "finally", "for", "from", "global",
+        "if", "import", "in", "is", "lambda", "nonlocal", "not", "or", "pass", "raise", "return",
+        "try", "while", "with", "yield",
+    ],
+    builtins: &[
+        "abs",
+        "all",
+        "any",
+        "bool",
+        "dict",
+        "enumerate",
+        "filter",
+        "float",
+        "int",
+        "isinstance",
+        "len",
+        "list",
+        "map",
+        "max",
+        "min",
+        "open",
+        "print",
+        "range",
+        "round",
+        "self",
+        "set",
+        "sorted",
+        "str",
+        "sum",
+        "super",
+        "tuple",
+        "type",
+        "zip",
+    ],
+    case_insensitive: false,
+    line_comments: &["#"],
+    block_comment: None,
+    string_delimiters: &["\"\"\"", "'''", "\"", "'"],
+};
+
+const RUST: Language = Language {
+    keywords: &[
+        "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum",
+        "extern", "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move",
+        "mut", "pub", "ref", "return", "self", "Self", "static", "struct", "super", "trait",
+        "true", "type", "unsafe", "use", "where", "while",
+    ],
+    builtins: &[
+        "bool", "char", "f32", "f64", "i8", "i16", "i32", "i64", "i128", "isize", "u8", "u16",
+        "u32", "u64", "u128", "usize", "str", "String", "Vec", "Option", "Result", "Some", "None",
+        "Ok", "Err", "Box",
+    ],
+    case_insensitive: false,
+    line_comments: &["//"],
+    block_comment: Some(("/*", "*/")),
+    // Single quotes are left out, they also start lifetimes.
+    string_delimiters: &["\""],
+};
+
+const JAVASCRIPT: Language = Language {
+    keywords: &[
+        "async",
+        "await",
+        "break",
+        "case",
+        "catch",
+        "class",
+        "const",
+        "continue",
+        "default",
+        "delete",
+        "do",
+        "else",
+        "export",
+        "extends",
+        "false",
+        "finally",
+        "for",
+        "function",
+        "if",
+        "import",
+        "in",
+        "instanceof",
+        "interface",
+        "let",
+        "new",
+        "null",
+        "of",
+        "return",
+        "static",
+        "switch",
+        "this",
+        "throw",
+        "true",
+        "try",
+        "type",
+        "typeof",
+        "undefined",
+        "var",
+        "void",
+        "while",
+        "yield",
+    ],
+    builtins: &[
+        "Array", "Boolean", "JSON", "Map", "Math", "Number", "Object", "Promise", "Set", "String",
+        "console", "number", "string", "boolean", "any",
+    ],
+    case_insensitive: false,
+    line_comments: &["//"],
+    block_comment: Some(("/*", "*/")),
+    string_delimiters: &["`", "\"", "'"],
+};
+
+const C_LIKE: Language = Language {
+    keywords: &[
+        "break",
+        "case",
+        "catch",
+        "class",
+        "const",
+        "continue",
+        "default",
+        "do",
+        "else",
+        "enum",
+        "extends",
+        "false",
+        "final",
+        "for",
+        "func",
+        "go",
+        "if",
+        "implements",
+        "import",
+        "interface",
+        "namespace",
+        "new",
+        "null",
+        "nullptr",
+        "package",
+        "private",
+        "protected",
+        "public",
+        "return",
+        "static",
+        "struct",
+        "switch",
+        "this",
+        "throw",
+        "true",
+        "try",
+        "typedef",
+        "using",
+        "var",
+        "virtual",
+        "void",
+        "while",
+    ],
+    builtins: &[
+        "bool", "byte", "char", "double", "float", "int", "long", "short", "string", "String",
+        "unsigned",
+    ],
+    case_insensitive: false,
+    line_comments: &["//"],
+    block_comment: Some(("/*", "*/")),
+    string_delimiters: &["\"", "'"],
+};
+
+const SQL: Language = Language {
+    keywords: &[
+        "all",
+        "and",
+        "as",
+        "asc",
+        "between",
+        "by",
+        "case",
+        "create",
+        "delete",
+        "desc",
+        "distinct",
+        "drop",
+        "else",
+        "end",
+        "exists",
+        "from",
+        "full",
+        "group",
+        "having",
+        "in",
+        "inner",
+        "insert",
+        "into",
+        "is",
+        "join",
+        "left",
+        "like",
+        "limit",
+        "not",
+        "null",
+        "on",
+        "or",
+        "order",
+        "outer",
+        "over",
+        "partition",
+        "right",
+        "select",
+        "set",
+        "table",
+        "then",
+        "union",
+        "update",
+        "values",
+        "view",
+        "when",
+        "where",
+        "with",
+    ],
+    builtins: &[
+        "avg", "cast", "coalesce", "count", "date", "integer", "max", "min", "numeric", "sum",
+        "varchar",
+    ],
+    case_insensitive: true,
+    line_comments: &["--"],
+    block_comment: Some(("/*", "*/")),
+    string_delimiters: &["'"],
+};
+
+const R: Language = Language {
+    keywords: &[
+        "FALSE", "Inf", "NA", "NaN", "NULL", "TRUE", "break", "else", "for", "function", "if",
+        "in", "next", "repeat", "return", "while",
+    ],
+    builtins: &[
+        "c", "factor", "library", "lm", "list", "mean", "paste", "print", "summary", "vector",
+    ],
+    case_insensitive: false,
+    line_comments: &["#"],
+    block_comment: None,
+    string_delimiters: &["\"", "'"],
+};
+
+const SHELL: Language = Language {
+    keywords: &[
+        "case", "do", "done", "elif", "else", "esac", "export", "fi", "for", "function", "if",
+        "in", "local", "return", "then", "until", "while",
+    ],
+    builtins: &[
+        "cd", "echo", "exit", "printf", "read", "set", "source", "unset",
+    ],
+    case_insensitive: false,
+    line_comments: &["#"],
+    block_comment: None,
+    string_delimiters: &["\"", "'"],
+};
+
+const JSON: Language = Language {
+    keywords: &["false", "null", "true"],
+    builtins: &[],
+    case_insensitive: false,
+    line_comments: &[],
+    block_comment: None,
+    string_delimiters: &["\""],
+};
+
+fn language(name: &str) -> Option<&'static Language> {
+    match name.trim().to_lowercase().as_str() {
+        "python" | "python3" | "py" | "ipython" | "ipython3" => Some(&PYTHON),
+        "rust" | "rs" => Some(&RUST),
+        "javascript" | "js" | "typescript" | "ts" | "jsx" | "tsx" => Some(&JAVASCRIPT),
+        "c" | "h" | "cpp" | "c++" | "java" | "go" | "csharp" | "cs" | "c#" | "kotlin" | "scala" => {
+            Some(&C_LIKE)
+        }
+        "sql" => Some(&SQL),
+        "r" => Some(&R),
+        "bash" | "sh" | "shell" | "zsh" | "console" => Some(&SHELL),
+        "json" => Some(&JSON),
+        _ => None,
+    }
+}
+
+/// Split code into lines of coloured spans. Code in unknown languages comes
+/// back as plain spans.
+pub(crate) fn highlight(code: &str, language_name: Option<&str>) -> Vec<Vec<Span>> {
+    let spans = match language_name.and_then(language) {
+        Some(language) => lex(code, language),
+        None => vec![(SpanKind::Plain, String::from(code))],
+    };
+
+    let mut lines: Vec<Vec<Span>> = vec![Vec::new()];
+    for (kind, text) in spans {
+        for (i, part) in text.split('\n').enumerate() {
+            if i > 0 {
+                lines.push(Vec::new());
+            }
+            if !part.is_empty() {
+                lines.last_mut().unwrap().push((kind, String::from(part)));
+            }
+        }
+    }
+
+    lines
+}
+
+fn lex(code: &str, language: &Language) -> Vec<Span> {
+    let chars: Vec<char> = code.chars().collect();
+    let mut spans: Vec<Span> = Vec::new();
+    let mut i: usize = 0;
+
+    let starts_with = |i: usize, pattern: &str| {
+        (i..)
+            .zip(pattern.chars())
+            .all(|(j, c)| chars.get(j) == Some(&c))
+    };
+    let text = |from: usize, to: usize| chars[from..to].iter().collect::<String>();
+
+    while i < chars.len() {
+        let start = i;
+        let c = chars[i];
+
+        let kind = if let Some((open, close)) = language
+            .block_comment
+            .filter(|(open, _)| starts_with(i, open))
+        {
+            i += open.chars().count();
+            while i < chars.len() && !starts_with(i, close) {
+                i += 1;
+            }
+            i = (i + close.chars().count()).min(chars.len());
+            SpanKind::Comment
+        } else if language
+            .line_comments
+            .iter()
+            .any(|comment| starts_with(i, comment))
+        {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+            SpanKind::Comment
+        } else if let Some(delimiter) = language
+            .string_delimiters
+            .iter()
+            .find(|delimiter| starts_with(i, delimiter))
+        {
+            let delimiter_len = delimiter.chars().count();
+            // Only multi-character delimiters and backticks span lines.
+            let multiline = delimiter_len > 1 || *delimiter == "`";
+            i += delimiter_len;
+            while i < chars.len() && !starts_with(i, delimiter) {
+                if chars[i] == '\\' {
+                    i += 1;
+                } else if chars[i] == '\n' && !multiline {
+                    break;
+                }
+                i += 1;
+            }
+            i = (i + delimiter_len).min(chars.len());
+            SpanKind::String
+        } else if c.is_ascii_digit() {
+            while i < chars.len()
+                && (chars[i].is_alphanumeric() || chars[i] == '.' || chars[i] == '_')
+            {
+                i += 1;
+            }
+            SpanKind::Number
+        } else if c.is_alphabetic() || c == '_' {
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            let word = text(start, i);
+            let is_in = |words: &[&str]| {
+                words.iter().any(|w| {
+                    if language.case_insensitive {
+                        w.eq_ignore_ascii_case(&word)
+                    } else {
+                        *w == word
+                    }
+                })
+            };
+            if is_in(language.keywords) {
+                SpanKind::Keyword
+            } else if is_in(language.builtins) {
+                SpanKind::Builtin
+            } else {
+                SpanKind::Plain
+            }
+        } else {
+            i += 1;
+            SpanKind::Plain
+        };
+
+        let span_text = text(start, i);
+        match spans.last_mut() {
+            Some((last_kind, last_text)) if *last_kind == kind && kind == SpanKind::Plain => {
+                last_text.push_str(&span_text)
+            }
+            _ => spans.push((kind, span_text)),
+        }
+    }
+
+    spans
+}
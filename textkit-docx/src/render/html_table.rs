@@ -0,0 +1,610 @@
+//! HTML tables as pandas writes them for DataFrames (e.g. `text/html`
+//! outputs of notebooks), read into a [`TableData`] so that they are
+//! rendered like any other table. Only tables are read; this is not an HTML
+//! parser.
+//!
+
+use crate::render::table_data::TableData;
+use crate::render::table_style::TableStyling;
+use datakit::{value::definitions::*, value::primitives::*};
+
+/// Texts pandas writes for missing values.
+const MISSING_VALUES: [&str; 5] = ["", "NaN", "NaT", "None", "<NA>"];
+
+/// A table read from HTML: its data, the styling that brings back the column
+/// levels and merged row labels of a MultiIndex, its `caption` and the text
+/// after it (pandas writes e.g. "5 rows × 3 columns" under truncated
+/// tables).
+#[derive(Debug)]
+pub(crate) struct HtmlTable {
+    pub table: TableData,
+    pub styling: TableStyling,
+    pub title: Option<String>,
+    pub note: Option<String>,
+}
+
+/// A table as written, before spans are expanded.
+#[derive(Debug, Default)]
+struct RawTable {
+    title: String,
+    head_rows: Vec<Vec<HtmlCell>>,
+    body_rows: Vec<Vec<HtmlCell>>,
+    note: String,
+}
+
+/// A cell as written, before spans are expanded.
+#[derive(Debug, Clone, Default)]
+struct HtmlCell {
+    text: String,
+    is_header: bool,
+    colspan: usize,
+    rowspan: usize,
+}
+
+/// A piece of HTML: a tag (name in lower case, attributes, whether it is an
+/// end tag) or text.
+#[derive(Debug)]
+enum HtmlPiece {
+    Tag {
+        name: String,
+        attributes: String,
+        is_end: bool,
+    },
+    Text(String),
+}
+
+/// Read the tables of an HTML fragment (statsmodels, for one, writes its
+/// summaries as several tables). Empty tables are left out; text that is
+/// not in or after a table is ignored.
+pub(crate) fn html_tables(html: &str) -> Vec<HtmlTable> {
+    let mut raw_tables: Vec<RawTable> = Vec::new();
+    let mut in_table = false;
+    let mut in_head = false;
+    let mut in_caption = false;
+    let mut row: Option<Vec<HtmlCell>> = None;
+    let mut cell: Option<HtmlCell> = None;
+
+    for piece in html_pieces(html).iter() {
+        match piece {
+            HtmlPiece::Tag {
+                name,
+                attributes,
+                is_end,
+            } => match (name.as_str(), *is_end) {
+                ("table", false) => {
+                    raw_tables.push(RawTable::default());
+                    in_table = true;
+                }
+                ("table", true) => in_table = false,
+                _ if !in_table => {
+                    if matches!(name.as_str(), "p" | "br" | "div") {
+                        if let Some(table) = raw_tables.last_mut() {
+                            table.note.push(' ');
+                        }
+                    }
+                }
+                ("caption", is_end) => in_caption = !is_end,
+                ("thead", is_end) => in_head = !is_end,
+                ("tr", false) => row = Some(Vec::new()),
+                ("tr", true) => {
+                    if let (Some(cells), Some(table)) = (row.take(), raw_tables.last_mut()) {
+                        // Without a `thead`, leading rows of `th` cells are
+                        // the header.
+                        let is_head_row = in_head
+                            || (table.body_rows.is_empty()
+                                && cells.iter().all(|cell| cell.is_header));
+                        if is_head_row {
+                            table.head_rows.push(cells);
+                        } else {
+                            table.body_rows.push(cells);
+                        }
+                    }
+                }
+                ("th", false) | ("td", false) => {
+                    cell = Some(HtmlCell {
+                        text: String::new(),
+                        is_header: name == "th",
+                        colspan: span_attribute(attributes, "colspan"),
+                        rowspan: span_attribute(attributes, "rowspan"),
+                    });
+                }
+                ("th", true) | ("td", true) => {
+                    if let (Some(mut finished), Some(cells)) = (cell.take(), row.as_mut()) {
+                        finished.text = collapse_whitespace(&finished.text);
+                        cells.push(finished);
+                    }
+                }
+                ("br", _) => {
+                    if let Some(cell) = cell.as_mut() {
+                        cell.text.push(' ');
+                    }
+                }
+                _ => {}
+            },
+            HtmlPiece::Text(text) => {
+                if let Some(cell) = cell.as_mut() {
+                    cell.text.push_str(text);
+                } else if let Some(table) = raw_tables.last_mut() {
+                    if in_caption {
+                        table.title.push_str(text);
+                    } else if !in_table {
+                        table.note.push_str(text);
+                    }
+                }
+            }
+        }
+    }
+
+    raw_tables.into_iter().filter_map(table_from_raw).collect()
+}
+
+fn table_from_raw(raw: RawTable) -> Option<HtmlTable> {
+    let non_empty = |text: &str| {
+        let text = collapse_whitespace(text);
+        if text.is_empty() {
+            None
+        } else {
+            Some(text)
+        }
+    };
+    let title = non_empty(&raw.title);
+    let note = non_empty(&raw.note);
+
+    let mut head = expand_spans(&raw.head_rows);
+    let body = expand_spans(&raw.body_rows);
+    let no_of_cols = head
+        .iter()
+        .chain(body.iter())
+        .map(|row| row.len())
+        .max()
+        .unwrap_or(0);
+    if no_of_cols == 0 {
+        return None;
+    }
+
+    // The row labels (index) are the leading `th` cells of the body.
+    let no_of_index_cols = body.first().map_or(0, |row| {
+        row.iter().take_while(|cell| cell.is_header).count()
+    });
+
+    for row in head.iter_mut() {
+        row.resize(no_of_cols, HtmlCell::default());
+    }
+
+    // A named index gets a header row of its own, with the names of the
+    // index levels and nothing else.
+    let mut index_names: Vec<String> = vec![String::new(); no_of_index_cols];
+    if head.len() > 1 {
+        let is_names_row = head.last().is_some_and(|row| {
+            row[..no_of_index_cols]
+                .iter()
+                .any(|cell| !cell.text.is_empty())
+                && row[no_of_index_cols..]
+                    .iter()
+                    .all(|cell| cell.text.is_empty())
+        });
+        if is_names_row {
+            if let Some(row) = head.pop() {
+                index_names = row[..no_of_index_cols]
+                    .iter()
+                    .map(|cell| cell.text.clone())
+                    .collect();
+            }
+        }
+    }
+
+    let headers: Vec<String> = match head.last() {
+        Some(row) => row
+            .iter()
+            .enumerate()
+            .map(|(col_i, cell)| match index_names.get(col_i) {
+                Some(name) if !name.is_empty() => name.clone(),
+                _ => cell.text.clone(),
+            })
+            .collect(),
+        None => Vec::new(),
+    };
+
+    // Upper levels of MultiIndex columns become group headers, which merge
+    // equal adjacent labels again.
+    let group_headers: Vec<Vec<String>> = match head.len() {
+        0 => Vec::new(),
+        len => head[..len - 1]
+            .iter()
+            .map(|row| row.iter().map(|cell| cell.text.clone()).collect())
+            .collect(),
+    };
+
+    let columns = (0..no_of_cols)
+        .map(|col_i| {
+            let texts: Vec<Option<&str>> = body
+                .iter()
+                .map(|row| row.get(col_i).map(|cell| cell.text.as_str()))
+                .collect();
+            column_values(&texts)
+        })
+        .collect();
+
+    // Outer levels of a MultiIndex are written once per group (with a
+    // `rowspan`); merging repeated labels brings the groups back. By index,
+    // as column names such as `1` would be taken for column numbers.
+    let merge_repeated_columns = (0..no_of_index_cols.saturating_sub(1)).collect();
+
+    Some(HtmlTable {
+        table: TableData { headers, columns },
+        styling: TableStyling {
+            group_headers,
+            merge_repeated_columns,
+            ..TableStyling::default()
+        },
+        title,
+        note,
+    })
+}
+
+/// Values of a column: numbers if all of its (present) cells are numbers,
+/// text otherwise.
+fn column_values(texts: &[Option<&str>]) -> Vec<Option<Value>> {
+    let present = || {
+        texts
+            .iter()
+            .flatten()
+            .filter(|text| !MISSING_VALUES.contains(*text))
+    };
+    let all_numbers = present().all(|text| text.parse::<f64>().is_ok());
+    let all_integers = present().all(|text| text.parse::<i64>().is_ok());
+
+    texts
+        .iter()
+        .map(|text| match text {
+            Some(text) if !MISSING_VALUES.contains(text) => Some(if all_integers {
+                Value::Number(Numeric::Integer(text.parse().unwrap_or_default()))
+            } else if all_numbers {
+                Value::Number(Numeric::Real(text.parse().unwrap_or_default()))
+            } else {
+                Value::Text(String::from(*text))
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Rows with the cells spanning several columns or rows repeated in each of
+/// them.
+fn expand_spans(rows: &[Vec<HtmlCell>]) -> Vec<Vec<HtmlCell>> {
+    let mut result: Vec<Vec<HtmlCell>> = Vec::new();
+    // Cells from rows above that reach into the next rows, with the number
+    // of rows still to go.
+    let mut carried: Vec<(usize, HtmlCell)> = Vec::new();
+
+    for row in rows.iter() {
+        let mut expanded: Vec<HtmlCell> = Vec::new();
+        let mut cells = row.iter();
+
+        loop {
+            let col_i = expanded.len();
+            if let Some((remaining, cell)) = carried.get_mut(col_i).filter(|(n, _)| *n > 0) {
+                *remaining -= 1;
+                expanded.push(cell.clone());
+                continue;
+            }
+            let cell = match cells.next() {
+                Some(cell) => cell,
+                None => break,
+            };
+            for _ in 0..cell.colspan {
+                let col_i = expanded.len();
+                if carried.len() <= col_i {
+                    carried.resize(col_i + 1, (0, HtmlCell::default()));
+                }
+                carried[col_i] = (cell.rowspan - 1, cell.clone());
+                expanded.push(cell.clone());
+            }
+        }
+
+        result.push(expanded);
+    }
+
+    result
+}
+
+/// Value of a `colspan` or `rowspan` attribute, 1 if there is none.
+fn span_attribute(attributes: &str, name: &str) -> usize {
+    attribute_value(attributes, name)
+        .and_then(|value| value.trim().parse::<usize>().ok())
+        .filter(|span| *span >= 1)
+        .unwrap_or(1)
+}
+
+fn attribute_value<'h>(attributes: &'h str, name: &str) -> Option<&'h str> {
+    let lower = attributes.to_ascii_lowercase();
+    let mut search_from = 0;
+
+    while let Some(found) = lower[search_from..].find(name) {
+        let start = search_from + found;
+        search_from = start + name.len();
+        let preceded_by_name = lower[..start]
+            .chars()
+            .last()
+            .is_some_and(|c| c.is_alphanumeric() || c == '-');
+        let rest = lower[search_from..].trim_start();
+        if preceded_by_name || !rest.starts_with('=') {
+            continue;
+        }
+
+        let value_start = attributes.len() - rest.len() + 1;
+        let value = attributes[value_start..].trim_start();
+        return match value.chars().next() {
+            Some(quote) if quote == '"' || quote == '\'' => value[1..].split(quote).next(),
+            _ => value.split_whitespace().next(),
+        };
+    }
+
+    None
+}
+
+/// Split HTML into tags and text (with entities decoded). Comments, and the
+/// contents of `style` and `script` elements, are left out.
+fn html_pieces(html: &str) -> Vec<HtmlPiece> {
+    let mut pieces: Vec<HtmlPiece> = Vec::new();
+    let mut rest = html;
+
+    while !rest.is_empty() {
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+            continue;
+        }
+
+        let tag_name_follows = rest.starts_with('<')
+            && rest[1..]
+                .chars()
+                .next()
+                .is_some_and(|c| c.is_ascii_alphabetic() || c == '/' || c == '!');
+        if !tag_name_follows {
+            let first_len = rest.chars().next().map_or(1, char::len_utf8);
+            let end = rest[first_len..]
+                .find('<')
+                .map_or(rest.len(), |end| end + first_len);
+            pieces.push(HtmlPiece::Text(decode_entities(&rest[..end])));
+            rest = &rest[end..];
+            continue;
+        }
+
+        let end = rest.find('>').unwrap_or(rest.len());
+        let inner = rest[1..end].trim_end_matches('/');
+        rest = &rest[(end + 1).min(rest.len())..];
+
+        let is_end = inner.starts_with('/');
+        let inner = inner.trim_start_matches('/');
+        let name_end = inner
+            .find(|c: char| c.is_whitespace())
+            .unwrap_or(inner.len());
+        let name = inner[..name_end].to_lowercase();
+
+        if !is_end && (name == "style" || name == "script") {
+            let closing = format!("</{}", name);
+            rest = rest
+                .to_ascii_lowercase()
+                .find(&closing)
+                .and_then(|start| rest[start..].find('>').map(|end| &rest[start + end + 1..]))
+                .unwrap_or("");
+            continue;
+        }
+
+        pieces.push(HtmlPiece::Tag {
+            name,
+            attributes: String::from(&inner[name_end..]),
+            is_end,
+        });
+    }
+
+    pieces
+}
+
+fn decode_entities(text: &str) -> String {
+    let mut result = String::new();
+    let mut rest = text;
+
+    while let Some(start) = rest.find('&') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = match rest.find(';') {
+            Some(end) if end <= 10 => end,
+            _ => {
+                result.push('&');
+                rest = &rest[1..];
+                continue;
+            }
+        };
+        let entity = &rest[1..end];
+        let decoded = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some('\u{A0}'),
+            "times" => Some('\u{D7}'),
+            "hellip" => Some('\u{2026}'),
+            _ => match entity.strip_prefix('#') {
+                Some(number) => match number.strip_prefix(|c| c == 'x' || c == 'X') {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                    None => number.parse::<u32>().ok(),
+                }
+                .and_then(std::char::from_u32),
+                None => None,
+            },
+        };
+        match decoded {
+            Some(c) => {
+                result.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                result.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    result.push_str(rest);
+
+    result
+}
+
+/// Whitespace runs as single spaces, as a browser shows them. Non-breaking
+/// spaces are kept.
+fn collapse_whitespace(text: &str) -> String {
+    text.split(|c: char| c.is_whitespace() && c != '\u{A0}')
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::table_style::VerticalMerge;
+
+    /// Vertical merge of every cell of a column.
+    fn column_merges(html_table: &HtmlTable, col_i: usize) -> Vec<VerticalMerge> {
+        let table = &html_table.table;
+        let cell_texts: Vec<Vec<String>> = table
+            .columns
+            .iter()
+            .map(|column| column.iter().map(|value| format!("{:?}", value)).collect())
+            .collect();
+        (0..table.no_of_rows())
+            .map(|row_i| {
+                html_table
+                    .styling
+                    .vertical_merge(table, &cell_texts, col_i, row_i)
+            })
+            .collect()
+    }
+
+    fn cell(text: &str, colspan: usize, rowspan: usize) -> HtmlCell {
+        HtmlCell {
+            text: String::from(text),
+            is_header: false,
+            colspan,
+            rowspan,
+        }
+    }
+
+    fn texts(rows: &[Vec<HtmlCell>]) -> Vec<Vec<&str>> {
+        rows.iter()
+            .map(|row| row.iter().map(|cell| cell.text.as_str()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn colspan_and_rowspan_expansion() {
+        let rows = vec![
+            vec![cell("a", 2, 1), cell("b", 1, 3)],
+            vec![cell("c", 1, 2), cell("d", 1, 1)],
+            vec![cell("e", 1, 1)],
+        ];
+        assert_eq!(
+            texts(&expand_spans(&rows)),
+            vec![
+                vec!["a", "a", "b"],
+                vec!["c", "d", "b"],
+                vec!["c", "e", "b"]
+            ]
+        );
+    }
+
+    #[test]
+    fn multi_index_columns_and_named_index() {
+        let tables = html_tables(
+            r#"<table border="1" class="dataframe">
+              <thead>
+                <tr><th></th><th></th><th colspan="2" halign="left">2019</th><th>2020</th></tr>
+                <tr><th></th><th></th><th>q1</th><th>q2</th><th>q1</th></tr>
+                <tr><th>region</th><th>shop</th><th></th><th></th><th></th></tr>
+              </thead>
+              <tbody>
+                <tr><th rowspan="2" valign="top">north</th><th>a</th><td>1</td><td>2.5</td><td>NaN</td></tr>
+                <tr><th>b</th><td>3</td><td>4</td><td>x</td></tr>
+              </tbody>
+            </table>
+            <p>2 rows &times; 3 columns</p>"#,
+        );
+        assert_eq!(tables.len(), 1);
+        let html_table = &tables[0];
+        let table = &html_table.table;
+        assert_eq!(table.headers, vec!["region", "shop", "q1", "q2", "q1"]);
+        assert_eq!(
+            html_table.styling.group_headers,
+            vec![vec!["", "", "2019", "2019", "2020"]]
+        );
+        assert_eq!(html_table.styling.merge_repeated_columns, vec![0]);
+        assert_eq!(html_table.note.as_deref(), Some("2 rows \u{D7} 3 columns"));
+
+        assert!(matches!(table.cell(0, 1), Some(Value::Text(text)) if text == "north"));
+        assert!(matches!(
+            table.cell(2, 0),
+            Some(Value::Number(Numeric::Integer(1)))
+        ));
+        assert!(matches!(
+            table.cell(3, 0),
+            Some(Value::Number(Numeric::Real(real))) if (*real - 2.5).abs() < 1e-9
+        ));
+        // A column with text stays text; `NaN` is missing.
+        assert!(table.cell(4, 0).is_none());
+        assert!(matches!(table.cell(4, 1), Some(Value::Text(text)) if text == "x"));
+    }
+
+    #[test]
+    fn entities_and_whitespace() {
+        assert_eq!(
+            decode_entities("a &amp; b &lt;c&gt; &#8364; &#x263A; &unknown; & d"),
+            "a & b <c> \u{20AC} \u{263A} &unknown; & d"
+        );
+        let tables = html_tables(
+            "<table><tr><th>Name</th></tr><tr><td>  Smith &amp;\n  Sons </td></tr></table>",
+        );
+        assert!(matches!(
+            tables[0].table.cell(0, 0),
+            Some(Value::Text(text)) if text == "Smith & Sons"
+        ));
+    }
+
+    #[test]
+    fn several_tables_with_captions() {
+        let tables = html_tables(
+            "<table><caption>OLS Regression Results</caption><tr><th>a</th><td>1</td></tr></table>\
+             <table><tr><th>b</th><td>2</td></tr></table>Notes: [1] Standard errors",
+        );
+        assert_eq!(tables.len(), 2);
+        assert_eq!(tables[0].title.as_deref(), Some("OLS Regression Results"));
+        assert!(tables[0].note.is_none());
+        assert!(tables[1].title.is_none());
+        assert_eq!(
+            tables[1].note.as_deref(),
+            Some("Notes: [1] Standard errors")
+        );
+        assert!(html_tables("<p>no tables</p>").is_empty());
+    }
+
+    #[test]
+    fn multi_index_with_integer_column_labels() {
+        let tables = html_tables(
+            r#"<table><thead><tr><th></th><th></th><th>0</th><th>1</th></tr></thead>
+            <tbody>
+            <tr><th rowspan="2">a</th><th>x</th><td>1</td><td>5</td></tr>
+            <tr><th>y</th><td>2</td><td>5</td></tr>
+            </tbody></table>"#,
+        );
+        let html_table = &tables[0];
+        assert_eq!(html_table.table.headers, vec!["", "", "0", "1"]);
+        assert_eq!(
+            column_merges(html_table, 0),
+            vec![VerticalMerge::Restart, VerticalMerge::Continue]
+        );
+        assert_eq!(
+            column_merges(html_table, 3),
+            vec![VerticalMerge::None, VerticalMerge::None]
+        );
+    }
+}
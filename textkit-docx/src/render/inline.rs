@@ -0,0 +1,243 @@
+//! Inline content: runs rendered from a placeholder (e.g. inline markdown)
+//! spliced into the template paragraph the placeholder is in, so that they
+//! take on the paragraph's properties and the run properties of the
+//! placeholder.
+//!
+
+use crate::render::{
+    char_text_tokens, is_wp_start_token, matching_end_index, paragraph_prequel_tokens,
+    paragraph_sequel_tokens, run_end_token, run_start_token, wp_end_token, wp_start_token,
+};
+use crate::{Token, NS_WP_ML};
+
+/// Children of `w:rPr` in the order of the schema. Elements of other
+/// namespaces (e.g. `w14:ligatures`) go last.
+const RUN_PROPERTY_ORDER: [&str; 40] = [
+    "rStyle",
+    "rFonts",
+    "b",
+    "bCs",
+    "i",
+    "iCs",
+    "caps",
+    "smallCaps",
+    "strike",
+    "dstrike",
+    "outline",
+    "shadow",
+    "emboss",
+    "imprint",
+    "noProof",
+    "snapToGrid",
+    "vanish",
+    "webHidden",
+    "color",
+    "spacing",
+    "w",
+    "kern",
+    "position",
+    "sz",
+    "szCs",
+    "highlight",
+    "u",
+    "effect",
+    "bdr",
+    "shd",
+    "fitText",
+    "vertAlign",
+    "rtl",
+    "cs",
+    "em",
+    "lang",
+    "eastAsianLayout",
+    "specVanish",
+    "oMath",
+    "rPrChange",
+];
+
+/// A part of a paragraph built from a template paragraph.
+#[derive(Debug)]
+pub(crate) enum InlinePiece {
+    /// Rendered template text, which goes into the placeholder's run.
+    Text(String),
+    /// Runs (or hyperlinks, etc.) that go between runs.
+    Runs(Vec<Token>),
+}
+
+/// The template paragraph with the placeholder text (at `text_index`)
+/// replaced by the pieces. The placeholder's run is split around runs, and
+/// the runs get its run properties underneath their own. Paragraphs where
+/// the placeholder is not in a `w:t` of a `w:r` get a plain paragraph.
+pub(crate) fn inline_paragraph_tokens(
+    template_tokens: &[Token],
+    text_index: usize,
+    pieces: &[InlinePiece],
+) -> Vec<Token> {
+    let text_start = (0..text_index)
+        .rev()
+        .find(|i| is_wp_start_token(&template_tokens[*i], "t"));
+    let run_start = text_start.and_then(|text_start| {
+        (0..text_start)
+            .rev()
+            .find(|i| is_wp_start_token(&template_tokens[*i], "r"))
+    });
+    let (text_start, run_start) = match (text_start, run_start) {
+        (Some(text_start), Some(run_start)) => (text_start, run_start),
+        _ => return plain_paragraph_tokens(pieces),
+    };
+
+    let base_properties: &[Token] = match template_tokens.get(run_start + 1) {
+        Some(token) if is_wp_start_token(token, "rPr") => {
+            match matching_end_index(template_tokens, run_start + 1, "rPr") {
+                Some(end) => &template_tokens[run_start + 1..=end],
+                None => &[],
+            }
+        }
+        _ => &[],
+    };
+
+    let mut result: Vec<Token> = Vec::new();
+    result.extend_from_slice(&template_tokens[..text_start]);
+    // Spaces at the edges of the split text have to survive.
+    result.push(preserved_text_start_token());
+    for piece in pieces {
+        match piece {
+            InlinePiece::Text(text) => result.push(characters_token(text)),
+            InlinePiece::Runs(runs) => {
+                result.push(wp_end_token("t"));
+                result.push(run_end_token());
+                result.extend(with_base_run_properties(runs, base_properties));
+                result.push(run_start_token());
+                result.extend_from_slice(base_properties);
+                result.push(preserved_text_start_token());
+            }
+        }
+    }
+    result.extend_from_slice(&template_tokens[text_index + 1..]);
+
+    result
+}
+
+fn plain_paragraph_tokens(pieces: &[InlinePiece]) -> Vec<Token> {
+    let mut result: Vec<Token> = paragraph_prequel_tokens();
+    for piece in pieces {
+        match piece {
+            InlinePiece::Text(text) => {
+                result.push(run_start_token());
+                result.extend(char_text_tokens(text, true));
+                result.push(run_end_token());
+            }
+            InlinePiece::Runs(runs) => result.extend(runs.iter().cloned()),
+        }
+    }
+    result.extend(paragraph_sequel_tokens());
+    result
+}
+
+fn preserved_text_start_token() -> Token {
+    char_text_tokens("", true).remove(0)
+}
+
+fn characters_token(text: &str) -> Token {
+    char_text_tokens(text, true).remove(1)
+}
+
+/// The runs with the base run properties merged into their own.
+fn with_base_run_properties(runs: &[Token], base_properties: &[Token]) -> Vec<Token> {
+    if base_properties.is_empty() {
+        return runs.to_vec();
+    }
+
+    let mut result: Vec<Token> = Vec::new();
+    let mut i: usize = 0;
+
+    while i < runs.len() {
+        result.push(runs[i].clone());
+        if is_wp_start_token(&runs[i], "r") {
+            let own_properties = match runs.get(i + 1) {
+                Some(token) if is_wp_start_token(token, "rPr") => {
+                    matching_end_index(runs, i + 1, "rPr").map(|end| &runs[i + 1..=end])
+                }
+                _ => None,
+            };
+            match own_properties {
+                Some(own_properties) => {
+                    result.extend(merge_run_properties(base_properties, own_properties));
+                    i += own_properties.len();
+                }
+                None => result.extend_from_slice(base_properties),
+            }
+        }
+        i += 1;
+    }
+
+    result
+}
+
+/// A `w:rPr` with the children of both, the run's own ones replacing the
+/// base ones of the same name.
+fn merge_run_properties(base_properties: &[Token], own_properties: &[Token]) -> Vec<Token> {
+    let own_children = property_children(own_properties);
+    let mut children: Vec<(String, &[Token])> = property_children(base_properties)
+        .into_iter()
+        .filter(|(name, _)| !own_children.iter().any(|(own_name, _)| own_name == name))
+        .chain(own_children.iter().cloned())
+        .collect();
+
+    let order = |name: &str| {
+        RUN_PROPERTY_ORDER
+            .iter()
+            .position(|known| *known == name)
+            .unwrap_or(RUN_PROPERTY_ORDER.len())
+    };
+    children.sort_by_key(|(name, _)| order(name));
+
+    let mut result: Vec<Token> = Vec::new();
+    result.push(wp_start_token("rPr", &[]));
+    for (_, tokens) in children {
+        result.extend_from_slice(tokens);
+    }
+    result.push(wp_end_token("rPr"));
+    result
+}
+
+/// The child elements of a `w:rPr` (given with its start and end), by name.
+/// Names of elements outside the `w:` namespace are empty.
+fn property_children(properties: &[Token]) -> Vec<(String, &[Token])> {
+    let mut children: Vec<(String, &[Token])> = Vec::new();
+    let inner = match properties.len() {
+        0 | 1 => return children,
+        len => &properties[1..len - 1],
+    };
+
+    let mut depth: usize = 0;
+    let mut child_start: usize = 0;
+    for (i, token) in inner.iter().enumerate() {
+        match &token.xml_reader_event {
+            xml::reader::XmlEvent::StartElement { .. } => {
+                if depth == 0 {
+                    child_start = i;
+                }
+                depth += 1;
+            }
+            xml::reader::XmlEvent::EndElement { .. } => {
+                depth = depth.saturating_sub(1);
+                if depth == 0 {
+                    let start_token = &inner[child_start];
+                    let name = match &start_token.xml_reader_event {
+                        xml::reader::XmlEvent::StartElement { name, .. }
+                            if name.namespace.as_deref() == Some(NS_WP_ML) =>
+                        {
+                            name.local_name.clone()
+                        }
+                        _ => String::new(),
+                    };
+                    children.push((name, &inner[child_start..=i]));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    children
+}
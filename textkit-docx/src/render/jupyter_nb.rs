@@ -1,14 +1,172 @@
 //! Render Jupyter Notebooks
 //!
 
+use crate::errors::TextkitDocxError;
+use crate::parse::{option_bool, option_list, option_text};
 use crate::render::{
-    image_paragraph_tokens, markdown::markdown_to_tokens, monospace_paragraph_tokens,
+    ansi::ansi_lines,
+    code_block::{
+        code_block_tokens, code_label_tokens, output_block_tokens, BlockKind, OutputSpan,
+    },
+    datakit_table::{datakit_table_to_tokens, TableOptions},
+    html_table::html_tables,
+    image_paragraph_tokens,
+    markdown::markdown_to_tokens,
+    DocumentParts, RenderSettings,
 };
 use crate::Token;
-use crate::{FileContents, ImageFileContents};
 use serde::{Deserialize, Serialize};
 use std::collections::{BTreeMap, HashMap};
-use std::io::Cursor;
+
+/// Which parts of code cells are rendered.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JupyterCodeCells {
+    /// The code, followed by what it produced.
+    InputOutput,
+    Output,
+    Input,
+}
+
+impl JupyterCodeCells {
+    /// Parse `input_output`, `output` or `input` (`-` and spaces may stand
+    /// for `_`).
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.trim().to_lowercase().replace(&['-', ' '][..], "_");
+        match name.as_str() {
+            "input_output" | "both" | "all" => Some(Self::InputOutput),
+            "output" | "outputs" => Some(Self::Output),
+            "input" | "inputs" | "code" => Some(Self::Input),
+            _ => None,
+        }
+    }
+
+    fn has_input(self) -> bool {
+        self != Self::Output
+    }
+
+    fn has_output(self) -> bool {
+        self != Self::Input
+    }
+}
+
+/// How notebooks are rendered.
+#[derive(Debug, Clone, Copy)]
+pub struct JupyterOptions {
+    /// Parts of code cells to render. Only outputs by default, as notebooks
+    /// are usually rendered for their results.
+    pub code_cells: JupyterCodeCells,
+
+    /// Put the `In [n]:` prompt (the execution count) above the code of a
+    /// cell.
+    pub prompts: bool,
+}
+
+impl Default for JupyterOptions {
+    fn default() -> Self {
+        Self {
+            code_cells: JupyterCodeCells::Output,
+            prompts: false,
+        }
+    }
+}
+
+impl JupyterOptions {
+    /// Take the notebook options (`cells` and `prompts`) out of the hash
+    /// arguments of a placeholder.
+    pub(crate) fn take_from_hash_args(
+        &self,
+        hash_args: &mut BTreeMap<String, serde_json::Value>,
+    ) -> Result<Self, TextkitDocxError> {
+        let mut options = *self;
+
+        if let Some(value) = hash_args.remove("cells") {
+            options.code_cells = value
+                .as_str()
+                .and_then(JupyterCodeCells::from_name)
+                .ok_or_else(|| TextkitDocxError::BadHelperOption(format!("cells={}", value)))?;
+        }
+        if let Some(value) = hash_args.remove("prompts") {
+            options.prompts = option_bool(&value)
+                .ok_or_else(|| TextkitDocxError::BadHelperOption(format!("prompts={}", value)))?;
+        }
+
+        Ok(options)
+    }
+}
+
+/// Which cells of a notebook are rendered, by their tags and position.
+/// Independently of these, cells tagged `remove-cell` are never rendered.
+#[derive(Debug, Clone, Default)]
+pub struct JupyterCellFilter {
+    /// Only render cells with one of these tags (all cells when empty).
+    pub tags: Vec<String>,
+
+    /// Do not render cells with any of these tags.
+    pub exclude_tags: Vec<String>,
+
+    /// Start at this cell: its id, its `name` metadata, one of its tags or its
+    /// 1-based number.
+    pub from: Option<String>,
+
+    /// End at (and including) this cell, named as for `from`.
+    pub to: Option<String>,
+}
+
+impl JupyterCellFilter {
+    /// Take the cell filter options (`tags`, `exclude_tags`, `from` and `to`)
+    /// out of the hash arguments of a placeholder.
+    pub(crate) fn take_from_hash_args(hash_args: &mut BTreeMap<String, serde_json::Value>) -> Self {
+        let name = |value: serde_json::Value| {
+            let name = option_text(&value);
+            String::from(name.trim())
+        };
+
+        Self {
+            tags: hash_args
+                .remove("tags")
+                .map(|value| option_list(&value))
+                .unwrap_or_default(),
+            exclude_tags: hash_args
+                .remove("exclude_tags")
+                .map(|value| option_list(&value))
+                .unwrap_or_default(),
+            from: hash_args.remove("from").map(name),
+            to: hash_args.remove("to").map(name),
+        }
+    }
+
+    /// Indices of the cells from `from` up to and including `to` (empty for a
+    /// notebook without cells).
+    fn cell_range(
+        &self,
+        cells: &[JupyterCell],
+    ) -> Result<std::ops::Range<usize>, TextkitDocxError> {
+        let find = |key: &str, name: &str, start: usize| {
+            (start..cells.len())
+                .find(|cell_i| cells[*cell_i].is_named(name, *cell_i))
+                .ok_or_else(|| {
+                    TextkitDocxError::BadHelperOption(format!("{}={} (no such cell)", key, name))
+                })
+        };
+
+        let start = match &self.from {
+            Some(name) => find("from", name, 0)?,
+            None => 0,
+        };
+        let end = match &self.to {
+            Some(name) => find("to", name, start)? + 1,
+            None => cells.len(),
+        };
+
+        Ok(start..end)
+    }
+
+    fn includes(&self, cell: &JupyterCell) -> bool {
+        !cell.has_tag("remove-cell")
+            && (self.tags.is_empty() || self.tags.iter().any(|tag| cell.has_tag(tag)))
+            && !self.exclude_tags.iter().any(|tag| cell.has_tag(tag))
+    }
+}
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "snake_case")]
@@ -18,94 +176,202 @@ pub enum JupyterCellType {
     Raw,
 }
 
+/// Multi-line text, which nbformat stores either as a single string or as a
+/// list of lines that keep their line breaks.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum JupyterText {
+    Lines(Vec<String>),
+    Text(String),
+}
+
+impl Default for JupyterText {
+    fn default() -> Self {
+        Self::Lines(Vec::new())
+    }
+}
+
+impl JupyterText {
+    pub fn text(&self) -> String {
+        match self {
+            Self::Lines(lines) => lines.join(""),
+            Self::Text(text) => text.clone(),
+        }
+    }
+}
+
+/// Name of a stream output.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum JupyterStreamName {
+    Stdout,
+    Stderr,
+}
+
+/// An output of a code cell (nbformat 4). Rich outputs carry a MIME bundle:
+/// the same result in several formats, keyed by MIME type.
 #[derive(Serialize, Deserialize, Debug)]
-pub struct JupyterCellOutput {
-    // FIXME this is a crutch, but at least a quick one.
-    pub data: HashMap<String, serde_json::Value>,
-    pub execution_count: Option<usize>,
-    pub output_type: String,
+#[serde(tag = "output_type", rename_all = "snake_case")]
+pub enum JupyterCellOutput {
+    Stream {
+        name: JupyterStreamName,
+        text: JupyterText,
+    },
+    DisplayData {
+        data: HashMap<String, serde_json::Value>,
+        #[serde(default)]
+        metadata: HashMap<String, serde_json::Value>,
+    },
+    ExecuteResult {
+        data: HashMap<String, serde_json::Value>,
+        #[serde(default)]
+        metadata: HashMap<String, serde_json::Value>,
+        execution_count: Option<usize>,
+    },
+    Error {
+        ename: String,
+        evalue: String,
+        #[serde(default)]
+        traceback: Vec<String>,
+    },
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct JupyterCell {
+    /// Cell id (nbformat 4.5 and later).
+    pub id: Option<String>,
     pub cell_type: JupyterCellType,
-    pub source: Vec<String>,
+    #[serde(default)]
+    pub metadata: HashMap<String, serde_json::Value>,
+    pub source: JupyterText,
     pub outputs: Option<Vec<JupyterCellOutput>>,
     pub execution_count: Option<usize>,
 }
 
+impl JupyterCell {
+    /// Tags of the cell (`tags` metadata).
+    pub fn tags(&self) -> Vec<&str> {
+        self.metadata
+            .get("tags")
+            .and_then(|tags| tags.as_array())
+            .map(|tags| tags.iter().filter_map(|tag| tag.as_str()).collect())
+            .unwrap_or_default()
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags().contains(&tag)
+    }
+
+    /// Whether the input is left out: tagged `remove-input` or `hide-input`
+    /// (a document cannot fold it away), or collapsed in JupyterLab
+    /// (`jupyter.source_hidden`).
+    pub fn input_hidden(&self) -> bool {
+        self.has_tag("remove-input")
+            || self.has_tag("hide-input")
+            || self.jupyter_flag("source_hidden")
+    }
+
+    /// Whether the outputs are left out: tagged `remove-output` or
+    /// `hide-output`, or collapsed (`jupyter.outputs_hidden`).
+    pub fn outputs_hidden(&self) -> bool {
+        self.has_tag("remove-output")
+            || self.has_tag("hide-output")
+            || self.jupyter_flag("outputs_hidden")
+    }
+
+    /// A flag of the `jupyter` metadata namespace.
+    fn jupyter_flag(&self, key: &str) -> bool {
+        self.metadata
+            .get("jupyter")
+            .and_then(|jupyter| jupyter.get(key))
+            .and_then(|flag| flag.as_bool())
+            .unwrap_or(false)
+    }
+
+    /// Whether the cell at index `cell_i` goes by this name: its id, its
+    /// `name` metadata, one of its tags or its 1-based number.
+    fn is_named(&self, name: &str, cell_i: usize) -> bool {
+        self.id.as_deref() == Some(name)
+            || self.metadata.get("name").and_then(|value| value.as_str()) == Some(name)
+            || self.has_tag(name)
+            || name.parse::<usize>().ok() == Some(cell_i + 1)
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct JupyterNotebook {
     pub cells: Vec<JupyterCell>,
+    #[serde(default)]
+    pub metadata: HashMap<String, serde_json::Value>,
     pub nbformat: usize,
     pub nbformat_minor: usize,
 }
 
+impl JupyterNotebook {
+    /// Language of the code cells, from the language info or the kernel spec.
+    pub fn language(&self) -> Option<&str> {
+        self.metadata
+            .get("language_info")
+            .and_then(|info| info.get("name"))
+            .or_else(|| {
+                self.metadata
+                    .get("kernelspec")
+                    .and_then(|kernel| kernel.get("language"))
+            })
+            .and_then(|language| language.as_str())
+    }
+}
+
 pub(crate) fn jupyter_nb_to_tokens(
     ipynb: &JupyterNotebook,
-    start_rels_id: &mut usize,
-    images: &mut BTreeMap<String, ImageFileContents>,
-) -> Vec<Token> {
+    filter: &JupyterCellFilter,
+    settings: &RenderSettings,
+    parts: &mut DocumentParts,
+) -> Result<Vec<Token>, TextkitDocxError> {
     let mut result: Vec<Token> = Vec::new();
-    let mut image_counter: usize = 1;
+    let options = settings.jupyter;
+    let range = filter.cell_range(&ipynb.cells)?;
 
-    for cell in ipynb.cells.iter() {
+    for cell in ipynb
+        .cells
+        .get(range)
+        .unwrap_or_default()
+        .iter()
+        .filter(|cell| filter.includes(cell))
+    {
         match cell.cell_type {
+            // The source of a markdown cell is what it shows.
+            JupyterCellType::Markdown if cell.input_hidden() => {}
             JupyterCellType::Markdown => {
-                let source_s = cell.source.join("\n");
-                let tokens = markdown_to_tokens(&source_s);
+                // The lines of the source keep their line breaks.
+                let source_s = cell.source.text();
+                let tokens = markdown_to_tokens(&source_s, settings, parts);
                 result.extend(tokens);
             }
             JupyterCellType::Code => {
-                if let Some(outputs) = &cell.outputs {
-                    for output in outputs.iter() {
-                        if output.data.contains_key("text/plain") {
-                            let value = output.data.get("text/plain").unwrap();
-                            let text_lines: Vec<String> =
-                                serde_json::from_value(value.clone()).unwrap();
-                            for line in text_lines.iter() {
-                                result.extend(monospace_paragraph_tokens(line));
-                            }
-                        }
-
-                        if output.data.contains_key("image/png") {
-                            let value = output.data.get("image/png").unwrap();
-                            let base64_encoded_string: String =
-                                serde_json::from_value(value.clone()).unwrap();
-
-                            // N.B! Important to trim, because Jupyter seems to add an
-                            // explicit newline character at the end of the Base64 string, for
-                            // some reason.
-                            if let Ok(payload) = base64::decode(base64_encoded_string.trim()) {
-                                *start_rels_id += 1;
-                                let figure_relationship_id = format!("rId{}", start_rels_id);
-                                let filename = format!("figure-{}.png", image_counter);
-                                let (width, height) = get_png_dimensions(&payload);
-                                images.insert(
-                                    figure_relationship_id.clone(),
-                                    ImageFileContents {
-                                        file_contents: FileContents { filename, payload },
-                                        height: height,
-                                        width: width,
-                                    },
-                                );
-
-                                let tokens = image_paragraph_tokens(
-                                    &figure_relationship_id,
-                                    width,
-                                    height,
-                                    image_counter,
-                                );
-
-                                result.extend(tokens);
-
-                                image_counter += 1;
-                            } else {
-                                // TODO do some proper error handling or error notifications
-                                // here.
-                            }
-                        }
+                let source_s = cell.source.text();
+                if options.code_cells.has_input()
+                    && !cell.input_hidden()
+                    && !source_s.trim().is_empty()
+                {
+                    if options.prompts {
+                        let execution_count = cell
+                            .execution_count
+                            .map_or_else(|| String::from(" "), |count| format!("{}", count));
+                        result.extend(code_label_tokens(&format!("In [{}]:", execution_count)));
                     }
+                    result.extend(code_block_tokens(
+                        &source_s,
+                        ipynb.language(),
+                        &settings.code_blocks,
+                    ));
+                }
+
+                if !options.code_cells.has_output() || cell.outputs_hidden() {
+                    continue;
+                }
+                for output in cell.outputs.iter().flatten() {
+                    result.extend(output_tokens(output, settings, parts));
                 }
             }
             JupyterCellType::Raw => {
@@ -114,12 +380,250 @@ pub(crate) fn jupyter_nb_to_tokens(
         }
     }
 
+    Ok(result)
+}
+
+fn output_tokens(
+    output: &JupyterCellOutput,
+    settings: &RenderSettings,
+    parts: &mut DocumentParts,
+) -> Vec<Token> {
+    let mut result: Vec<Token> = Vec::new();
+
+    match output {
+        JupyterCellOutput::Stream { name, text } => {
+            let kind = match name {
+                JupyterStreamName::Stdout => BlockKind::Output,
+                JupyterStreamName::Stderr => BlockKind::Error,
+            };
+            result.extend(output_block_tokens(&ansi_lines(&text.text()), kind));
+        }
+        JupyterCellOutput::DisplayData { data, .. } => {
+            result.extend(mime_bundle_tokens(data, settings, parts));
+        }
+        JupyterCellOutput::ExecuteResult {
+            data,
+            execution_count,
+            ..
+        } => {
+            if settings.jupyter.prompts {
+                let execution_count =
+                    execution_count.map_or_else(|| String::from(" "), |count| format!("{}", count));
+                result.extend(code_label_tokens(&format!("Out[{}]:", execution_count)));
+            }
+            result.extend(mime_bundle_tokens(data, settings, parts));
+        }
+        JupyterCellOutput::Error {
+            ename,
+            evalue,
+            traceback,
+        } => {
+            // The traceback ends with the error itself; without one, the
+            // error is all there is.
+            let text = if traceback.is_empty() {
+                format!("{}: {}", ename, evalue)
+            } else {
+                traceback
+                    .iter()
+                    .map(|entry| entry.trim_end_matches('\n'))
+                    .collect::<Vec<&str>>()
+                    .join("\n")
+            };
+            result.extend(output_block_tokens(&ansi_lines(&text), BlockKind::Error));
+        }
+    }
+
     result
 }
 
-fn get_png_dimensions(png_payload: &[u8]) -> (u32, u32) {
-    let cursor = Cursor::new(png_payload);
-    let decoder = png::Decoder::new(cursor);
-    let (info, _) = decoder.read_info().unwrap();
-    (info.width, info.height)
+/// The richest format of a MIME bundle that can go into a document: a PNG
+/// image, a table (e.g. a DataFrame), else plain text.
+fn mime_bundle_tokens(
+    data: &HashMap<String, serde_json::Value>,
+    settings: &RenderSettings,
+    parts: &mut DocumentParts,
+) -> Vec<Token> {
+    if let Some(base64_encoded_string) = mime_text(data, "image/png") {
+        // N.B! Important to trim, because Jupyter seems to add an
+        // explicit newline character at the end of the Base64 string, for
+        // some reason.
+        if let Some(image) = base64::decode(base64_encoded_string.trim())
+            .ok()
+            .and_then(|payload| parts.add_image(payload))
+        {
+            return image_paragraph_tokens(
+                &image.relationship_id,
+                image.width,
+                image.height,
+                image.serial_number,
+            );
+        }
+    }
+
+    let html_tables = mime_text(data, "text/html")
+        .map(|html| html_tables(&html))
+        .unwrap_or_default();
+    if !html_tables.is_empty() {
+        // Only the layout of the template's tables applies, as for tables in
+        // markdown.
+        let options = TableOptions {
+            autofit: settings.table_options.autofit,
+            format: settings.table_options.format.clone(),
+            ..TableOptions::default()
+        };
+        let mut result: Vec<Token> = Vec::new();
+        for html_table in html_tables {
+            if let Some(title) = html_table.title {
+                result.extend(output_text_tokens(title, true));
+            }
+            result.extend(datakit_table_to_tokens(
+                &html_table.table,
+                &html_table.styling,
+                settings.dimensions,
+                &options,
+            ));
+            if let Some(note) = html_table.note {
+                result.extend(output_text_tokens(note, false));
+            }
+        }
+        return result;
+    }
+
+    match mime_text(data, "text/plain") {
+        Some(text) => output_block_tokens(&ansi_lines(&text), BlockKind::Output),
+        None => Vec::new(),
+    }
+}
+
+/// Text of a MIME bundle entry, given as a string or a list of lines.
+fn mime_text(data: &HashMap<String, serde_json::Value>, mime_type: &str) -> Option<String> {
+    data.get(mime_type)
+        .and_then(|value| serde_json::from_value::<JupyterText>(value.clone()).ok())
+        .map(|text| text.text())
+}
+
+/// A line of output text, e.g. the title of a table.
+fn output_text_tokens(text: String, bold: bool) -> Vec<Token> {
+    let line = vec![OutputSpan {
+        text,
+        color: None,
+        bold,
+    }];
+    output_block_tokens(&[line], BlockKind::Output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::FileAccess;
+    use crate::render::{
+        code_block::CodeBlockOptions,
+        footnotes::Footnotes,
+        markdown::MarkdownStyles,
+        numbering::Numbering,
+        styles::StyleSheet,
+        testing::{a4_dimensions, xml},
+    };
+
+    fn cells(json: &str) -> Vec<JupyterCell> {
+        serde_json::from_str(json).unwrap()
+    }
+
+    /// The XML of a notebook with the given cells.
+    fn render_xml(cells_json: &str, jupyter: JupyterOptions) -> String {
+        let notebook: JupyterNotebook = serde_json::from_str(&format!(
+            r#"{{"cells": {}, "nbformat": 4, "nbformat_minor": 5}}"#,
+            cells_json
+        ))
+        .unwrap();
+        let dimensions = a4_dimensions();
+        let table_options = TableOptions::default();
+        let styles = StyleSheet::from_xml(None).unwrap();
+        let settings = RenderSettings {
+            dimensions: &dimensions,
+            table_options: &table_options,
+            code_blocks: CodeBlockOptions::default(),
+            jupyter,
+            styles: &styles,
+            markdown_styles: &MarkdownStyles::default(),
+            files: &FileAccess::default(),
+        };
+        let mut parts = DocumentParts {
+            latest_rels_id: 0,
+            images: BTreeMap::new(),
+            relationships: Vec::new(),
+            numbering: Numbering::from_xml(None).unwrap(),
+            footnotes: Footnotes::from_xml(None).unwrap(),
+            warnings: Vec::new(),
+        };
+        let tokens = jupyter_nb_to_tokens(
+            &notebook,
+            &JupyterCellFilter::default(),
+            &settings,
+            &mut parts,
+        )
+        .unwrap();
+        xml(&tokens)
+    }
+
+    #[test]
+    fn cell_range_of_empty_notebook_is_empty() {
+        let range = JupyterCellFilter::default().cell_range(&[]).unwrap();
+        assert!(range.is_empty());
+        assert!(Vec::<JupyterCell>::new().get(range).is_some());
+    }
+
+    #[test]
+    fn cell_range_by_name_tag_and_number() {
+        let cells = cells(
+            r#"[
+                {"cell_type": "markdown", "source": "a"},
+                {"cell_type": "markdown", "source": "b", "metadata": {"name": "start"}},
+                {"cell_type": "markdown", "source": "c", "metadata": {"tags": ["end"]}},
+                {"cell_type": "markdown", "source": "d"}
+            ]"#,
+        );
+        let filter = JupyterCellFilter {
+            from: Some(String::from("start")),
+            to: Some(String::from("end")),
+            ..JupyterCellFilter::default()
+        };
+        assert_eq!(filter.cell_range(&cells).unwrap(), 1..3);
+
+        let filter = JupyterCellFilter {
+            from: Some(String::from("3")),
+            ..JupyterCellFilter::default()
+        };
+        assert_eq!(filter.cell_range(&cells).unwrap(), 2..4);
+
+        let filter = JupyterCellFilter {
+            to: Some(String::from("missing")),
+            ..JupyterCellFilter::default()
+        };
+        assert!(filter.cell_range(&cells).is_err());
+    }
+
+    #[test]
+    fn code_cell_prompts() {
+        let cells_json = r#"[
+            {"cell_type": "code", "source": "1 + 2", "execution_count": 3,
+             "outputs": [{"output_type": "execute_result", "execution_count": 3,
+                          "data": {"text/plain": "3"}, "metadata": {}}]},
+            {"cell_type": "code", "source": "x", "execution_count": null, "outputs": []}
+        ]"#;
+        let mut options = JupyterOptions {
+            code_cells: JupyterCodeCells::InputOutput,
+            prompts: true,
+        };
+        let xml = render_xml(cells_json, options);
+        assert!(xml.contains(r#"<w:keepNext></w:keepNext>"#));
+        assert!(xml.contains(">In [3]:</w:t>"));
+        assert!(xml.contains(">Out[3]:</w:t>"));
+        assert!(xml.contains(">In [ ]:</w:t>"));
+        assert!(xml.find(">In [3]:</w:t>") < xml.find(">1 + 2</w:t>"));
+
+        options.prompts = false;
+        let xml = render_xml(cells_json, options);
+        assert!(!xml.contains("In [") && !xml.contains("Out["));
+    }
 }
@@ -0,0 +1,303 @@
+//! Two-column "Property | Value" tables from (nested) JSON objects.
+//!
+
+use crate::errors::TextkitDocxError;
+use crate::parse::{option_bool, option_list, option_text};
+use crate::render::table_data::TableData;
+use datakit::{value::definitions::*, value::primitives::*};
+use std::collections::BTreeMap;
+
+/// How an object is turned into a key-value table.
+#[derive(Debug, Clone)]
+pub struct KvOptions {
+    /// Joins the keys of nested objects, e.g. `model.params.alpha`.
+    pub separator: String,
+
+    /// Turn keys like `modelName` or `model_id` into `Model name` and `Model id`.
+    pub humanize: bool,
+
+    /// Only these (flattened, not humanized) keys, in this order. All keys if `None`.
+    pub keys: Option<Vec<String>>,
+
+    /// Names of the key and value columns.
+    pub headers: (String, String),
+}
+
+impl Default for KvOptions {
+    fn default() -> Self {
+        Self {
+            separator: String::from("."),
+            humanize: false,
+            keys: None,
+            headers: (String::from("Property"), String::from("Value")),
+        }
+    }
+}
+
+impl KvOptions {
+    /// Take the key-value options (`separator`, `humanize`, `keys` and
+    /// `headers`) out of the hash arguments of a `{{kvtable ...}}` placeholder,
+    /// leaving the table options.
+    pub(crate) fn take_from_hash_args(
+        hash_args: &mut BTreeMap<String, serde_json::Value>,
+    ) -> Result<Self, TextkitDocxError> {
+        let mut options = Self::default();
+
+        if let Some(value) = hash_args.remove("separator") {
+            options.separator = option_text(&value);
+        }
+        if let Some(value) = hash_args.remove("humanize") {
+            options.humanize = option_bool(&value)
+                .ok_or_else(|| TextkitDocxError::BadHelperOption(format!("humanize={}", value)))?;
+        }
+        if let Some(value) = hash_args.remove("keys") {
+            options.keys = Some(option_list(&value));
+        }
+        if let Some(value) = hash_args.remove("headers") {
+            match option_list(&value).as_slice() {
+                [key_header, value_header] => {
+                    options.headers = (key_header.clone(), value_header.clone())
+                }
+                _ => {
+                    return Err(TextkitDocxError::BadHelperOption(format!(
+                        "headers={}",
+                        value
+                    )))
+                }
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+/// A key-value table of an object, with nested objects flattened into
+/// `outer<separator>inner` keys. Arrays of plain values become a comma
+/// separated list, other arrays are flattened by index.
+pub fn kv_table(object: &serde_json::Value, options: &KvOptions) -> TableData {
+    let mut entries: Vec<(Vec<String>, Option<Value>)> = Vec::new();
+    flatten(object, &mut Vec::new(), &mut entries);
+
+    if let Some(keys) = &options.keys {
+        entries = keys
+            .iter()
+            .filter_map(|key| {
+                entries
+                    .iter()
+                    .find(|(path, _)| path.join(&options.separator) == *key)
+                    .cloned()
+            })
+            .collect();
+    }
+
+    let keys: Vec<Option<Value>> = entries
+        .iter()
+        .map(|(path, _)| {
+            let segments: Vec<String> = if options.humanize {
+                path.iter().map(|segment| humanize(segment)).collect()
+            } else {
+                path.clone()
+            };
+            Some(Value::Text(segments.join(&options.separator)))
+        })
+        .collect();
+    let values: Vec<Option<Value>> = entries.into_iter().map(|(_, value)| value).collect();
+
+    TableData {
+        headers: vec![options.headers.0.clone(), options.headers.1.clone()],
+        columns: vec![keys, values],
+    }
+}
+
+fn flatten(
+    value: &serde_json::Value,
+    path: &mut Vec<String>,
+    entries: &mut Vec<(Vec<String>, Option<Value>)>,
+) {
+    match value {
+        // An empty nested object still gets its row, without a value.
+        serde_json::Value::Object(map) if map.is_empty() && !path.is_empty() => {
+            entries.push((path.clone(), None));
+        }
+        serde_json::Value::Object(map) => {
+            for (key, member) in map.iter() {
+                path.push(key.clone());
+                flatten(member, path, entries);
+                path.pop();
+            }
+        }
+        serde_json::Value::Array(items)
+            if items.iter().any(|item| item.is_object() || item.is_array()) =>
+        {
+            for (i, item) in items.iter().enumerate() {
+                path.push(format!("{}", i));
+                flatten(item, path, entries);
+                path.pop();
+            }
+        }
+        serde_json::Value::Array(items) => {
+            let texts: Vec<String> = items
+                .iter()
+                .map(|item| match item {
+                    serde_json::Value::String(text) => text.clone(),
+                    other => other.to_string(),
+                })
+                .collect();
+            entries.push((path.clone(), Some(Value::Text(texts.join(", ")))));
+        }
+        serde_json::Value::String(text) => {
+            entries.push((path.clone(), Some(Value::Text(text.clone()))));
+        }
+        serde_json::Value::Number(number) => {
+            let numeric = match number.as_i64() {
+                Some(int) => Numeric::Integer(int),
+                None => Numeric::Real(number.as_f64().unwrap_or(f64::NAN)),
+            };
+            entries.push((path.clone(), Some(Value::Number(numeric))));
+        }
+        serde_json::Value::Bool(boolean) => {
+            entries.push((path.clone(), Some(Value::Boolean(*boolean))));
+        }
+        serde_json::Value::Null => entries.push((path.clone(), None)),
+    }
+}
+
+/// `modelName`, `model_name` and `model-name` all become `Model name`.
+fn humanize(key: &str) -> String {
+    let mut words: Vec<String> = Vec::new();
+    let mut word = String::new();
+    let mut previous_lowercase = false;
+
+    for c in key.chars() {
+        if c == '_' || c == '-' || c.is_whitespace() {
+            if !word.is_empty() {
+                words.push(std::mem::take(&mut word));
+            }
+            previous_lowercase = false;
+            continue;
+        }
+        if c.is_uppercase() && previous_lowercase && !word.is_empty() {
+            words.push(std::mem::take(&mut word));
+        }
+        previous_lowercase = c.is_lowercase() || c.is_numeric();
+        word.push(c);
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+
+    let sentence = words
+        .iter()
+        .enumerate()
+        .map(|(i, word)| {
+            // Keep acronyms like `ID` as they are.
+            if i > 0 && word.chars().any(|c| c.is_lowercase()) {
+                word.to_lowercase()
+            } else {
+                word.clone()
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(" ");
+
+    let mut chars = sentence.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => sentence,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(text: &str) -> Option<Value> {
+        Some(Value::Text(String::from(text)))
+    }
+
+    fn model() -> serde_json::Value {
+        serde_json::json!({
+            "modelName": "ridge",
+            "params": {"alpha": 0.5, "fit_intercept": true, "extra": {}},
+            "features": ["age", "bmi"],
+            "folds": [{"score": 1}, {"score": 2}],
+            "notes": null,
+        })
+    }
+
+    #[test]
+    fn flattened_keys() {
+        let table = kv_table(&model(), &KvOptions::default());
+        assert_eq!(table.headers, vec!["Property", "Value"]);
+        assert_eq!(
+            table.columns[0],
+            vec![
+                text("modelName"),
+                text("params.alpha"),
+                text("params.fit_intercept"),
+                text("params.extra"),
+                text("features"),
+                text("folds.0.score"),
+                text("folds.1.score"),
+                text("notes"),
+            ]
+        );
+        assert_eq!(
+            table.columns[1],
+            vec![
+                text("ridge"),
+                Some(Value::Number(Numeric::Real(0.5))),
+                Some(Value::Boolean(true)),
+                None,
+                text("age, bmi"),
+                Some(Value::Number(Numeric::Integer(1))),
+                Some(Value::Number(Numeric::Integer(2))),
+                None,
+            ]
+        );
+
+        let empty = kv_table(&serde_json::json!({}), &KvOptions::default());
+        assert_eq!(empty.no_of_rows(), 0);
+    }
+
+    #[test]
+    fn selected_and_humanized_keys() {
+        let mut hash_args: BTreeMap<String, serde_json::Value> = BTreeMap::new();
+        hash_args.insert(
+            String::from("keys"),
+            serde_json::json!("params/alpha, modelName, missing"),
+        );
+        hash_args.insert(String::from("separator"), serde_json::json!("/"));
+        hash_args.insert(String::from("humanize"), serde_json::json!(true));
+        hash_args.insert(String::from("headers"), serde_json::json!("Key, Setting"));
+        hash_args.insert(String::from("limit"), serde_json::json!(5));
+        let options = KvOptions::take_from_hash_args(&mut hash_args).unwrap();
+        // Table options are left.
+        assert_eq!(hash_args.len(), 1);
+
+        let table = kv_table(&model(), &options);
+        assert_eq!(table.headers, vec!["Key", "Setting"]);
+        // In the order of `keys`, which are not humanized.
+        assert_eq!(
+            table.columns[0],
+            vec![text("Params/Alpha"), text("Model name")]
+        );
+    }
+
+    #[test]
+    fn humanized_keys() {
+        assert_eq!(humanize("modelName"), "Model name");
+        assert_eq!(humanize("model_name"), "Model name");
+        assert_eq!(humanize("model-name"), "Model name");
+        assert_eq!(humanize("model_ID"), "Model ID");
+        assert_eq!(humanize("r2Score"), "R2 score");
+        assert_eq!(humanize("__"), "");
+    }
+
+    #[test]
+    fn bad_options() {
+        let mut hash_args: BTreeMap<String, serde_json::Value> = BTreeMap::new();
+        hash_args.insert(String::from("headers"), serde_json::json!("Key"));
+        assert!(KvOptions::take_from_hash_args(&mut hash_args).is_err());
+    }
+}
@@ -1,136 +1,1570 @@
 use crate::{
+    files::FileAccess,
     render::{
-        char_text_tokens, end_tag_event, heading_prequel_tokens, heading_sequel_tokens,
-        paragraph_prequel_tokens, paragraph_sequel_tokens, run_end_token, run_start_token,
-        start_tag_event,
+        char_text_tokens,
+        code_block::code_block_tokens,
+        datakit_table::{datakit_table_to_tokens, ColumnAlignment, TableOptions},
+        heading_prequel_tokens, heading_sequel_tokens, image_run_tokens, is_wp_start_token,
+        matching_end_index,
+        math::{display_math_tokens, math_tokens},
+        numbering::{LIST_INDENT, MAX_LIST_LEVELS},
+        owned_attribute, paragraph_prequel_tokens, paragraph_sequel_tokens, run_end_token,
+        run_start_token, start_tag_event,
+        styles::{StyleKind, StyleSheet},
+        table_data::TableData,
+        table_style::TableStyling,
+        wp_empty_tokens, wp_end_token, wp_start_token, AddedImage, DocumentParts, RenderSettings,
     },
-    Token, TokenType, NS_WP_ML,
+    Token, TokenType, NS_REL, NS_WP_ML,
 };
-use pulldown_cmark::{Options, Parser};
+use datakit::{value::definitions::*, value::primitives::*};
+use pulldown_cmark::{Alignment, CodeBlockKind, LinkType, Options, Parser};
+use std::collections::BTreeMap;
 
-pub(crate) fn markdown_to_tokens(md_text: &str) -> Vec<Token> {
-    let mut result: Vec<Token> = Vec::new();
+/// Font of inline code spans.
+const CODE_FONT: &str = "Consolas";
+
+/// Background colour of inline code spans.
+const CODE_SHADING: &str = "F2F2F2";
+
+/// Colour of the left border of quotes when the template has no quote styles.
+const QUOTE_BORDER_COLOR: &str = "BFBFBF";
+
+/// Colour of hyperlinks. Colour and underline are set as well as the
+/// hyperlink style, in case the template does not define the style.
+const HYPERLINK_COLOR: &str = "0563C1";
+
+/// Font sizes (in half points) of headings 1 to 6 when the template has no
+/// heading styles.
+const HEADING_FONT_SIZES: [&str; 6] = ["32", "26", "24", "22", "22", "22"];
+
+/// The markdown elements that are rendered with a template style.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MarkdownElement {
+    /// Headings of levels 1 to 6.
+    Heading(u32),
+    Paragraph,
+    Quote,
+    /// Quotes nested in quotes.
+    IntenseQuote,
+    ListParagraph,
+    Hyperlink,
+    /// Inline code spans.
+    Code,
+    FootnoteText,
+    FootnoteReference,
+}
+
+impl MarkdownElement {
+    /// The element for a name like `heading1`, `paragraph` or `list_paragraph`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.trim().to_lowercase().replace(&['-', ' '][..], "_");
+        match name.as_str() {
+            "paragraph" => Some(Self::Paragraph),
+            "quote" => Some(Self::Quote),
+            "intense_quote" => Some(Self::IntenseQuote),
+            "list_paragraph" => Some(Self::ListParagraph),
+            "hyperlink" | "link" => Some(Self::Hyperlink),
+            "code" => Some(Self::Code),
+            "footnote_text" => Some(Self::FootnoteText),
+            "footnote_reference" => Some(Self::FootnoteReference),
+            _ => name
+                .strip_prefix("heading")
+                .and_then(|level| level.trim_start_matches('_').parse::<u32>().ok())
+                .filter(|level| (1..=6).contains(level))
+                .map(Self::Heading),
+        }
+    }
+
+    /// Name of the Word style used by default. Built-in styles keep their
+    /// English name whatever the language of the template, unlike their ids.
+    fn builtin_style_name(self) -> Option<String> {
+        match self {
+            Self::Heading(level) => Some(format!("heading {}", level)),
+            Self::Paragraph | Self::Code => None,
+            Self::Quote => Some(String::from("Quote")),
+            Self::IntenseQuote => Some(String::from("Intense Quote")),
+            Self::ListParagraph => Some(String::from("List Paragraph")),
+            Self::Hyperlink => Some(String::from("Hyperlink")),
+            Self::FootnoteText => Some(String::from("footnote text")),
+            Self::FootnoteReference => Some(String::from("footnote reference")),
+        }
+    }
+
+    fn style_kind(self) -> StyleKind {
+        match self {
+            Self::Hyperlink | Self::Code | Self::FootnoteReference => StyleKind::Character,
+            _ => StyleKind::Paragraph,
+        }
+    }
+}
+
+/// Template styles (by id or by name) to render markdown elements with,
+/// instead of Word's built-in styles.
+#[derive(Debug, Clone, Default)]
+pub struct MarkdownStyles {
+    styles: BTreeMap<MarkdownElement, String>,
+}
+
+impl MarkdownStyles {
+    /// Render the element with the style with this id or name.
+    pub fn set(&mut self, element: MarkdownElement, style: &str) {
+        self.styles.insert(element, String::from(style));
+    }
+
+    /// Id of the style of an element: the configured style if the template
+    /// has it, otherwise the built-in one if the template has that. `None`
+    /// means the element gets direct formatting instead.
+    pub(crate) fn style_id<'s>(
+        &'s self,
+        element: MarkdownElement,
+        style_sheet: &'s StyleSheet,
+    ) -> Option<&'s str> {
+        let builtin = element.builtin_style_name();
+        self.styles
+            .get(&element)
+            .into_iter()
+            .chain(builtin.as_ref())
+            .find_map(|style| style_sheet.resolve(style, element.style_kind()))
+    }
+}
+
+/// Marks the start and end of the index of a math span in the markdown
+/// given to the parser (characters of the private use area).
+const MATH_MARKER_START: char = '\u{E000}';
+const MATH_MARKER_END: char = '\u{E001}';
+
+/// Relationship type of external hyperlinks.
+const REL_TYPE_HYPERLINK: &str =
+    "http://schemas.openxmlformats.org/officeDocument/2006/relationships/hyperlink";
+
+/// Inline formatting of the text currently being rendered. Formats nest, so
+/// they are kept on a stack while walking through the markdown events.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum InlineFormat {
+    Emphasis,
+    Strong,
+    Strikethrough,
+    Hyperlink,
+    /// Text of a heading of the given level without a heading style.
+    Heading(u32),
+}
+
+/// A table being read. Cells only keep their text; inline formatting is lost.
+#[derive(Debug, Default)]
+struct MarkdownTable {
+    alignments: Vec<Alignment>,
+    headers: Vec<String>,
+    rows: Vec<Vec<String>>,
+    row: Vec<String>,
+    cell: Option<String>,
+}
+
+impl MarkdownTable {
+    /// The table with one text column per header (rows with missing cells are
+    /// padded, extra cells dropped, as GFM does), and options aligning the
+    /// columns like the `:---:` markers do.
+    fn into_table(self, base_options: &TableOptions) -> (TableData, TableOptions) {
+        let columns = (0..self.headers.len())
+            .map(|col_i| {
+                self.rows
+                    .iter()
+                    .map(|row| {
+                        row.get(col_i)
+                            .filter(|text| !text.is_empty())
+                            .map(|text| Value::Text(text.clone()))
+                    })
+                    .collect()
+            })
+            .collect();
+
+        // Only the layout of the template's tables applies; column selection,
+        // filters, etc. are meant for the data tables.
+        let mut options = TableOptions {
+            autofit: base_options.autofit,
+            format: base_options.format.clone(),
+            ..TableOptions::default()
+        };
+        for (col_i, alignment) in self.alignments.iter().enumerate() {
+            let column_alignment = match alignment {
+                Alignment::None => continue,
+                Alignment::Left => ColumnAlignment::Left,
+                Alignment::Center => ColumnAlignment::Center,
+                Alignment::Right => ColumnAlignment::Right,
+            };
+            options.column_alignments.insert(col_i, column_alignment);
+        }
+
+        let table = TableData {
+            headers: self.headers,
+            columns,
+        };
+        (table, options)
+    }
+}
+
+/// A `$...$` (inline) or `$$...$$` (display) span of LaTeX math, which
+/// CommonMark does not know about. The spans are taken out of the markdown
+/// before parsing, so that the parser does not treat `_` or `*` in them as
+/// emphasis.
+#[derive(Debug)]
+struct MathSpan {
+    latex: String,
+    display: bool,
+    /// The span as written, for places where math is not rendered (code,
+    /// table cells, alt text).
+    source: String,
+}
+
+/// A level of the (nested) markdown lists the renderer is in.
+#[derive(Debug, Clone, Copy)]
+struct ListLevel {
+    num_id: usize,
+}
+
+/// A footnote definition being read. Its paragraphs go to the footnotes part
+/// instead of the document, so the state of the document is put aside.
+#[derive(Debug)]
+struct FootnoteDefinition {
+    label: String,
+    /// The first paragraph of the footnote still needs the reference mark.
+    mark_pending: bool,
+    outer_result: Vec<Token>,
+    outer_lists: Vec<ListLevel>,
+    outer_quote_depth: usize,
+}
+
+/// State while walking through the markdown events.
+struct MarkdownRenderer<'a> {
+    settings: RenderSettings<'a>,
+    parts: &'a mut DocumentParts,
+    result: Vec<Token>,
+    inline_formats: Vec<InlineFormat>,
+    lists: Vec<ListLevel>,
+    quote_depth: usize,
+    paragraph_open: bool,
+    /// The paragraph of a list item was opened, but nothing was put in it
+    /// yet. The first paragraph of a (loose) list item goes there.
+    item_paragraph_pending: bool,
+    /// Source and alt text of the image being read; the alt text comes as
+    /// text events between the start and end of the image.
+    image: Option<(String, String)>,
+    table: Option<MarkdownTable>,
+    /// Language and text of the code block being read.
+    code_block: Option<(Option<String>, String)>,
+    /// Whether each open link is a `w:hyperlink`. Links to other documents
+    /// in footnotes are plain text, the relationships they need belong to
+    /// the document part.
+    hyperlinks: Vec<bool>,
+    /// Footnote ids of the referenced labels, and the paragraphs of the
+    /// defined ones.
+    footnote_ids: BTreeMap<String, usize>,
+    footnote_bodies: BTreeMap<String, Vec<Token>>,
+    footnote: Option<FootnoteDefinition>,
+    math_spans: Vec<MathSpan>,
+}
 
+/// Markdown extensions (on top of CommonMark) that are rendered.
+fn markdown_options() -> Options {
     let mut markdown_options = Options::empty();
     markdown_options.insert(Options::ENABLE_STRIKETHROUGH);
+    markdown_options.insert(Options::ENABLE_TABLES);
+    markdown_options.insert(Options::ENABLE_FOOTNOTES);
+    markdown_options
+}
 
-    let parser = Parser::new_ext(md_text, markdown_options);
-    let mut is_inline = false;
+/// The runs of markdown that is a single paragraph (footnote definitions
+/// aside), to go into a paragraph of the template. `None` if the markdown
+/// has other blocks, which need paragraphs of their own.
+pub(crate) fn markdown_to_inline_tokens(
+    md_text: &str,
+    settings: &RenderSettings,
+    parts: &mut DocumentParts,
+) -> Option<Vec<Token>> {
+    if !is_single_paragraph(md_text) {
+        return None;
+    }
 
-    for parser_event in parser {
+    let tokens = markdown_to_tokens(md_text, settings, parts);
+    let paragraph_end = matching_end_index(&tokens, 0, "p")?;
+    let runs_start = match tokens.get(1) {
+        Some(token) if is_wp_start_token(token, "pPr") => {
+            matching_end_index(&tokens, 1, "pPr")? + 1
+        }
+        _ => 1,
+    };
+    Some(tokens[runs_start..paragraph_end].to_vec())
+}
+
+fn is_single_paragraph(md_text: &str) -> bool {
+    let mut depth: usize = 0;
+    let mut paragraphs: usize = 0;
+
+    for parser_event in Parser::new_ext(md_text, markdown_options()) {
         match parser_event {
             pulldown_cmark::Event::Start(tag) => {
-                result.extend(cmark_tag_to_wp_tag_start(&tag, &mut is_inline));
+                if depth == 0 {
+                    match tag {
+                        pulldown_cmark::Tag::Paragraph => paragraphs += 1,
+                        pulldown_cmark::Tag::FootnoteDefinition(_) => {}
+                        _ => return false,
+                    }
+                }
+                depth += 1;
             }
-            pulldown_cmark::Event::Text(x) => {
-                if is_inline {
-                    let text_with_spaces = format!(" {} ", x);
-                    result.extend(char_text_tokens(&text_with_spaces, false));
+            pulldown_cmark::Event::End(_) => depth = depth.saturating_sub(1),
+            _ if depth == 0 => return false,
+            _ => {}
+        }
+    }
+
+    paragraphs == 1
+}
+
+pub(crate) fn markdown_to_tokens(
+    md_text: &str,
+    settings: &RenderSettings,
+    parts: &mut DocumentParts,
+) -> Vec<Token> {
+    let (md_text, math_spans) = extract_math(md_text);
+    let parser = Parser::new_ext(&md_text, markdown_options());
+    let mut renderer = MarkdownRenderer {
+        settings: *settings,
+        parts,
+        result: Vec::new(),
+        inline_formats: Vec::new(),
+        lists: Vec::new(),
+        quote_depth: 0,
+        paragraph_open: false,
+        item_paragraph_pending: false,
+        image: None,
+        table: None,
+        code_block: None,
+        hyperlinks: Vec::new(),
+        footnote_ids: BTreeMap::new(),
+        footnote_bodies: BTreeMap::new(),
+        footnote: None,
+        math_spans,
+    };
+
+    for parser_event in parser {
+        match parser_event {
+            pulldown_cmark::Event::Start(tag) => match inline_format(&tag) {
+                Some(format) => renderer.inline_formats.push(format),
+                None => renderer.start_tag(&tag),
+            },
+            pulldown_cmark::Event::Text(x) => renderer.text(&x, false),
+            pulldown_cmark::Event::Code(x) => renderer.text(&x, true),
+            // A soft break is a space, unlike in Word, where it is a line break.
+            pulldown_cmark::Event::SoftBreak => renderer.text(" ", false),
+            pulldown_cmark::Event::HardBreak => renderer.line_break(),
+            pulldown_cmark::Event::Rule => renderer.horizontal_rule(),
+            pulldown_cmark::Event::FootnoteReference(label) => renderer.footnote_reference(&label),
+            pulldown_cmark::Event::End(tag) => match inline_format(&tag) {
+                Some(_) => {
+                    renderer.inline_formats.pop();
+                }
+                None => renderer.end_tag(&tag),
+            },
+            _ => {}
+        }
+    }
+
+    renderer.add_footnotes();
+    renderer.result
+}
+
+impl<'a> MarkdownRenderer<'a> {
+    fn start_tag(&mut self, cmark_tag: &pulldown_cmark::Tag) {
+        match cmark_tag {
+            pulldown_cmark::Tag::Heading(level) => {
+                self.close_paragraph();
+                let level = (*level).min(6);
+                match self.style(MarkdownElement::Heading(level)) {
+                    Some(style) => self.result.extend(heading_prequel_tokens(style)),
+                    None => {
+                        self.result.extend(heading_fallback_prequel_tokens(level));
+                        self.inline_formats.push(InlineFormat::Heading(level));
+                    }
+                }
+                self.paragraph_open = true;
+            }
+            pulldown_cmark::Tag::Paragraph => {
+                if self.item_paragraph_pending {
+                    self.item_paragraph_pending = false;
                 } else {
-                    result.push(run_start_token());
-                    result.extend(char_text_tokens(&x, true));
-                    result.push(run_end_token());
+                    self.close_paragraph();
+                    self.open_paragraph();
+                }
+            }
+            pulldown_cmark::Tag::List(start) => {
+                self.close_paragraph();
+                let level = self.lists.len().min(MAX_LIST_LEVELS - 1);
+                let num_id = match start {
+                    Some(start) => self.parts.numbering.ordered_num_id(level, *start),
+                    None => self.parts.numbering.bullet_num_id(),
+                };
+                self.lists.push(ListLevel { num_id });
+            }
+            pulldown_cmark::Tag::Item => {
+                self.close_paragraph();
+                if let Some(list) = self.lists.last() {
+                    let level = (self.lists.len() - 1).min(MAX_LIST_LEVELS - 1);
+                    let style = self.style(MarkdownElement::ListParagraph);
+                    self.result
+                        .extend(list_paragraph_prequel_tokens(level, list.num_id, style));
+                    self.paragraph_open = true;
+                    self.item_paragraph_pending = true;
+                }
+            }
+            pulldown_cmark::Tag::Link(link_type, destination, title) => {
+                // Table cells are plain text: a link there keeps its text.
+                if self.table.is_some() {
+                    self.hyperlinks.push(false);
+                    self.inline_formats.push(InlineFormat::Hyperlink);
+                    return;
+                }
+                self.ensure_paragraph();
+                let hyperlink = if let Some(anchor) = destination.strip_prefix('#') {
+                    hyperlink_start_token(None, Some(anchor), title)
+                } else if self.footnote.is_some() {
+                    self.hyperlinks.push(false);
+                    self.inline_formats.push(InlineFormat::Hyperlink);
+                    return;
+                } else {
+                    let target = match link_type {
+                        LinkType::Email => format!("mailto:{}", destination),
+                        _ => destination.to_string(),
+                    };
+                    let relationship_id =
+                        self.parts
+                            .add_relationship(REL_TYPE_HYPERLINK, &target, true);
+                    hyperlink_start_token(Some(&relationship_id), None, title)
+                };
+                self.result.push(hyperlink);
+                self.hyperlinks.push(true);
+                self.inline_formats.push(InlineFormat::Hyperlink);
+            }
+            pulldown_cmark::Tag::Image(_, source, _) => {
+                // In a table cell the alt text goes into the cell as text.
+                if self.table.is_some() {
+                    return;
                 }
+                self.ensure_paragraph();
+                self.image = Some((source.to_string(), String::new()));
             }
-            pulldown_cmark::Event::End(tag) => {
-                result.extend(cmark_tag_to_wp_tag_end(&tag, &mut is_inline));
+            pulldown_cmark::Tag::Table(alignments) => {
+                self.close_paragraph();
+                self.table = Some(MarkdownTable {
+                    alignments: alignments.clone(),
+                    ..MarkdownTable::default()
+                });
+            }
+            pulldown_cmark::Tag::TableCell => {
+                if let Some(table) = self.table.as_mut() {
+                    table.cell = Some(String::new());
+                }
+            }
+            pulldown_cmark::Tag::BlockQuote => {
+                self.close_paragraph();
+                self.quote_depth += 1;
+            }
+            pulldown_cmark::Tag::FootnoteDefinition(label) => {
+                self.close_paragraph();
+                self.footnote = Some(FootnoteDefinition {
+                    label: footnote_label(label),
+                    mark_pending: true,
+                    outer_result: std::mem::take(&mut self.result),
+                    outer_lists: std::mem::take(&mut self.lists),
+                    outer_quote_depth: std::mem::replace(&mut self.quote_depth, 0),
+                });
+            }
+            pulldown_cmark::Tag::CodeBlock(kind) => {
+                self.close_paragraph();
+                let language = match kind {
+                    // The info string may carry more than the language, e.g.
+                    // `python title="fit.py"`.
+                    CodeBlockKind::Fenced(info) => info
+                        .split_whitespace()
+                        .next()
+                        .map(|language| String::from(language.trim_start_matches('{'))),
+                    CodeBlockKind::Indented => None,
+                };
+                self.code_block = Some((language, String::new()));
             }
             _ => {}
         }
     }
 
+    fn end_tag(&mut self, cmark_tag: &pulldown_cmark::Tag) {
+        match cmark_tag {
+            pulldown_cmark::Tag::Heading(_) => {
+                if let Some(InlineFormat::Heading(_)) = self.inline_formats.last() {
+                    self.inline_formats.pop();
+                }
+                self.result.extend(heading_sequel_tokens());
+                self.paragraph_open = false;
+            }
+            pulldown_cmark::Tag::Paragraph | pulldown_cmark::Tag::Item => self.close_paragraph(),
+            pulldown_cmark::Tag::List(_) => {
+                self.close_paragraph();
+                self.lists.pop();
+            }
+            pulldown_cmark::Tag::Link(..) => {
+                self.inline_formats.pop();
+                if self.hyperlinks.pop() == Some(true) {
+                    self.result.push(wp_end_token("hyperlink"));
+                }
+            }
+            pulldown_cmark::Tag::Image(..) => {
+                if let Some((source, alt_text)) = self.image.take() {
+                    self.image(&source, &alt_text);
+                }
+            }
+            pulldown_cmark::Tag::BlockQuote => {
+                self.close_paragraph();
+                self.quote_depth = self.quote_depth.saturating_sub(1);
+            }
+            pulldown_cmark::Tag::FootnoteDefinition(_) => {
+                self.close_paragraph();
+                if let Some(footnote) = self.footnote.take() {
+                    let mut body = std::mem::replace(&mut self.result, footnote.outer_result);
+                    self.lists = footnote.outer_lists;
+                    self.quote_depth = footnote.outer_quote_depth;
+                    // E.g. a footnote that is only a code block.
+                    if footnote.mark_pending {
+                        let mut mark_paragraph = self.footnote_paragraph_prequel_tokens();
+                        mark_paragraph.extend(paragraph_sequel_tokens());
+                        body.splice(0..0, mark_paragraph);
+                    }
+                    self.footnote_bodies.insert(footnote.label, body);
+                }
+            }
+            pulldown_cmark::Tag::CodeBlock(_) => {
+                if let Some((language, code)) = self.code_block.take() {
+                    self.result.extend(code_block_tokens(
+                        &code,
+                        language.as_deref(),
+                        &self.settings.code_blocks,
+                    ));
+                }
+            }
+            pulldown_cmark::Tag::TableCell => {
+                if let Some(table) = self.table.as_mut() {
+                    let cell = table.cell.take().unwrap_or_default();
+                    table.row.push(String::from(cell.trim()));
+                }
+            }
+            pulldown_cmark::Tag::TableHead => {
+                if let Some(table) = self.table.as_mut() {
+                    table.headers = std::mem::take(&mut table.row);
+                }
+            }
+            pulldown_cmark::Tag::TableRow => {
+                if let Some(table) = self.table.as_mut() {
+                    let row = std::mem::take(&mut table.row);
+                    table.rows.push(row);
+                }
+            }
+            pulldown_cmark::Tag::Table(_) => {
+                if let Some(table) = self.table.take() {
+                    let (table_data, options) = table.into_table(self.settings.table_options);
+                    self.result.extend(datakit_table_to_tokens(
+                        &table_data,
+                        &TableStyling::default(),
+                        self.settings.dimensions,
+                        &options,
+                    ));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn text(&mut self, text: &str, is_code: bool) {
+        if self.image.is_some() || self.code_block.is_some() || self.table.is_some() || is_code {
+            let text = self.math_source(text);
+            if let Some((_, alt_text)) = self.image.as_mut() {
+                alt_text.push_str(&text);
+                return;
+            }
+            if let Some((_, code)) = self.code_block.as_mut() {
+                code.push_str(&text);
+                return;
+            }
+            if let Some(cell) = self.table.as_mut().and_then(|table| table.cell.as_mut()) {
+                cell.push_str(&text);
+                return;
+            }
+            self.ensure_paragraph();
+            self.result
+                .extend(self.formatted_run_tokens(&text, &self.inline_formats, is_code));
+            return;
+        }
+
+        self.ensure_paragraph();
+        for (text, math_span) in split_math_markers(text) {
+            if !text.is_empty() {
+                self.result
+                    .extend(self.formatted_run_tokens(text, &self.inline_formats, false));
+            }
+            match math_span.and_then(|index| self.math_spans.get(index)) {
+                Some(span) if span.display => self.result.extend(display_math_tokens(&span.latex)),
+                Some(span) => self.result.extend(math_tokens(&span.latex)),
+                None => {}
+            }
+        }
+    }
+
+    /// The text with math spans put back as they were written.
+    fn math_source(&self, text: &str) -> String {
+        let mut result = String::new();
+        for (text, math_span) in split_math_markers(text) {
+            result.push_str(text);
+            if let Some(span) = math_span.and_then(|index| self.math_spans.get(index)) {
+                result.push_str(&span.source);
+            }
+        }
+        result
+    }
+
+    /// Embed a PNG or JPEG image from a file or a `data:` URI. Images that
+    /// cannot be read (or are remote), and images in footnotes, are replaced
+    /// by their alt text.
+    fn image(&mut self, source: &str, alt_text: &str) {
+        let added_image = match self.footnote {
+            Some(_) => None,
+            None => self.embedded_image(source),
+        };
+        match added_image {
+            Some(image) => self.result.extend(image_run_tokens(
+                &image.relationship_id,
+                image.width,
+                image.height,
+                image.serial_number,
+                Some(alt_text),
+            )),
+            None => {
+                self.result
+                    .extend(self.formatted_run_tokens(alt_text, &self.inline_formats, false))
+            }
+        }
+    }
+
+    /// Add an image to the package. An image that cannot be read or is not a
+    /// PNG or JPEG image gives a warning, as the image silently turning into
+    /// its alt text is easy to miss.
+    fn embedded_image(&mut self, source: &str) -> Option<AddedImage> {
+        let parts = &mut self.parts;
+        let added_image =
+            image_payload(source, self.settings.files).and_then(|payload| match payload {
+                Some(payload) => parts
+                    .add_image(payload)
+                    .map(Some)
+                    .ok_or_else(|| String::from("not a PNG or JPEG image")),
+                None => Ok(None),
+            });
+        match added_image {
+            Ok(image) => image,
+            Err(error) => {
+                let name = if source.starts_with("data:") {
+                    "data: URI"
+                } else {
+                    source
+                };
+                parts.warnings.push(format!(
+                    "Image {} replaced by its alt text: {}",
+                    name, error
+                ));
+                None
+            }
+        }
+    }
+
+    /// A raised reference mark to the footnote with the given label. The
+    /// footnote gets its id at the first reference, so that footnotes are
+    /// numbered in the order they are referenced. References in table cells
+    /// and in footnotes themselves are kept as text.
+    fn footnote_reference(&mut self, label: &str) {
+        if self.footnote.is_some() || self.table.is_some() {
+            self.text(&format!("[{}]", label), false);
+            return;
+        }
+
+        let parts = &mut self.parts;
+        let id = *self
+            .footnote_ids
+            .entry(footnote_label(label))
+            .or_insert_with(|| parts.footnotes.reserve_id());
+
+        self.ensure_paragraph();
+        self.result.extend(footnote_mark_run_tokens(
+            "footnoteReference",
+            Some(id),
+            self.style(MarkdownElement::FootnoteReference),
+        ));
+    }
+
+    /// Add the referenced footnotes to the footnotes part. Definitions that
+    /// are not referenced are dropped; references without a definition get
+    /// a footnote with the label, so that the document stays valid.
+    fn add_footnotes(&mut self) {
+        let mut footnotes: Vec<(usize, String)> = self
+            .footnote_ids
+            .iter()
+            .map(|(label, id)| (*id, label.clone()))
+            .collect();
+        footnotes.sort();
+
+        for (id, label) in footnotes {
+            let body = match self.footnote_bodies.remove(&label) {
+                Some(body) => body,
+                None => {
+                    let mut body = self.footnote_paragraph_prequel_tokens();
+                    body.extend(self.formatted_run_tokens(&label, &[], false));
+                    body.extend(paragraph_sequel_tokens());
+                    body
+                }
+            };
+            self.parts.footnotes.add(id, body);
+        }
+    }
+
+    /// Start of a footnote paragraph with the footnote text style, and with
+    /// the reference mark if it is the first paragraph of the footnote.
+    fn footnote_paragraph_prequel_tokens(&mut self) -> Vec<Token> {
+        let mut result: Vec<Token> = Vec::new();
+
+        result.push(wp_start_token("p", &[]));
+        result.push(wp_start_token("pPr", &[]));
+        if let Some(style) = self.style(MarkdownElement::FootnoteText) {
+            result.extend(wp_empty_tokens("pStyle", &[("val", style)]));
+        } else {
+            result.extend(wp_empty_tokens(
+                "spacing",
+                &[("after", "0"), ("line", "240"), ("lineRule", "auto")],
+            ));
+        }
+        result.push(wp_end_token("pPr"));
+
+        let mark_pending = self
+            .footnote
+            .as_ref()
+            .is_none_or(|footnote| footnote.mark_pending);
+        if mark_pending {
+            result.extend(footnote_mark_run_tokens(
+                "footnoteRef",
+                None,
+                self.style(MarkdownElement::FootnoteReference),
+            ));
+            result.extend(self.formatted_run_tokens(" ", &[], false));
+            if let Some(footnote) = self.footnote.as_mut() {
+                footnote.mark_pending = false;
+            }
+        }
+
+        result
+    }
+
+    /// Text of a list item after a nested list has no paragraph of its own.
+    fn ensure_paragraph(&mut self) {
+        if !self.paragraph_open {
+            self.open_paragraph();
+        }
+        self.item_paragraph_pending = false;
+    }
+
+    fn line_break(&mut self) {
+        self.ensure_paragraph();
+        self.result.push(run_start_token());
+        self.result.extend(wp_empty_tokens("br", &[]));
+        self.result.push(run_end_token());
+    }
+
+    /// An empty paragraph with a bottom border.
+    fn horizontal_rule(&mut self) {
+        self.close_paragraph();
+        self.result.push(wp_start_token("p", &[]));
+        self.result.push(wp_start_token("pPr", &[]));
+        self.result.push(wp_start_token("pBdr", &[]));
+        self.result.extend(wp_empty_tokens(
+            "bottom",
+            &[
+                ("val", "single"),
+                ("sz", "6"),
+                ("space", "1"),
+                ("color", "auto"),
+            ],
+        ));
+        self.result.push(wp_end_token("pBdr"));
+        self.result.push(wp_end_token("pPr"));
+        self.result.push(wp_end_token("p"));
+    }
+
+    /// Open a plain paragraph, a quote paragraph or, inside a list, an
+    /// unnumbered paragraph indented like the list item it continues.
+    fn open_paragraph(&mut self) {
+        if self.lists.is_empty() && self.quote_depth > 0 {
+            self.result.extend(self.quote_paragraph_prequel_tokens());
+        } else if self.lists.is_empty() && self.footnote.is_some() {
+            let prequel = self.footnote_paragraph_prequel_tokens();
+            self.result.extend(prequel);
+        } else if self.lists.is_empty() {
+            match self.style(MarkdownElement::Paragraph) {
+                Some(style) => {
+                    self.result.push(wp_start_token("p", &[]));
+                    self.result.push(wp_start_token("pPr", &[]));
+                    self.result
+                        .extend(wp_empty_tokens("pStyle", &[("val", style)]));
+                    self.result.push(wp_end_token("pPr"));
+                }
+                None => self.result.extend(paragraph_prequel_tokens()),
+            }
+        } else {
+            let indent = format!("{}", LIST_INDENT * self.lists.len().min(MAX_LIST_LEVELS));
+            self.result.push(wp_start_token("p", &[]));
+            self.result.push(wp_start_token("pPr", &[]));
+            if let Some(style) = self.style(MarkdownElement::ListParagraph) {
+                self.result
+                    .extend(wp_empty_tokens("pStyle", &[("val", style)]));
+            }
+            self.result
+                .extend(wp_empty_tokens("ind", &[("left", &indent)]));
+            self.result.push(wp_end_token("pPr"));
+        }
+        self.paragraph_open = true;
+    }
+
+    /// Paragraphs of block quotes get the quote style (the intense quote
+    /// style in nested quotes) if the template has it, and otherwise an
+    /// indentation and a border on the left.
+    fn quote_paragraph_prequel_tokens(&self) -> Vec<Token> {
+        let mut result: Vec<Token> = Vec::new();
+
+        let preferred_elements = if self.quote_depth > 1 {
+            [MarkdownElement::IntenseQuote, MarkdownElement::Quote]
+        } else {
+            [MarkdownElement::Quote, MarkdownElement::IntenseQuote]
+        };
+        let style = preferred_elements
+            .iter()
+            .find_map(|element| self.style(*element));
+
+        result.push(wp_start_token("p", &[]));
+        result.push(wp_start_token("pPr", &[]));
+        match style {
+            Some(style) => result.extend(wp_empty_tokens("pStyle", &[("val", style)])),
+            None => {
+                let indent = format!("{}", LIST_INDENT * self.quote_depth);
+                result.push(wp_start_token("pBdr", &[]));
+                result.extend(wp_empty_tokens(
+                    "left",
+                    &[
+                        ("val", "single"),
+                        ("sz", "18"),
+                        ("space", "8"),
+                        ("color", QUOTE_BORDER_COLOR),
+                    ],
+                ));
+                result.push(wp_end_token("pBdr"));
+                result.extend(wp_empty_tokens("ind", &[("left", &indent)]));
+            }
+        }
+        result.push(wp_end_token("pPr"));
+
+        result
+    }
+
+    fn close_paragraph(&mut self) {
+        if self.paragraph_open {
+            self.result.extend(paragraph_sequel_tokens());
+        }
+        self.paragraph_open = false;
+        self.item_paragraph_pending = false;
+    }
+
+    /// Id of the template style of an element, see [`MarkdownStyles::style_id`].
+    fn style(&self, element: MarkdownElement) -> Option<&'a str> {
+        self.settings
+            .markdown_styles
+            .style_id(element, self.settings.styles)
+    }
+
+    fn formatted_run_tokens(
+        &self,
+        text: &str,
+        formats: &[InlineFormat],
+        is_code: bool,
+    ) -> Vec<Token> {
+        formatted_run_tokens(
+            text,
+            formats,
+            is_code,
+            self.style(MarkdownElement::Hyperlink),
+            self.style(MarkdownElement::Code),
+        )
+    }
+}
+
+/// Start of a heading paragraph when the template has no style for it: the
+/// outline level keeps it in the navigation pane and the table of contents,
+/// and [`InlineFormat::Heading`] makes its text bold and larger.
+fn heading_fallback_prequel_tokens(level: u32) -> Vec<Token> {
+    let mut result: Vec<Token> = Vec::new();
+
+    result.push(wp_start_token("p", &[]));
+    result.push(wp_start_token("pPr", &[]));
+    result.extend(wp_empty_tokens("keepNext", &[]));
+    result.extend(wp_empty_tokens(
+        "spacing",
+        &[("before", "240"), ("after", "60")],
+    ));
+    result.extend(wp_empty_tokens(
+        "outlineLvl",
+        &[("val", &format!("{}", level.saturating_sub(1)))],
+    ));
+    result.push(wp_end_token("pPr"));
+
     result
 }
 
-// Goal here is to produce a vector of tokens for each cmark_tag.
-fn cmark_tag_to_wp_tag_start(cmark_tag: &pulldown_cmark::Tag, is_inline: &mut bool) -> Vec<Token> {
-    match cmark_tag {
-        pulldown_cmark::Tag::Heading(level) => {
-            let heading_style = format!("Heading{}", level + 1);
-            heading_prequel_tokens(&heading_style)
-        }
-        pulldown_cmark::Tag::Paragraph => paragraph_prequel_tokens(),
-        pulldown_cmark::Tag::Emphasis => {
-            *is_inline = true;
-            let mut emphasis_start: Vec<Token> = Vec::new();
-            emphasis_start.push(run_start_token());
-            emphasis_start.push(Token {
-                token_type: TokenType::Normal,
-                // xml_reader_event: start_tag_event("rPr", None),
-                xml_reader_event: start_tag_event(
-                    &Some(String::from("w")),
-                    &Some(String::from(NS_WP_ML)),
-                    &String::from("rPr"),
-                    None,
-                ),
-                token_text: None,
-            });
-            emphasis_start.push(Token {
-                token_type: TokenType::Normal,
-                // xml_reader_event: start_tag_event("i", None),
-                xml_reader_event: start_tag_event(
-                    &Some(String::from("w")),
-                    &Some(String::from(NS_WP_ML)),
-                    &String::from("i"),
-                    None,
-                ),
-                token_text: None,
-            });
-            emphasis_start.push(Token {
-                token_type: TokenType::Normal,
-                // xml_reader_event: end_tag_event("i"),
-                xml_reader_event: end_tag_event(
-                    &Some(String::from("w")),
-                    &Some(String::from(NS_WP_ML)),
-                    &String::from("i"),
-                ),
-                token_text: None,
-            });
-            emphasis_start.push(Token {
-                token_type: TokenType::Normal,
-                // xml_reader_event: start_tag_event("iCs", None),
-                xml_reader_event: start_tag_event(
-                    &Some(String::from("w")),
-                    &Some(String::from(NS_WP_ML)),
-                    &String::from("iCs"),
-                    None,
-                ),
-                token_text: None,
-            });
-            emphasis_start.push(Token {
-                token_type: TokenType::Normal,
-                // xml_reader_event: end_tag_event("iCs"),
-                xml_reader_event: end_tag_event(
-                    &Some(String::from("w")),
-                    &Some(String::from(NS_WP_ML)),
-                    &String::from("iCs"),
-                ),
-                token_text: None,
-            });
-            emphasis_start.push(Token {
-                token_type: TokenType::Normal,
-                // xml_reader_event: end_tag_event("rPr"),
-                xml_reader_event: end_tag_event(
-                    &Some(String::from("w")),
-                    &Some(String::from(NS_WP_ML)),
-                    &String::from("rPr"),
-                ),
-                token_text: None,
-            });
-            emphasis_start
+/// Start of a numbered or bulleted paragraph at the given list level.
+fn list_paragraph_prequel_tokens(level: usize, num_id: usize, style: Option<&str>) -> Vec<Token> {
+    let mut result: Vec<Token> = Vec::new();
+
+    result.push(wp_start_token("p", &[]));
+    result.push(wp_start_token("pPr", &[]));
+    if let Some(style) = style {
+        result.extend(wp_empty_tokens("pStyle", &[("val", style)]));
+    }
+    result.push(wp_start_token("numPr", &[]));
+    result.extend(wp_empty_tokens("ilvl", &[("val", &format!("{}", level))]));
+    result.extend(wp_empty_tokens("numId", &[("val", &format!("{}", num_id))]));
+    result.push(wp_end_token("numPr"));
+    result.push(wp_end_token("pPr"));
+
+    result
+}
+
+/// Labels of footnote references and definitions match case-insensitively.
+fn footnote_label(label: &str) -> String {
+    label.trim().to_lowercase()
+}
+
+/// A raised run with a footnote mark: `w:footnoteReference` (with the id of
+/// the footnote) in the text, or `w:footnoteRef` in the footnote itself.
+fn footnote_mark_run_tokens(mark: &str, id: Option<usize>, style: Option<&str>) -> Vec<Token> {
+    let mut result: Vec<Token> = Vec::new();
+
+    result.push(run_start_token());
+    result.push(wp_start_token("rPr", &[]));
+    if let Some(style) = style {
+        result.extend(wp_empty_tokens("rStyle", &[("val", style)]));
+    }
+    result.extend(wp_empty_tokens("vertAlign", &[("val", "superscript")]));
+    result.push(wp_end_token("rPr"));
+    match id {
+        Some(id) => result.extend(wp_empty_tokens(mark, &[("id", &format!("{}", id))])),
+        None => result.extend(wp_empty_tokens(mark, &[])),
+    }
+    result.push(run_end_token());
+
+    result
+}
+
+/// Start of a hyperlink to an external target (by relationship id) or to a
+/// bookmark in the document.
+fn hyperlink_start_token(
+    relationship_id: Option<&str>,
+    anchor: Option<&str>,
+    tooltip: &str,
+) -> Token {
+    let w_prefix = Some(String::from("w"));
+    let w_ns = Some(String::from(NS_WP_ML));
+    let mut attributes = Vec::new();
+
+    if let Some(relationship_id) = relationship_id {
+        attributes.push(owned_attribute(
+            &Some(String::from("r")),
+            &Some(String::from(NS_REL)),
+            &String::from("id"),
+            &String::from(relationship_id),
+        ));
+    }
+    if let Some(anchor) = anchor {
+        attributes.push(owned_attribute(
+            &w_prefix,
+            &w_ns,
+            &String::from("anchor"),
+            &String::from(anchor),
+        ));
+    }
+    if !tooltip.is_empty() {
+        attributes.push(owned_attribute(
+            &w_prefix,
+            &w_ns,
+            &String::from("tooltip"),
+            &String::from(tooltip),
+        ));
+    }
+    attributes.push(owned_attribute(
+        &w_prefix,
+        &w_ns,
+        &String::from("history"),
+        &String::from("1"),
+    ));
+
+    Token {
+        token_type: TokenType::Normal,
+        xml_reader_event: start_tag_event(
+            &w_prefix,
+            &w_ns,
+            &String::from("hyperlink"),
+            Some(&attributes),
+        ),
+        token_text: None,
+    }
+}
+
+/// Contents of an image given as a `data:<type>;base64,` URI or a local path
+/// (see [`FileAccess`]). Other URIs, such as remote images and notebook
+/// attachments, give `None`, and images that cannot be read an error.
+fn image_payload(source: &str, files: &FileAccess) -> Result<Option<Vec<u8>>, String> {
+    if let Some(data_uri) = source.strip_prefix("data:") {
+        let (_, encoded) = data_uri
+            .split_once(";base64,")
+            .ok_or_else(|| String::from("not a base64 data: URI"))?;
+        base64::decode(encoded.trim())
+            .map(Some)
+            .map_err(|error| error.to_string())
+    } else if has_uri_scheme(source) {
+        Ok(None)
+    } else {
+        files
+            .read(source)
+            .map(Some)
+            .map_err(|error| error.to_string())
+    }
+}
+
+/// Whether the source is a URI (`https://...`, `attachment:...`) rather than
+/// a path. A single letter before the colon is a Windows drive.
+fn has_uri_scheme(source: &str) -> bool {
+    match source.find(':') {
+        Some(i) => {
+            i > 1
+                && source[..i]
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.')
         }
-        _ => vec![],
+        None => false,
     }
 }
 
-fn cmark_tag_to_wp_tag_end(cmark_tag: &pulldown_cmark::Tag, is_inline: &mut bool) -> Vec<Token> {
+/// The markdown with its math spans replaced by markers, and the spans. As in
+/// pandoc, the opening `$` of inline math must not be followed by a space,
+/// and the closing one must not be preceded by a space or followed by a
+/// digit, so that prices like $5 and $10 stay text. Math does not go past a
+/// blank line, and code (fenced blocks and backtick spans) and escaped
+/// dollars (`\$`) are left alone.
+fn extract_math(md_text: &str) -> (String, Vec<MathSpan>) {
+    let chars: Vec<char> = md_text.chars().collect();
+    let mut result = String::new();
+    let mut spans: Vec<MathSpan> = Vec::new();
+    let mut fence: Option<String> = None;
+    let mut i: usize = 0;
+
+    while i < chars.len() {
+        // Fenced code blocks, line by line.
+        if i == 0 || chars[i - 1] == '\n' {
+            let line_end = (i..chars.len())
+                .find(|j| chars[*j] == '\n')
+                .map_or(chars.len(), |j| j + 1);
+            let line: String = chars[i..line_end].iter().collect();
+            let marker: String = line
+                .trim_start()
+                .chars()
+                .take_while(|c| *c == '`' || *c == '~')
+                .collect();
+            let is_fence = marker.len() >= 3
+                && marker
+                    .chars()
+                    .all(|c| c == marker.chars().next().unwrap_or('`'));
+            let in_fence = match (&fence, is_fence) {
+                (Some(open), true) if marker.starts_with(open.as_str()) => {
+                    fence = None;
+                    true
+                }
+                (Some(_), _) => true,
+                (None, true) => {
+                    fence = Some(marker);
+                    true
+                }
+                (None, false) => false,
+            };
+            if in_fence {
+                result.push_str(&line);
+                i = line_end;
+                continue;
+            }
+        }
+
+        match chars[i] {
+            '\\' => {
+                result.extend(chars[i..(i + 2).min(chars.len())].iter());
+                i += 2;
+            }
+            '`' => {
+                let ticks = (i..chars.len()).take_while(|j| chars[*j] == '`').count();
+                let span_end = (i + ticks..chars.len())
+                    .find(|j| {
+                        chars[*j..].iter().take_while(|c| **c == '`').count() == ticks
+                            && chars[*j - 1] != '`'
+                    })
+                    .map_or(i + ticks, |j| j + ticks);
+                result.extend(chars[i..span_end].iter());
+                i = span_end;
+            }
+            '$' => match math_span_end(&chars, i) {
+                Some((latex, display, span_end)) => {
+                    result.push(MATH_MARKER_START);
+                    result.push_str(&spans.len().to_string());
+                    result.push(MATH_MARKER_END);
+                    spans.push(MathSpan {
+                        latex,
+                        display,
+                        source: chars[i..span_end].iter().collect(),
+                    });
+                    i = span_end;
+                }
+                None => {
+                    result.push('$');
+                    i += 1;
+                }
+            },
+            c => {
+                result.push(c);
+                i += 1;
+            }
+        }
+    }
+
+    (result, spans)
+}
+
+/// The LaTeX of the math span starting at the `$` at `start`, whether it is
+/// display math, and the index after the span.
+fn math_span_end(chars: &[char], start: usize) -> Option<(String, bool, usize)> {
+    let display = chars.get(start + 1) == Some(&'$');
+    let content_start = if display { start + 2 } else { start + 1 };
+    if !display && chars.get(content_start).is_none_or(|c| c.is_whitespace()) {
+        return None;
+    }
+
+    let mut i = content_start;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            // Code spans come first.
+            '`' if !display => return None,
+            '\n' if chars[i + 1..]
+                .iter()
+                .take_while(|c| **c != '\n')
+                .all(|c| c.is_whitespace()) =>
+            {
+                return None
+            }
+            '$' if display => {
+                if chars.get(i + 1) == Some(&'$') {
+                    let latex: String = chars[content_start..i].iter().collect();
+                    return Some((String::from(latex.trim()), true, i + 2));
+                }
+                return None;
+            }
+            '$' => {
+                if chars[i - 1].is_whitespace()
+                    || i == content_start
+                    || chars.get(i + 1).is_some_and(|c| c.is_ascii_digit())
+                {
+                    return None;
+                }
+                return Some((chars[content_start..i].iter().collect(), false, i + 1));
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+
+    None
+}
+
+/// Pieces of text with the index of the math span that follows each of them.
+fn split_math_markers(text: &str) -> Vec<(&str, Option<usize>)> {
+    let mut pieces: Vec<(&str, Option<usize>)> = Vec::new();
+    let mut rest = text;
+
+    while let Some(start) = rest.find(MATH_MARKER_START) {
+        let index_start = start + MATH_MARKER_START.len_utf8();
+        match rest[index_start..].find(MATH_MARKER_END) {
+            Some(length) => {
+                pieces.push((
+                    &rest[..start],
+                    rest[index_start..index_start + length].parse().ok(),
+                ));
+                rest = &rest[index_start + length + MATH_MARKER_END.len_utf8()..];
+            }
+            None => break,
+        }
+    }
+    pieces.push((rest, None));
+
+    pieces
+}
+
+fn inline_format(cmark_tag: &pulldown_cmark::Tag) -> Option<InlineFormat> {
     match cmark_tag {
-        pulldown_cmark::Tag::Heading(_) => heading_sequel_tokens(),
-        pulldown_cmark::Tag::Paragraph => paragraph_sequel_tokens(),
-        pulldown_cmark::Tag::Emphasis => {
-            *is_inline = false;
-            vec![run_end_token()]
+        pulldown_cmark::Tag::Emphasis => Some(InlineFormat::Emphasis),
+        pulldown_cmark::Tag::Strong => Some(InlineFormat::Strong),
+        pulldown_cmark::Tag::Strikethrough => Some(InlineFormat::Strikethrough),
+        _ => None,
+    }
+}
+
+/// A single run of text with the run properties for the given inline formats.
+/// Inline code gets the code style if there is one, and is otherwise set in
+/// a monospace font on a shaded background.
+fn formatted_run_tokens(
+    text: &str,
+    formats: &[InlineFormat],
+    is_code: bool,
+    hyperlink_style: Option<&str>,
+    code_style: Option<&str>,
+) -> Vec<Token> {
+    let mut result: Vec<Token> = Vec::new();
+
+    let heading_font_size = formats.iter().find_map(|format| match format {
+        InlineFormat::Heading(level) => Some(HEADING_FONT_SIZES[(*level as usize).clamp(1, 6) - 1]),
+        _ => None,
+    });
+    let bold = formats.contains(&InlineFormat::Strong) || heading_font_size.is_some();
+    let italic = formats.contains(&InlineFormat::Emphasis);
+    let strike = formats.contains(&InlineFormat::Strikethrough);
+    let hyperlink = formats.contains(&InlineFormat::Hyperlink);
+    // A run has a single character style; links win over code.
+    let style = match (hyperlink, is_code) {
+        (true, _) => hyperlink_style,
+        (false, true) => code_style,
+        (false, false) => None,
+    };
+    let code_formatting = is_code && (hyperlink || code_style.is_none());
+
+    result.push(run_start_token());
+
+    if bold || italic || strike || hyperlink || is_code || heading_font_size.is_some() {
+        // The children of `w:rPr` have to be in the order of the schema.
+        result.push(wp_start_token("rPr", &[]));
+        if let Some(style) = style {
+            result.extend(wp_empty_tokens("rStyle", &[("val", style)]));
+        }
+        if code_formatting {
+            result.extend(wp_empty_tokens(
+                "rFonts",
+                &[
+                    ("ascii", CODE_FONT),
+                    ("hAnsi", CODE_FONT),
+                    ("cs", CODE_FONT),
+                ],
+            ));
+        }
+        if bold {
+            result.extend(wp_empty_tokens("b", &[]));
+            result.extend(wp_empty_tokens("bCs", &[]));
+        }
+        if italic {
+            result.extend(wp_empty_tokens("i", &[]));
+            result.extend(wp_empty_tokens("iCs", &[]));
+        }
+        if strike {
+            result.extend(wp_empty_tokens("strike", &[]));
+        }
+        if hyperlink {
+            result.extend(wp_empty_tokens("color", &[("val", HYPERLINK_COLOR)]));
         }
-        _ => vec![],
+        if let Some(size) = heading_font_size {
+            result.extend(wp_empty_tokens("sz", &[("val", size)]));
+            result.extend(wp_empty_tokens("szCs", &[("val", size)]));
+        }
+        if hyperlink {
+            result.extend(wp_empty_tokens("u", &[("val", "single")]));
+        }
+        if code_formatting {
+            result.extend(wp_empty_tokens(
+                "shd",
+                &[("val", "clear"), ("color", "auto"), ("fill", CODE_SHADING)],
+            ));
+        }
+        result.push(wp_end_token("rPr"));
+    }
+
+    result.extend(char_text_tokens(text, true));
+    result.push(run_end_token());
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::testing::{a4_dimensions, xml};
+    use crate::render::{
+        code_block::CodeBlockOptions, footnotes::Footnotes, is_wp_end_token,
+        jupyter_nb::JupyterOptions, numbering::Numbering,
+    };
+
+    fn render(md_text: &str) -> (Vec<Token>, DocumentParts) {
+        render_styled(md_text, None, &MarkdownStyles::default())
+    }
+
+    /// Render with the styles of a template and the styles set for elements.
+    fn render_styled(
+        md_text: &str,
+        styles_xml: Option<&str>,
+        markdown_styles: &MarkdownStyles,
+    ) -> (Vec<Token>, DocumentParts) {
+        let dimensions = a4_dimensions();
+        let table_options = TableOptions::default();
+        let styles = StyleSheet::from_xml(styles_xml).unwrap();
+        let settings = RenderSettings {
+            dimensions: &dimensions,
+            table_options: &table_options,
+            code_blocks: CodeBlockOptions::default(),
+            jupyter: JupyterOptions::default(),
+            styles: &styles,
+            markdown_styles,
+            files: &FileAccess::default(),
+        };
+        let mut parts = DocumentParts {
+            latest_rels_id: 0,
+            images: BTreeMap::new(),
+            relationships: Vec::new(),
+            numbering: Numbering::from_xml(None).unwrap(),
+            footnotes: Footnotes::from_xml(None).unwrap(),
+            warnings: Vec::new(),
+        };
+        let tokens = markdown_to_tokens(md_text, &settings, &mut parts);
+        (tokens, parts)
+    }
+
+    /// Whether a table starts inside an open paragraph.
+    fn has_table_in_paragraph(tokens: &[Token]) -> bool {
+        let mut paragraph_depth: usize = 0;
+        for token in tokens {
+            if is_wp_start_token(token, "p") {
+                paragraph_depth += 1;
+            } else if is_wp_end_token(token, "p") {
+                paragraph_depth = paragraph_depth.saturating_sub(1);
+            } else if is_wp_start_token(token, "tbl") && paragraph_depth > 0 {
+                return true;
+            }
+        }
+        false
+    }
+
+    #[test]
+    fn links_and_images_in_table_cells_are_text() {
+        let (tokens, parts) = render("| a |\n|---|\n| [a](http://x) ![alt](x.png) |\n");
+        assert!(tokens.iter().any(|token| is_wp_start_token(token, "tbl")));
+        assert!(!has_table_in_paragraph(&tokens));
+        assert!(!tokens
+            .iter()
+            .any(|token| is_wp_start_token(token, "hyperlink")));
+        assert!(parts.relationships.is_empty());
+        assert!(parts.images.is_empty());
+    }
+
+    /// LaTeX and display flag of the math spans of a markdown text.
+    fn math_spans(md_text: &str) -> Vec<(String, bool)> {
+        let (_, spans) = extract_math(md_text);
+        spans
+            .into_iter()
+            .map(|span| (span.latex, span.display))
+            .collect()
+    }
+
+    #[test]
+    fn inline_and_display_math() {
+        assert_eq!(
+            math_spans("Area $\\pi r^2$ and\n\n$$\nE = mc^2\n$$\n"),
+            vec![
+                (String::from("\\pi r^2"), false),
+                (String::from("E = mc^2"), true)
+            ]
+        );
+    }
+
+    #[test]
+    fn prices_are_not_math() {
+        assert!(math_spans("It costs $5 and $10.").is_empty());
+        assert!(math_spans("Between $ 5 and 10 $ apples.").is_empty());
+    }
+
+    #[test]
+    fn escaped_dollars_are_not_math() {
+        let (text, spans) = extract_math("\\$x$ is not math");
+        assert!(spans.is_empty());
+        assert_eq!(text, "\\$x$ is not math");
+    }
+
+    #[test]
+    fn no_math_in_code() {
+        assert!(math_spans("Use `$x$` or `` $y$ ``.").is_empty());
+        assert!(math_spans("```\n$x$\n```\n~~~~\n$$y$$\n~~~~\n").is_empty());
+        // Inline math does not run into a code span.
+        assert!(math_spans("$a `b$` c").is_empty());
+        assert_eq!(
+            math_spans("```\ncode\n```\n$x$"),
+            vec![(String::from("x"), false)]
+        );
+    }
+
+    #[test]
+    fn local_images_are_not_read_by_default() {
+        let (tokens, parts) = render("![secret](/etc/hostname)");
+        assert!(parts.images.is_empty());
+        assert!(tokens
+            .iter()
+            .any(|token| matches!(&token.xml_reader_event, xml::reader::XmlEvent::Characters(text) if text == "secret")));
+    }
+
+    /// The XML of the tokens of a markdown text.
+    fn render_xml(md_text: &str) -> String {
+        xml(&render(md_text).0)
+    }
+
+    #[test]
+    fn inline_formatting() {
+        let xml = render_xml("**bold** *it* ***both*** ~~gone~~ `code`");
+        let run = |rpr: &str, text: &str| {
+            format!(
+                r#"<w:r><w:rPr>{}</w:rPr><w:t xml:space="preserve">{}</w:t></w:r>"#,
+                rpr, text
+            )
+        };
+        assert!(xml.contains(&run("<w:b></w:b><w:bCs></w:bCs>", "bold")));
+        assert!(xml.contains(&run("<w:i></w:i><w:iCs></w:iCs>", "it")));
+        assert!(xml.contains(&run(
+            "<w:b></w:b><w:bCs></w:bCs><w:i></w:i><w:iCs></w:iCs>",
+            "both"
+        )));
+        assert!(xml.contains(&run("<w:strike></w:strike>", "gone")));
+        assert!(xml.contains(r#"<w:rFonts w:ascii="Consolas" w:hAnsi="Consolas" w:cs="Consolas"></w:rFonts><w:shd w:val="clear" w:color="auto" w:fill="F2F2F2"></w:shd></w:rPr><w:t xml:space="preserve">code</w:t>"#));
+        // The text is not padded with spaces.
+        assert!(!xml.contains(">bold </w:t>") && !xml.contains("> bold</w:t>"));
+    }
+
+    #[test]
+    fn list_numbering() {
+        let (tokens, parts) = render("- a\n  - b\n- c\n\n3. x\n4. y\n");
+        let xml = xml(&tokens);
+        let num_pr = |level: usize, num_id: usize| {
+            format!(
+                r#"<w:numPr><w:ilvl w:val="{}"></w:ilvl><w:numId w:val="{}"></w:numId></w:numPr>"#,
+                level, num_id
+            )
+        };
+        assert_eq!(xml.matches(&num_pr(0, 1)).count(), 2);
+        assert_eq!(xml.matches(&num_pr(1, 1)).count(), 1);
+        assert_eq!(xml.matches(&num_pr(0, 2)).count(), 2);
+
+        assert!(parts.numbering.is_modified());
+        let numbering_xml = parts.numbering.to_xml().unwrap();
+        assert_eq!(numbering_xml.matches("<w:abstractNum ").count(), 2);
+        assert!(numbering_xml.contains(r#"<w:numFmt w:val="bullet"/>"#));
+        assert!(numbering_xml.contains(r#"<w:numFmt w:val="decimal"/>"#));
+        assert!(numbering_xml
+            .contains(r#"<w:lvlOverride w:ilvl="0"><w:startOverride w:val="3"/></w:lvlOverride>"#));
+    }
+
+    #[test]
+    fn jpeg_images() {
+        let jpeg = [
+            0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x04, 0x00, 0x00, 0xFF, 0xC0, 0x00, 0x0B, 0x08, 0x00,
+            0x02, 0x00, 0x03, 0x01, 0x01, 0x11, 0x00, 0xFF, 0xD9,
+        ];
+        let md_text = format!("![photo](data:image/jpeg;base64,{})", base64::encode(jpeg));
+        let (tokens, parts) = render(&md_text);
+        let image = parts.images.values().next().unwrap();
+        assert_eq!(image.file_contents.filename, "figure-1.jpeg");
+        assert_eq!((image.width, image.height), (3, 2));
+        assert!(parts.warnings.is_empty());
+        assert!(xml(&tokens).contains(r#"descr="photo""#));
+    }
+
+    #[test]
+    fn unusable_images_give_warnings() {
+        let md_text = format!(
+            "![gif](data:image/gif;base64,{}) ![local](missing.png) ![remote](https://x/y.png)",
+            base64::encode("GIF89a")
+        );
+        let (tokens, parts) = render(&md_text);
+        assert!(parts.images.is_empty());
+        assert_eq!(parts.warnings.len(), 2);
+        assert_eq!(
+            parts.warnings[0],
+            "Image data: URI replaced by its alt text: not a PNG or JPEG image"
+        );
+        assert!(parts.warnings[1].starts_with("Image missing.png replaced by its alt text: "));
+        let xml = xml(&tokens);
+        assert!(xml.contains(">gif</w:t>") && xml.contains(">local</w:t>"));
+    }
+
+    #[test]
+    fn code_block_highlighting() {
+        let xml = render_xml("```rust\nlet x = 1; // hi\n```\n");
+        let colored = |color: &str, text: &str| {
+            let run = format!(r#"<w:color w:val="{}"></w:color>"#, color);
+            let text = format!(r#"<w:t xml:space="preserve">{}</w:t>"#, text);
+            xml.split("<w:r>")
+                .any(|run_xml| run_xml.contains(&run) && run_xml.contains(&text))
+        };
+        assert!(colored("0000FF", "let"));
+        assert!(colored("098658", "1"));
+        assert!(colored("008000", "// hi"));
+        assert!(xml.contains(r#"w:fill="F6F8FA""#));
+    }
+
+    #[test]
+    fn quotes_rules_and_breaks() {
+        let xml = render_xml("> quoted\n\n---\n\nline  \nbreak\nsoft");
+        assert!(xml.contains(r#"<w:pBdr><w:left w:val="single" w:sz="18" w:space="8" w:color="BFBFBF"></w:left></w:pBdr><w:ind w:left="720"></w:ind>"#));
+        assert!(xml.contains(r#"<w:p><w:pPr><w:pBdr><w:bottom w:val="single" w:sz="6" w:space="1" w:color="auto"></w:bottom></w:pBdr></w:pPr></w:p>"#));
+        assert_eq!(xml.matches("<w:r><w:br></w:br></w:r>").count(), 1);
+        // A soft break is a space.
+        assert!(xml.contains(r#"<w:t xml:space="preserve"> </w:t>"#));
+    }
+
+    #[test]
+    fn styles_by_name() {
+        let styles_xml = r#"<w:styles xmlns:w="http://schemas.openxmlformats.org/wordprocessingml/2006/main">
+            <w:style w:type="paragraph" w:styleId="Overskrift1"><w:name w:val="heading 1"/></w:style>
+            <w:style w:type="paragraph" w:styleId="Citat"><w:name w:val="Quote"/></w:style>
+            <w:style w:type="paragraph" w:styleId="BodyText2"><w:name w:val="Body Text"/></w:style>
+            <w:style w:type="character" w:styleId="BodyTextChar"><w:name w:val="Body Text"/></w:style>
+        </w:styles>"#;
+        let mut markdown_styles = MarkdownStyles::default();
+        markdown_styles.set(MarkdownElement::Paragraph, "body text");
+        markdown_styles.set(MarkdownElement::Code, "Body Text");
+        let (tokens, _) = render_styled(
+            "# Head\n\n> quoted\n\nplain `code`",
+            Some(styles_xml),
+            &markdown_styles,
+        );
+        let xml = xml(&tokens);
+        assert!(xml.contains(r#"<w:pStyle w:val="Overskrift1"></w:pStyle>"#));
+        assert!(xml.contains(r#"<w:pStyle w:val="Citat"></w:pStyle>"#));
+        assert!(xml.contains(r#"<w:pStyle w:val="BodyText2"></w:pStyle>"#));
+        assert!(xml.contains(r#"<w:rStyle w:val="BodyTextChar"></w:rStyle>"#));
+        // No direct formatting where a style applies.
+        assert!(!xml.contains("<w:outlineLvl") && !xml.contains(QUOTE_BORDER_COLOR));
     }
 }
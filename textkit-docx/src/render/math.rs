@@ -0,0 +1,1328 @@
+//! LaTeX math to native Word equations (Office Math, OMML). The LaTeX is
+//! parsed into a small tree of math nodes (fractions, scripts, roots, large
+//! operators, delimiters, matrices, accents), which maps one to one onto OMML
+//! elements (`m:f`, `m:sSub`, `m:rad`, `m:nary`, `m:d`, `m:m`, `m:acc`, ...).
+//!
+//! Parsing never fails: unknown commands are shown as they are written and
+//! unbalanced groups are closed at the end of the formula, so that a typo in
+//! a report shows up in the document rather than breaking the rendering.
+//!
+
+use crate::render::{
+    end_tag_event, owned_attribute, start_tag_event, wp_empty_tokens, wp_end_token, wp_start_token,
+};
+use crate::{Token, TokenType, NS_OMML};
+
+/// Font Word uses for equations.
+const MATH_FONT: &str = "Cambria Math";
+
+/// Commands whose argument is text rather than math, e.g. `\text{if }`.
+const TEXT_COMMANDS: [&str; 8] = [
+    "text",
+    "textrm",
+    "textit",
+    "textbf",
+    "mbox",
+    "mathrm",
+    "operatorname",
+    "textnormal",
+];
+
+/// Function names set upright, e.g. `\sin`.
+const FUNCTIONS: [&str; 33] = [
+    "arccos", "arcsin", "arctan", "arg", "cos", "cosh", "cot", "coth", "csc", "deg", "det", "dim",
+    "exp", "gcd", "hom", "inf", "ker", "lg", "lim", "liminf", "limsup", "ln", "log", "max", "min",
+    "Pr", "sec", "sin", "sinh", "sup", "tan", "tanh", "argmax",
+];
+
+/// Functions whose subscript goes underneath, e.g. `\lim_{x \to 0}`.
+const LIMIT_FUNCTIONS: [&str; 10] = [
+    "lim", "liminf", "limsup", "max", "min", "sup", "inf", "det", "Pr", "argmax",
+];
+
+/// How the characters of a run are set.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RunStyle {
+    /// Word's default: letters in italic, digits and operators upright.
+    Math,
+    Upright,
+    Bold,
+    Italic,
+    DoubleStruck,
+    Script,
+    Fraktur,
+    Monospace,
+    /// Ordinary text, e.g. `\text{...}`.
+    Text,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Lexeme {
+    Command(String),
+    /// A text command with its (raw) argument.
+    Text(String, String),
+    Char(char),
+    Number(String),
+    OpenGroup,
+    CloseGroup,
+    Subscript,
+    Superscript,
+    Prime,
+    ColumnSeparator,
+    RowSeparator,
+}
+
+#[derive(Debug, Clone)]
+enum MathNode {
+    Run(String, RunStyle),
+    Group(Vec<MathNode>),
+    Fraction {
+        numerator: Vec<MathNode>,
+        denominator: Vec<MathNode>,
+        bar: bool,
+    },
+    Scripts {
+        base: Vec<MathNode>,
+        sub: Option<Vec<MathNode>>,
+        sup: Option<Vec<MathNode>>,
+    },
+    Radical {
+        degree: Option<Vec<MathNode>>,
+        body: Vec<MathNode>,
+    },
+    /// Sums, products and integrals.
+    Nary {
+        operator: char,
+        under_over: bool,
+        sub: Option<Vec<MathNode>>,
+        sup: Option<Vec<MathNode>>,
+        body: Vec<MathNode>,
+    },
+    /// A limit under (or over) its base, e.g. `\lim_{x \to 0}`.
+    Limit {
+        upper: bool,
+        base: Vec<MathNode>,
+        limit: Vec<MathNode>,
+    },
+    Delimited {
+        open: String,
+        close: String,
+        body: Vec<MathNode>,
+    },
+    Matrix(Vec<Vec<Vec<MathNode>>>),
+    EquationArray(Vec<Vec<MathNode>>),
+    Accent(char, Vec<MathNode>),
+    Bar {
+        top: bool,
+        body: Vec<MathNode>,
+    },
+}
+
+/// An inline equation (`m:oMath`), to go into a paragraph between runs.
+pub(crate) fn math_tokens(latex: &str) -> Vec<Token> {
+    let mut result: Vec<Token> = Vec::new();
+    result.push(m_start_token("oMath", &[]));
+    result.extend(nodes_tokens(&parse(latex)));
+    result.push(m_end_token("oMath"));
+    result
+}
+
+/// A display equation (`m:oMathPara`), centred on a line of its own.
+pub(crate) fn display_math_tokens(latex: &str) -> Vec<Token> {
+    let mut result: Vec<Token> = Vec::new();
+    result.push(m_start_token("oMathPara", &[]));
+    result.push(m_start_token("oMathParaPr", &[]));
+    result.extend(m_empty_tokens("jc", Some("center")));
+    result.push(m_end_token("oMathParaPr"));
+    result.extend(math_tokens(latex));
+    result.push(m_end_token("oMathPara"));
+    result
+}
+
+/// A paragraph with a display equation.
+pub(crate) fn math_paragraph_tokens(latex: &str) -> Vec<Token> {
+    let mut result: Vec<Token> = Vec::new();
+    result.push(wp_start_token("p", &[]));
+    result.extend(display_math_tokens(latex));
+    result.push(wp_end_token("p"));
+    result
+}
+
+fn lex(latex: &str) -> Vec<Lexeme> {
+    let chars: Vec<char> = latex.chars().collect();
+    let mut lexemes: Vec<Lexeme> = Vec::new();
+    let mut i: usize = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        i += 1;
+        match c {
+            '\\' => match chars.get(i) {
+                None => lexemes.push(Lexeme::Char('\\')),
+                Some('\\') => {
+                    i += 1;
+                    lexemes.push(Lexeme::RowSeparator);
+                }
+                Some(next) if next.is_ascii_alphabetic() => {
+                    let start = i;
+                    while i < chars.len() && chars[i].is_ascii_alphabetic() {
+                        i += 1;
+                    }
+                    let name: String = chars[start..i].iter().collect();
+
+                    let mut j = i;
+                    while j < chars.len() && chars[j].is_whitespace() {
+                        j += 1;
+                    }
+                    if TEXT_COMMANDS.contains(&name.as_str()) && chars.get(j) == Some(&'{') {
+                        // The argument is kept as written, spaces included.
+                        let mut depth = 0;
+                        let mut end = j;
+                        while end < chars.len() {
+                            match chars[end] {
+                                '{' => depth += 1,
+                                '}' => {
+                                    depth -= 1;
+                                    if depth == 0 {
+                                        break;
+                                    }
+                                }
+                                _ => {}
+                            }
+                            end += 1;
+                        }
+                        let text: String = chars[j + 1..end.min(chars.len())].iter().collect();
+                        lexemes.push(Lexeme::Text(name, text));
+                        i = end + 1;
+                    } else {
+                        lexemes.push(Lexeme::Command(name));
+                    }
+                }
+                Some(next) => {
+                    lexemes.push(Lexeme::Command(next.to_string()));
+                    i += 1;
+                }
+            },
+            '{' => lexemes.push(Lexeme::OpenGroup),
+            '}' => lexemes.push(Lexeme::CloseGroup),
+            '_' => lexemes.push(Lexeme::Subscript),
+            '^' => lexemes.push(Lexeme::Superscript),
+            '\'' => lexemes.push(Lexeme::Prime),
+            '&' => lexemes.push(Lexeme::ColumnSeparator),
+            '~' => lexemes.push(Lexeme::Char('\u{A0}')),
+            c if c.is_whitespace() => {}
+            c if c.is_ascii_digit() => {
+                let start = i - 1;
+                while i < chars.len()
+                    && (chars[i].is_ascii_digit()
+                        || chars[i] == '.' && chars.get(i + 1).is_some_and(|c| c.is_ascii_digit()))
+                {
+                    i += 1;
+                }
+                lexemes.push(Lexeme::Number(chars[start..i].iter().collect()));
+            }
+            c => lexemes.push(Lexeme::Char(c)),
+        }
+    }
+
+    lexemes
+}
+
+fn parse(latex: &str) -> Vec<MathNode> {
+    let mut parser = Parser {
+        lexemes: lex(latex),
+        position: 0,
+        style: RunStyle::Math,
+    };
+
+    let mut nodes: Vec<MathNode> = Vec::new();
+    loop {
+        nodes.extend(parser.sequence());
+        // Whatever ended the sequence is out of place at the top level.
+        if parser.next().is_none() {
+            break;
+        }
+    }
+    nodes
+}
+
+struct Parser {
+    lexemes: Vec<Lexeme>,
+    position: usize,
+    style: RunStyle,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Lexeme> {
+        self.lexemes.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Lexeme> {
+        let lexeme = self.lexemes.get(self.position).cloned();
+        self.position += 1;
+        lexeme
+    }
+
+    fn run(&self, text: &str) -> MathNode {
+        MathNode::Run(String::from(text), self.style)
+    }
+
+    /// Nodes up to the end of the group, cell, row, `\right` or `\end`.
+    fn sequence(&mut self) -> Vec<MathNode> {
+        let mut nodes: Vec<MathNode> = Vec::new();
+        while !self.at_sequence_end() {
+            if let Some(node) = self.scripted_atom() {
+                nodes.push(node);
+            }
+        }
+        nodes
+    }
+
+    fn at_sequence_end(&self) -> bool {
+        match self.peek() {
+            None
+            | Some(Lexeme::CloseGroup)
+            | Some(Lexeme::ColumnSeparator)
+            | Some(Lexeme::RowSeparator) => true,
+            Some(Lexeme::Command(name)) => name == "right" || name == "end",
+            _ => false,
+        }
+    }
+
+    /// The body of a sum or integral: everything up to the next `+`, `-` or
+    /// relation (e.g. `=`), or the end of the sequence. The body of an
+    /// integral also ends with its differential, e.g. `dx`.
+    fn nary_body(&mut self, is_integral: bool) -> Vec<MathNode> {
+        let mut nodes: Vec<MathNode> = Vec::new();
+        while !self.at_sequence_end() && !self.at_relation() {
+            if !nodes.is_empty() && matches!(self.peek(), Some(Lexeme::Char('+' | '-'))) {
+                break;
+            }
+            if let Some(node) = self.scripted_atom() {
+                let is_differential = is_integral
+                    && matches!(&node, MathNode::Run(text, _) if text.chars().all(char::is_alphabetic))
+                    && matches!(nodes.last(), Some(MathNode::Run(text, _)) if text == "d");
+                nodes.push(node);
+                if is_differential {
+                    break;
+                }
+            }
+        }
+        nodes
+    }
+
+    fn at_relation(&self) -> bool {
+        match self.peek() {
+            Some(Lexeme::Char(c)) => "=<>".contains(*c),
+            Some(Lexeme::Command(name)) => matches!(
+                name.as_str(),
+                "le" | "leq"
+                    | "ge"
+                    | "geq"
+                    | "ne"
+                    | "neq"
+                    | "approx"
+                    | "equiv"
+                    | "sim"
+                    | "simeq"
+                    | "propto"
+                    | "to"
+                    | "rightarrow"
+                    | "Rightarrow"
+                    | "iff"
+            ),
+            _ => false,
+        }
+    }
+
+    /// An atom with its subscript, superscript and primes.
+    fn scripted_atom(&mut self) -> Option<MathNode> {
+        let atom = match self.peek() {
+            // Scripts without a base, e.g. `^{14}C`.
+            Some(Lexeme::Subscript) | Some(Lexeme::Superscript) => MathNode::Group(Vec::new()),
+            _ => self.atom()?,
+        };
+
+        let mut sub: Option<Vec<MathNode>> = None;
+        let mut sup: Option<Vec<MathNode>> = None;
+        loop {
+            match self.peek() {
+                Some(Lexeme::Subscript) => {
+                    self.next();
+                    sub = Some(self.script_argument());
+                }
+                Some(Lexeme::Superscript) => {
+                    self.next();
+                    let mut argument = self.script_argument();
+                    if let Some(mut primes) = sup.take() {
+                        primes.append(&mut argument);
+                        argument = primes;
+                    }
+                    sup = Some(argument);
+                }
+                Some(Lexeme::Prime) => {
+                    self.next();
+                    sup.get_or_insert_with(Vec::new)
+                        .push(MathNode::Run(String::from("\u{2032}"), RunStyle::Math));
+                }
+                Some(Lexeme::Command(name)) if name == "limits" || name == "nolimits" => {
+                    self.next();
+                }
+                _ => break,
+            }
+        }
+
+        let node = match atom {
+            MathNode::Nary {
+                operator,
+                under_over,
+                ..
+            } => MathNode::Nary {
+                operator,
+                under_over,
+                sub,
+                sup,
+                body: self.nary_body(!under_over),
+            },
+            MathNode::Run(ref name, RunStyle::Upright)
+                if sub.is_some() && sup.is_none() && LIMIT_FUNCTIONS.contains(&name.as_str()) =>
+            {
+                MathNode::Limit {
+                    upper: false,
+                    base: vec![atom],
+                    limit: sub.unwrap_or_default(),
+                }
+            }
+            atom if sub.is_none() && sup.is_none() => atom,
+            atom => MathNode::Scripts {
+                base: vec![atom],
+                sub,
+                sup,
+            },
+        };
+        Some(node)
+    }
+
+    /// The argument of `_` or `^`: a group or a single character (`x^23` is
+    /// x squared followed by 3, as in TeX).
+    fn script_argument(&mut self) -> Vec<MathNode> {
+        if let Some(Lexeme::Number(number)) = self.peek() {
+            let mut digits = number.chars();
+            let first = digits.next().map(String::from).unwrap_or_default();
+            let rest: String = digits.collect();
+            if rest.is_empty() {
+                self.next();
+            } else {
+                self.lexemes[self.position] = Lexeme::Number(rest);
+            }
+            return vec![self.run(&first)];
+        }
+        self.argument()
+    }
+
+    /// The argument of a command: a group or a single atom.
+    fn argument(&mut self) -> Vec<MathNode> {
+        match self.peek() {
+            Some(Lexeme::OpenGroup) => {
+                self.next();
+                let nodes = self.sequence();
+                if let Some(Lexeme::CloseGroup) = self.peek() {
+                    self.next();
+                }
+                nodes
+            }
+            _ if self.at_sequence_end() => Vec::new(),
+            _ => self.atom().into_iter().collect(),
+        }
+    }
+
+    fn atom(&mut self) -> Option<MathNode> {
+        match self.next()? {
+            Lexeme::OpenGroup => {
+                let nodes = self.sequence();
+                if let Some(Lexeme::CloseGroup) = self.peek() {
+                    self.next();
+                }
+                Some(MathNode::Group(nodes))
+            }
+            Lexeme::Char(c) => Some(self.run(&c.to_string())),
+            Lexeme::Number(number) => Some(self.run(&number)),
+            Lexeme::Prime => Some(self.run("\u{2032}")),
+            Lexeme::Text(command, text) => {
+                let style = match command.as_str() {
+                    "mathrm" | "operatorname" => RunStyle::Upright,
+                    "textbf" => RunStyle::Bold,
+                    "textit" => RunStyle::Italic,
+                    _ => RunStyle::Text,
+                };
+                Some(MathNode::Run(text, style))
+            }
+            Lexeme::Command(name) => Some(self.command(&name)),
+            // Stray separators and scripts are skipped.
+            _ => None,
+        }
+    }
+
+    fn command(&mut self, name: &str) -> MathNode {
+        if let Some(symbol) = symbol(name) {
+            return self.run(symbol);
+        }
+        if FUNCTIONS.contains(&name) {
+            return MathNode::Run(String::from(name), RunStyle::Upright);
+        }
+        if let Some((operator, under_over)) = nary_operator(name) {
+            return MathNode::Nary {
+                operator,
+                under_over,
+                sub: None,
+                sup: None,
+                body: Vec::new(),
+            };
+        }
+        if let Some(accent) = accent(name) {
+            return MathNode::Accent(accent, self.argument());
+        }
+        if let Some(style) = font_style(name) {
+            let outer_style = self.style;
+            self.style = style;
+            let nodes = self.argument();
+            self.style = outer_style;
+            return MathNode::Group(nodes);
+        }
+
+        match name {
+            "frac" | "dfrac" | "tfrac" | "cfrac" => MathNode::Fraction {
+                numerator: self.argument(),
+                denominator: self.argument(),
+                bar: true,
+            },
+            "binom" | "dbinom" | "tbinom" => MathNode::Delimited {
+                open: String::from("("),
+                close: String::from(")"),
+                body: vec![MathNode::Fraction {
+                    numerator: self.argument(),
+                    denominator: self.argument(),
+                    bar: false,
+                }],
+            },
+            "sqrt" => {
+                let degree = match self.peek() {
+                    Some(Lexeme::Char('[')) => {
+                        self.next();
+                        let mut degree: Vec<MathNode> = Vec::new();
+                        while !self.at_sequence_end() && self.peek() != Some(&Lexeme::Char(']')) {
+                            if let Some(node) = self.scripted_atom() {
+                                degree.push(node);
+                            }
+                        }
+                        if self.peek() == Some(&Lexeme::Char(']')) {
+                            self.next();
+                        }
+                        Some(degree)
+                    }
+                    _ => None,
+                };
+                MathNode::Radical {
+                    degree,
+                    body: self.argument(),
+                }
+            }
+            "left" => {
+                let open = self.delimiter();
+                let body = self.sequence();
+                let close = match self.peek() {
+                    Some(Lexeme::Command(name)) if name == "right" => {
+                        self.next();
+                        self.delimiter()
+                    }
+                    _ => String::new(),
+                };
+                MathNode::Delimited { open, close, body }
+            }
+            "big" | "Big" | "bigg" | "Bigg" | "bigl" | "bigr" | "Bigl" | "Bigr" | "biggl"
+            | "biggr" | "Biggl" | "Biggr" | "middle" => {
+                let delimiter = self.delimiter();
+                MathNode::Run(delimiter, RunStyle::Math)
+            }
+            "overline" => MathNode::Bar {
+                top: true,
+                body: self.argument(),
+            },
+            "underline" => MathNode::Bar {
+                top: false,
+                body: self.argument(),
+            },
+            "overset" | "stackrel" | "underset" => {
+                let limit = self.argument();
+                let base = self.argument();
+                MathNode::Limit {
+                    upper: name != "underset",
+                    base,
+                    limit,
+                }
+            }
+            "not" => match self.atom() {
+                Some(MathNode::Run(text, style)) => MathNode::Run(text + "\u{338}", style),
+                Some(node) => node,
+                None => self.run("/"),
+            },
+            "begin" => self.environment(),
+            // Spacing.
+            "," => MathNode::Run(String::from("\u{2009}"), RunStyle::Upright),
+            ":" | ">" | ";" => MathNode::Run(String::from("\u{2005}"), RunStyle::Upright),
+            " " => MathNode::Run(String::from(" "), RunStyle::Upright),
+            "quad" => MathNode::Run(String::from("\u{2003}"), RunStyle::Upright),
+            "qquad" => MathNode::Run(String::from("\u{2003}\u{2003}"), RunStyle::Upright),
+            "!" | "displaystyle" | "textstyle" | "scriptstyle" | "nonumber" | "notag" => {
+                MathNode::Group(Vec::new())
+            }
+            "label" | "tag" => {
+                self.argument();
+                MathNode::Group(Vec::new())
+            }
+            // Escaped characters.
+            "{" | "}" | "%" | "$" | "&" | "#" | "_" => self.run(name),
+            "|" => self.run("\u{2016}"),
+            // Shown as written, so that it can be spotted and fixed.
+            _ => MathNode::Run(format!("\\{}", name), RunStyle::Upright),
+        }
+    }
+
+    /// The delimiter after `\left`, `\right` or `\big`; `.` is none.
+    fn delimiter(&mut self) -> String {
+        match self.next() {
+            Some(Lexeme::Char('.')) => String::new(),
+            Some(Lexeme::Char(c)) => c.to_string(),
+            Some(Lexeme::Command(name)) => match name.as_str() {
+                "{" | "lbrace" => String::from("{"),
+                "}" | "rbrace" => String::from("}"),
+                "|" | "Vert" | "lVert" | "rVert" => String::from("\u{2016}"),
+                "vert" | "lvert" | "rvert" => String::from("|"),
+                name => symbol(name).map(String::from).unwrap_or_default(),
+            },
+            _ => String::new(),
+        }
+    }
+
+    /// `\begin{name} ... \end{name}`: matrices, cases and aligned equations.
+    fn environment(&mut self) -> MathNode {
+        let name = self.environment_name();
+        if name == "array" {
+            // The column specification, e.g. `{cc|c}`.
+            self.argument();
+        }
+
+        let mut rows: Vec<Vec<Vec<MathNode>>> = Vec::new();
+        let mut row: Vec<Vec<MathNode>> = Vec::new();
+        loop {
+            row.push(self.sequence());
+            match self.next() {
+                Some(Lexeme::ColumnSeparator) => continue,
+                Some(Lexeme::RowSeparator) => rows.push(std::mem::take(&mut row)),
+                Some(Lexeme::Command(command)) if command == "end" => {
+                    self.environment_name();
+                    rows.push(std::mem::take(&mut row));
+                    break;
+                }
+                Some(Lexeme::CloseGroup) => continue,
+                _ => {
+                    rows.push(std::mem::take(&mut row));
+                    break;
+                }
+            }
+        }
+        // A `\\` before `\end` does not start a row.
+        if rows.len() > 1
+            && rows
+                .last()
+                .is_some_and(|row| row.iter().all(|cell| cell.is_empty()))
+        {
+            rows.pop();
+        }
+
+        let delimiters = match name.trim_end_matches('*') {
+            "pmatrix" => Some(("(", ")")),
+            "bmatrix" => Some(("[", "]")),
+            "Bmatrix" => Some(("{", "}")),
+            "vmatrix" => Some(("|", "|")),
+            "Vmatrix" => Some(("\u{2016}", "\u{2016}")),
+            "cases" => Some(("{", "")),
+            _ => None,
+        };
+        let is_equation_array = matches!(
+            name.trim_end_matches('*'),
+            "align" | "aligned" | "alignat" | "split" | "gather" | "gathered" | "eqnarray"
+        );
+
+        let node = if is_equation_array {
+            MathNode::EquationArray(rows.into_iter().map(|row| row.concat()).collect())
+        } else {
+            MathNode::Matrix(rows)
+        };
+        match delimiters {
+            Some((open, close)) => MathNode::Delimited {
+                open: String::from(open),
+                close: String::from(close),
+                body: vec![node],
+            },
+            None => node,
+        }
+    }
+
+    fn environment_name(&mut self) -> String {
+        let mut name = String::new();
+        if let Some(Lexeme::OpenGroup) = self.peek() {
+            self.next();
+            while let Some(lexeme) = self.next() {
+                match lexeme {
+                    Lexeme::CloseGroup => break,
+                    Lexeme::Char(c) => name.push(c),
+                    _ => {}
+                }
+            }
+        }
+        name
+    }
+}
+
+fn symbol(name: &str) -> Option<&'static str> {
+    let symbol = match name {
+        // Greek letters.
+        "alpha" => "\u{3B1}",
+        "beta" => "\u{3B2}",
+        "gamma" => "\u{3B3}",
+        "delta" => "\u{3B4}",
+        "epsilon" => "\u{3F5}",
+        "varepsilon" => "\u{3B5}",
+        "zeta" => "\u{3B6}",
+        "eta" => "\u{3B7}",
+        "theta" => "\u{3B8}",
+        "vartheta" => "\u{3D1}",
+        "iota" => "\u{3B9}",
+        "kappa" => "\u{3BA}",
+        "lambda" => "\u{3BB}",
+        "mu" => "\u{3BC}",
+        "nu" => "\u{3BD}",
+        "xi" => "\u{3BE}",
+        "omicron" => "\u{3BF}",
+        "pi" => "\u{3C0}",
+        "varpi" => "\u{3D6}",
+        "rho" => "\u{3C1}",
+        "varrho" => "\u{3F1}",
+        "sigma" => "\u{3C3}",
+        "varsigma" => "\u{3C2}",
+        "tau" => "\u{3C4}",
+        "upsilon" => "\u{3C5}",
+        "phi" => "\u{3D5}",
+        "varphi" => "\u{3C6}",
+        "chi" => "\u{3C7}",
+        "psi" => "\u{3C8}",
+        "omega" => "\u{3C9}",
+        "Gamma" => "\u{393}",
+        "Delta" => "\u{394}",
+        "Theta" => "\u{398}",
+        "Lambda" => "\u{39B}",
+        "Xi" => "\u{39E}",
+        "Pi" => "\u{3A0}",
+        "Sigma" => "\u{3A3}",
+        "Upsilon" => "\u{3A5}",
+        "Phi" => "\u{3A6}",
+        "Psi" => "\u{3A8}",
+        "Omega" => "\u{3A9}",
+        // Operators and relations.
+        "cdot" => "\u{22C5}",
+        "times" => "\u{D7}",
+        "div" => "\u{F7}",
+        "pm" => "\u{B1}",
+        "mp" => "\u{2213}",
+        "ast" => "\u{2217}",
+        "star" => "\u{22C6}",
+        "circ" => "\u{2218}",
+        "bullet" => "\u{2219}",
+        "oplus" => "\u{2295}",
+        "otimes" => "\u{2297}",
+        "le" | "leq" => "\u{2264}",
+        "ge" | "geq" => "\u{2265}",
+        "ne" | "neq" => "\u{2260}",
+        "ll" => "\u{226A}",
+        "gg" => "\u{226B}",
+        "approx" => "\u{2248}",
+        "sim" => "\u{223C}",
+        "simeq" => "\u{2243}",
+        "cong" => "\u{2245}",
+        "equiv" => "\u{2261}",
+        "propto" => "\u{221D}",
+        "perp" => "\u{22A5}",
+        "parallel" => "\u{2225}",
+        "mid" => "\u{2223}",
+        "in" => "\u{2208}",
+        "notin" => "\u{2209}",
+        "ni" => "\u{220B}",
+        "subset" => "\u{2282}",
+        "supset" => "\u{2283}",
+        "subseteq" => "\u{2286}",
+        "supseteq" => "\u{2287}",
+        "cup" => "\u{222A}",
+        "cap" => "\u{2229}",
+        "setminus" => "\u{2216}",
+        "wedge" | "land" => "\u{2227}",
+        "vee" | "lor" => "\u{2228}",
+        "neg" | "lnot" => "\u{AC}",
+        "forall" => "\u{2200}",
+        "exists" => "\u{2203}",
+        // Arrows.
+        "to" | "rightarrow" => "\u{2192}",
+        "leftarrow" | "gets" => "\u{2190}",
+        "leftrightarrow" => "\u{2194}",
+        "Rightarrow" | "implies" => "\u{21D2}",
+        "Leftarrow" => "\u{21D0}",
+        "Leftrightarrow" | "iff" => "\u{21D4}",
+        "mapsto" => "\u{21A6}",
+        "uparrow" => "\u{2191}",
+        "downarrow" => "\u{2193}",
+        // Miscellaneous symbols.
+        "infty" => "\u{221E}",
+        "partial" => "\u{2202}",
+        "nabla" => "\u{2207}",
+        "emptyset" | "varnothing" => "\u{2205}",
+        "hbar" => "\u{210F}",
+        "ell" => "\u{2113}",
+        "Re" => "\u{211C}",
+        "Im" => "\u{2111}",
+        "aleph" => "\u{2135}",
+        "angle" => "\u{2220}",
+        "degree" => "\u{B0}",
+        "prime" => "\u{2032}",
+        "ldots" | "dots" => "\u{2026}",
+        "cdots" => "\u{22EF}",
+        "vdots" => "\u{22EE}",
+        "ddots" => "\u{22F1}",
+        "langle" => "\u{27E8}",
+        "rangle" => "\u{27E9}",
+        "lfloor" => "\u{230A}",
+        "rfloor" => "\u{230B}",
+        "lceil" => "\u{2308}",
+        "rceil" => "\u{2309}",
+        _ => return None,
+    };
+    Some(symbol)
+}
+
+/// Character of a large operator, and whether its limits go underneath and
+/// above (sums) rather than beside it (integrals).
+fn nary_operator(name: &str) -> Option<(char, bool)> {
+    match name {
+        "sum" => Some(('\u{2211}', true)),
+        "prod" => Some(('\u{220F}', true)),
+        "coprod" => Some(('\u{2210}', true)),
+        "bigcup" => Some(('\u{22C3}', true)),
+        "bigcap" => Some(('\u{22C2}', true)),
+        "int" => Some(('\u{222B}', false)),
+        "iint" => Some(('\u{222C}', false)),
+        "iiint" => Some(('\u{222D}', false)),
+        "oint" => Some(('\u{222E}', false)),
+        _ => None,
+    }
+}
+
+/// Combining character of an accent command.
+fn accent(name: &str) -> Option<char> {
+    match name {
+        "hat" | "widehat" => Some('\u{302}'),
+        "tilde" | "widetilde" => Some('\u{303}'),
+        "bar" => Some('\u{305}'),
+        "vec" => Some('\u{20D7}'),
+        "dot" => Some('\u{307}'),
+        "ddot" => Some('\u{308}'),
+        "acute" => Some('\u{301}'),
+        "grave" => Some('\u{300}'),
+        "breve" => Some('\u{306}'),
+        "check" => Some('\u{30C}'),
+        _ => None,
+    }
+}
+
+fn font_style(name: &str) -> Option<RunStyle> {
+    match name {
+        "mathbf" | "boldsymbol" | "bm" => Some(RunStyle::Bold),
+        "mathit" => Some(RunStyle::Italic),
+        "mathsf" | "rm" => Some(RunStyle::Upright),
+        "mathbb" => Some(RunStyle::DoubleStruck),
+        "mathcal" | "mathscr" => Some(RunStyle::Script),
+        "mathfrak" => Some(RunStyle::Fraktur),
+        "mathtt" => Some(RunStyle::Monospace),
+        _ => None,
+    }
+}
+
+/// Tokens of a list of nodes. Consecutive characters of the same style go
+/// into one run, as Word writes them.
+fn nodes_tokens(nodes: &[MathNode]) -> Vec<Token> {
+    let mut result: Vec<Token> = Vec::new();
+    let mut pending_run: Option<(String, RunStyle)> = None;
+
+    for node in nodes {
+        match node {
+            MathNode::Run(text, style) => match pending_run.as_mut() {
+                Some((pending_text, pending_style)) if pending_style == style => {
+                    pending_text.push_str(text)
+                }
+                _ => {
+                    if let Some((text, style)) = pending_run.take() {
+                        result.extend(run_tokens(&text, style));
+                    }
+                    pending_run = Some((text.clone(), *style));
+                }
+            },
+            node => {
+                if let Some((text, style)) = pending_run.take() {
+                    result.extend(run_tokens(&text, style));
+                }
+                result.extend(node_tokens(node));
+            }
+        }
+    }
+    if let Some((text, style)) = pending_run.take() {
+        result.extend(run_tokens(&text, style));
+    }
+
+    result
+}
+
+fn node_tokens(node: &MathNode) -> Vec<Token> {
+    let mut result: Vec<Token> = Vec::new();
+
+    match node {
+        MathNode::Run(text, style) => result.extend(run_tokens(text, *style)),
+        MathNode::Group(nodes) => result.extend(nodes_tokens(nodes)),
+        MathNode::Fraction {
+            numerator,
+            denominator,
+            bar,
+        } => {
+            result.push(m_start_token("f", &[]));
+            if !bar {
+                result.push(m_start_token("fPr", &[]));
+                result.extend(m_empty_tokens("type", Some("noBar")));
+                result.push(m_end_token("fPr"));
+            }
+            result.extend(container_tokens("num", numerator));
+            result.extend(container_tokens("den", denominator));
+            result.push(m_end_token("f"));
+        }
+        MathNode::Scripts { base, sub, sup } => {
+            let tag = match (sub, sup) {
+                (Some(_), Some(_)) => "sSubSup",
+                (Some(_), None) => "sSub",
+                _ => "sSup",
+            };
+            result.push(m_start_token(tag, &[]));
+            result.extend(container_tokens("e", base));
+            if let Some(sub) = sub {
+                result.extend(container_tokens("sub", sub));
+            }
+            if let Some(sup) = sup {
+                result.extend(container_tokens("sup", sup));
+            }
+            result.push(m_end_token(tag));
+        }
+        MathNode::Radical { degree, body } => {
+            result.push(m_start_token("rad", &[]));
+            if degree.is_none() {
+                result.push(m_start_token("radPr", &[]));
+                result.extend(m_empty_tokens("degHide", Some("1")));
+                result.push(m_end_token("radPr"));
+            }
+            result.extend(container_tokens("deg", degree.as_deref().unwrap_or(&[])));
+            result.extend(container_tokens("e", body));
+            result.push(m_end_token("rad"));
+        }
+        MathNode::Nary {
+            operator,
+            under_over,
+            sub,
+            sup,
+            body,
+        } => {
+            result.push(m_start_token("nary", &[]));
+            result.push(m_start_token("naryPr", &[]));
+            result.extend(m_empty_tokens("chr", Some(&operator.to_string())));
+            let limit_location = if *under_over { "undOvr" } else { "subSup" };
+            result.extend(m_empty_tokens("limLoc", Some(limit_location)));
+            if sub.is_none() {
+                result.extend(m_empty_tokens("subHide", Some("1")));
+            }
+            if sup.is_none() {
+                result.extend(m_empty_tokens("supHide", Some("1")));
+            }
+            result.push(m_end_token("naryPr"));
+            result.extend(container_tokens("sub", sub.as_deref().unwrap_or(&[])));
+            result.extend(container_tokens("sup", sup.as_deref().unwrap_or(&[])));
+            result.extend(container_tokens("e", body));
+            result.push(m_end_token("nary"));
+        }
+        MathNode::Limit { upper, base, limit } => {
+            let tag = if *upper { "limUpp" } else { "limLow" };
+            result.push(m_start_token(tag, &[]));
+            result.extend(container_tokens("e", base));
+            result.extend(container_tokens("lim", limit));
+            result.push(m_end_token(tag));
+        }
+        MathNode::Delimited { open, close, body } => {
+            result.push(m_start_token("d", &[]));
+            result.push(m_start_token("dPr", &[]));
+            result.extend(m_empty_tokens("begChr", Some(open)));
+            result.extend(m_empty_tokens("endChr", Some(close)));
+            result.push(m_end_token("dPr"));
+            result.extend(container_tokens("e", body));
+            result.push(m_end_token("d"));
+        }
+        MathNode::Matrix(rows) => {
+            // Word expects the same number of cells in every row.
+            let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+            result.push(m_start_token("m", &[]));
+            for row in rows {
+                result.push(m_start_token("mr", &[]));
+                for col_i in 0..columns {
+                    let cell = row.get(col_i).map(Vec::as_slice).unwrap_or(&[]);
+                    result.extend(container_tokens("e", cell));
+                }
+                result.push(m_end_token("mr"));
+            }
+            result.push(m_end_token("m"));
+        }
+        MathNode::EquationArray(rows) => {
+            result.push(m_start_token("eqArr", &[]));
+            for row in rows {
+                result.extend(container_tokens("e", row));
+            }
+            result.push(m_end_token("eqArr"));
+        }
+        MathNode::Accent(accent, body) => {
+            result.push(m_start_token("acc", &[]));
+            result.push(m_start_token("accPr", &[]));
+            result.extend(m_empty_tokens("chr", Some(&accent.to_string())));
+            result.push(m_end_token("accPr"));
+            result.extend(container_tokens("e", body));
+            result.push(m_end_token("acc"));
+        }
+        MathNode::Bar { top, body } => {
+            result.push(m_start_token("bar", &[]));
+            result.push(m_start_token("barPr", &[]));
+            result.extend(m_empty_tokens(
+                "pos",
+                Some(if *top { "top" } else { "bot" }),
+            ));
+            result.push(m_end_token("barPr"));
+            result.extend(container_tokens("e", body));
+            result.push(m_end_token("bar"));
+        }
+    }
+
+    result
+}
+
+/// An argument element such as `m:num` or `m:e` with the nodes in it.
+fn container_tokens(tag: &str, nodes: &[MathNode]) -> Vec<Token> {
+    let mut result: Vec<Token> = Vec::new();
+    result.push(m_start_token(tag, &[]));
+    result.extend(nodes_tokens(nodes));
+    result.push(m_end_token(tag));
+    result
+}
+
+/// A math run (`m:r`) in the math font.
+fn run_tokens(text: &str, style: RunStyle) -> Vec<Token> {
+    let mut result: Vec<Token> = Vec::new();
+
+    result.push(m_start_token("r", &[]));
+
+    let (script, plain_style) = match style {
+        RunStyle::Math | RunStyle::Text => (None, None),
+        RunStyle::Upright => (None, Some("p")),
+        RunStyle::Bold => (None, Some("b")),
+        RunStyle::Italic => (None, Some("i")),
+        RunStyle::DoubleStruck => (Some("double-struck"), Some("p")),
+        RunStyle::Script => (Some("script"), Some("p")),
+        RunStyle::Fraktur => (Some("fraktur"), Some("p")),
+        RunStyle::Monospace => (Some("monospace"), Some("p")),
+    };
+    if style != RunStyle::Math {
+        // The children of `m:rPr` have to be in the order of the schema.
+        result.push(m_start_token("rPr", &[]));
+        if style == RunStyle::Text {
+            result.extend(m_empty_tokens("nor", None));
+        }
+        if let Some(script) = script {
+            result.extend(m_empty_tokens("scr", Some(script)));
+        }
+        if let Some(plain_style) = plain_style {
+            result.extend(m_empty_tokens("sty", Some(plain_style)));
+        }
+        result.push(m_end_token("rPr"));
+    }
+
+    result.push(wp_start_token("rPr", &[]));
+    result.extend(wp_empty_tokens(
+        "rFonts",
+        &[("ascii", MATH_FONT), ("hAnsi", MATH_FONT)],
+    ));
+    result.push(wp_end_token("rPr"));
+
+    let preserve_space = vec![owned_attribute(
+        &Some(String::from("xml")),
+        &None,
+        &String::from("space"),
+        &String::from("preserve"),
+    )];
+    result.push(Token {
+        token_type: TokenType::Normal,
+        xml_reader_event: start_tag_event(
+            &Some(String::from("m")),
+            &Some(String::from(NS_OMML)),
+            &String::from("t"),
+            Some(&preserve_space),
+        ),
+        token_text: None,
+    });
+    result.push(Token {
+        token_type: TokenType::Normal,
+        xml_reader_event: xml::reader::XmlEvent::Characters(String::from(text)),
+        token_text: Some(String::from(text)),
+    });
+    result.push(m_end_token("t"));
+    result.push(m_end_token("r"));
+
+    result
+}
+
+/// Shorthand for a start tag token in the `m:` (math) namespace, with all
+/// attributes in the same namespace.
+fn m_start_token(tag_name: &str, attrs: &[(&str, &str)]) -> Token {
+    let prefix = Some(String::from("m"));
+    let ns = Some(String::from(NS_OMML));
+    let attributes: Vec<xml::attribute::OwnedAttribute> = attrs
+        .iter()
+        .map(|(name, value)| {
+            owned_attribute(&prefix, &ns, &String::from(*name), &String::from(*value))
+        })
+        .collect();
+
+    Token {
+        token_type: TokenType::Normal,
+        xml_reader_event: start_tag_event(&prefix, &ns, &String::from(tag_name), Some(&attributes)),
+        token_text: None,
+    }
+}
+
+fn m_end_token(tag_name: &str) -> Token {
+    Token {
+        token_type: TokenType::Normal,
+        xml_reader_event: end_tag_event(
+            &Some(String::from("m")),
+            &Some(String::from(NS_OMML)),
+            &String::from(tag_name),
+        ),
+        token_text: None,
+    }
+}
+
+/// A property element such as `<m:chr m:val="∑"/>`.
+fn m_empty_tokens(tag_name: &str, value: Option<&str>) -> Vec<Token> {
+    let attrs: Vec<(&str, &str)> = value.map(|value| ("val", value)).into_iter().collect();
+    vec![m_start_token(tag_name, &attrs), m_end_token(tag_name)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Text of the runs of a list of nodes.
+    fn text(nodes: &[MathNode]) -> String {
+        nodes
+            .iter()
+            .map(|node| match node {
+                MathNode::Run(text, _) => text.clone(),
+                MathNode::Group(nodes) => text(nodes),
+                _ => String::from("?"),
+            })
+            .collect()
+    }
+
+    /// Whether start and end tags of the tokens are balanced.
+    fn is_balanced(tokens: &[Token]) -> bool {
+        let mut depth: i32 = 0;
+        for token in tokens {
+            match token.xml_reader_event {
+                xml::reader::XmlEvent::StartElement { .. } => depth += 1,
+                xml::reader::XmlEvent::EndElement { .. } => depth -= 1,
+                _ => (),
+            }
+            if depth < 0 {
+                return false;
+            }
+        }
+        depth == 0
+    }
+
+    #[test]
+    fn fraction() {
+        match parse(r"\frac{a+1}{b}").as_slice() {
+            [MathNode::Fraction {
+                numerator,
+                denominator,
+                bar: true,
+            }] => {
+                assert_eq!(text(numerator), "a+1");
+                assert_eq!(text(denominator), "b");
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn scripts_in_either_order() {
+        for latex in [r"x_i^2", r"x^{2}_{i}"].iter() {
+            match parse(latex).as_slice() {
+                [MathNode::Scripts {
+                    base,
+                    sub: Some(sub),
+                    sup: Some(sup),
+                }] => {
+                    assert_eq!(text(base), "x");
+                    assert_eq!(text(sub), "i");
+                    assert_eq!(text(sup), "2");
+                }
+                other => panic!("{}: {:?}", latex, other),
+            }
+        }
+    }
+
+    #[test]
+    fn radical_with_degree() {
+        match parse(r"\sqrt[3]{x}").as_slice() {
+            [MathNode::Radical {
+                degree: Some(degree),
+                body,
+            }] => {
+                assert_eq!(text(degree), "3");
+                assert_eq!(text(body), "x");
+            }
+            other => panic!("{:?}", other),
+        }
+        assert!(matches!(
+            parse(r"\sqrt{x}").as_slice(),
+            [MathNode::Radical { degree: None, .. }]
+        ));
+    }
+
+    #[test]
+    fn sum_body_ends_at_plus() {
+        match parse(r"\sum_{i=1}^{n} i^2 + 1").as_slice() {
+            [MathNode::Nary {
+                operator: '∑',
+                under_over: true,
+                sub: Some(sub),
+                sup: Some(sup),
+                body,
+            }, plus, one] => {
+                assert_eq!(text(sub), "i=1");
+                assert_eq!(text(sup), "n");
+                assert!(matches!(body.as_slice(), [MathNode::Scripts { .. }]));
+                assert_eq!(text(&[plus.clone(), one.clone()]), "+1");
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn integral_body_ends_after_differential() {
+        match parse(r"\int_0^1 x^2 dx = \frac{1}{3}").as_slice() {
+            [MathNode::Nary {
+                operator: '∫',
+                under_over: false,
+                body,
+                ..
+            }, equals, MathNode::Fraction { .. }] => {
+                assert_eq!(text(&body[1..]), "dx");
+                assert_eq!(text(std::slice::from_ref(equals)), "=");
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn matrix() {
+        match parse(r"\begin{pmatrix} a & b \\ c & d \end{pmatrix}").as_slice() {
+            [MathNode::Delimited { open, close, body }] => {
+                assert_eq!((open.as_str(), close.as_str()), ("(", ")"));
+                match body.as_slice() {
+                    [MathNode::Matrix(rows)] => {
+                        let cells: Vec<Vec<String>> = rows
+                            .iter()
+                            .map(|row| row.iter().map(|cell| text(cell)).collect())
+                            .collect();
+                        assert_eq!(cells, vec![vec!["a", "b"], vec!["c", "d"]]);
+                    }
+                    other => panic!("{:?}", other),
+                }
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn cases() {
+        let nodes = parse(r"f(x) = \begin{cases} 1 & x > 0 \\ 0 & \text{otherwise} \end{cases}");
+        match nodes.last() {
+            Some(MathNode::Delimited { open, close, body }) => {
+                assert_eq!((open.as_str(), close.as_str()), ("{", ""));
+                match body.as_slice() {
+                    [MathNode::Matrix(rows)] => {
+                        assert_eq!(rows.len(), 2);
+                        assert!(matches!(
+                            rows[1][1].as_slice(),
+                            [MathNode::Run(text, RunStyle::Text)] if text == "otherwise"
+                        ));
+                    }
+                    other => panic!("{:?}", other),
+                }
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn unbalanced_groups() {
+        assert!(matches!(
+            parse(r"\frac{a").as_slice(),
+            [MathNode::Fraction { denominator, .. }] if denominator.is_empty()
+        ));
+        assert_eq!(text(&parse("{x")), "x");
+        assert_eq!(text(&parse("x}")), "x");
+        assert!(matches!(
+            parse(r"\left( x \right.").as_slice(),
+            [MathNode::Delimited { close, .. }] if close.is_empty()
+        ));
+    }
+
+    #[test]
+    fn tokens_are_balanced() {
+        let inputs = [
+            r"\frac{a+1}{b}",
+            r"x_i^2",
+            r"\sqrt[3]{x}",
+            r"\sum_{i=1}^{n} i^2",
+            r"\int_0^1 x^2 dx",
+            r"\begin{pmatrix} a & b \\ c & d \end{pmatrix}",
+            r"\begin{cases} 1 & x > 0 \\ 0 & \text{otherwise} \end{cases}",
+            r"\frac{a",
+            r"x}",
+            r"\left( x \right.",
+        ];
+        for latex in inputs.iter() {
+            assert!(is_balanced(&math_tokens(latex)), "{}", latex);
+            assert!(is_balanced(&math_paragraph_tokens(latex)), "{}", latex);
+        }
+    }
+}
@@ -1,11 +1,34 @@
+mod ansi;
+pub mod code_block;
 pub mod datakit_table;
+pub mod describe;
+pub mod footnotes;
+pub mod format;
+mod highlight;
+mod html_table;
+pub(crate) mod inline;
 pub mod jupyter_nb;
+pub mod kv_table;
 pub mod markdown;
+pub mod math;
+pub mod numbering;
+pub mod pivot;
+pub(crate) mod styles;
+pub mod table_data;
+pub mod table_style;
+#[cfg(test)]
+pub(crate) mod testing;
 
 use crate::errors::TextkitDocxError;
+use crate::files::FileAccess;
 use crate::print_xml_reader_event;
+use crate::render::{
+    code_block::CodeBlockOptions, datakit_table::TableOptions, footnotes::Footnotes,
+    jupyter_nb::JupyterOptions, markdown::MarkdownStyles, numbering::Numbering, styles::StyleSheet,
+};
 use crate::{
-    ImageFileContents, Token, TokenType, NS_DWML_MAIN, NS_DWML_PIC, NS_REL, NS_WPD_ML, NS_WP_ML,
+    FileContents, ImageFileContents, PageDimensions, Token, TokenType, NS_DWML_MAIN, NS_DWML_PIC,
+    NS_REL, NS_WPD_ML, NS_WP_ML,
 };
 use serde::Serialize;
 use std::collections::BTreeMap;
@@ -15,6 +38,109 @@ use std::path::Path;
 use xml::writer::EmitterConfig;
 use zip::{write::FileOptions, ZipArchive, ZipWriter};
 
+/// Everything besides `word/document.xml` that rendered content adds to the
+/// package: relationships, media files, list numbering and footnotes.
+#[derive(Debug)]
+pub(crate) struct DocumentParts {
+    /// Numerical part of the last relationship id in `word/_rels/document.xml.rels`.
+    pub latest_rels_id: usize,
+    pub images: BTreeMap<String, ImageFileContents>,
+    /// Relationships other than images, e.g. to the numbering part.
+    pub relationships: Vec<Relationship>,
+    pub numbering: Numbering,
+    pub footnotes: Footnotes,
+    /// Problems that did not stop the rendering, e.g. images replaced by
+    /// their alt text.
+    pub warnings: Vec<String>,
+}
+
+impl DocumentParts {
+    /// Reserve the next relationship id.
+    pub(crate) fn next_rels_id(&mut self) -> String {
+        self.latest_rels_id += 1;
+        format!("rId{}", self.latest_rels_id)
+    }
+
+    /// Add a PNG or JPEG image as `word/media/figure-<n>.png` (or `.jpeg`), or
+    /// return `None` if the payload is neither.
+    pub(crate) fn add_image(&mut self, payload: Vec<u8>) -> Option<AddedImage> {
+        let (extension, (width, height)) = match png_dimensions(&payload) {
+            Some(dimensions) => ("png", dimensions),
+            None => ("jpeg", jpeg_dimensions(&payload)?),
+        };
+
+        // Keep the numbering of figures going across notebooks and markdown.
+        let serial_number = self.images.len() + 1;
+        let relationship_id = self.next_rels_id();
+        let filename = format!("figure-{}.{}", serial_number, extension);
+        self.images.insert(
+            relationship_id.clone(),
+            ImageFileContents {
+                file_contents: FileContents { filename, payload },
+                height,
+                width,
+            },
+        );
+
+        Some(AddedImage {
+            relationship_id,
+            serial_number,
+            width,
+            height,
+        })
+    }
+
+    /// Add a relationship and return its id.
+    pub(crate) fn add_relationship(
+        &mut self,
+        rel_type: &str,
+        target: &str,
+        external: bool,
+    ) -> String {
+        let id = self.next_rels_id();
+        self.relationships.push(Relationship {
+            id: id.clone(),
+            rel_type: String::from(rel_type),
+            target: String::from(target),
+            external,
+        });
+        id
+    }
+}
+
+/// Settings of the template that rendered content follows, so that e.g.
+/// tables in markdown are laid out like the ones of the `table` helper.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct RenderSettings<'a> {
+    pub dimensions: &'a PageDimensions,
+    pub table_options: &'a TableOptions,
+    pub code_blocks: CodeBlockOptions,
+    pub jupyter: JupyterOptions,
+    pub styles: &'a StyleSheet,
+    pub markdown_styles: &'a MarkdownStyles,
+    /// Local files that images in markdown may be read from.
+    pub files: &'a FileAccess,
+}
+
+/// An image added to the package, see [`DocumentParts::add_image`].
+#[derive(Debug, Clone)]
+pub(crate) struct AddedImage {
+    pub relationship_id: String,
+    /// Number of the image in the document, starting at 1.
+    pub serial_number: usize,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// A relationship of `word/document.xml` to another part or an external target.
+#[derive(Debug, Clone)]
+pub(crate) struct Relationship {
+    pub id: String,
+    pub rel_type: String,
+    pub target: String,
+    pub external: bool,
+}
+
 pub(crate) fn new_zip_bytes_with_document_xml(
     zip_payload: &mut ZipArchive<Cursor<Vec<u8>>>,
     document_xml: &str,
@@ -137,28 +263,99 @@ pub(crate) fn end_tag_event(
     }
 }
 
-pub(crate) fn paragraph_tokens(contents: &str) -> Vec<Token> {
-    let mut result: Vec<Token> = Vec::new();
-    let paragraphs = split_string_by_empty_line(contents);
+/// Shorthand for a start tag token in the `w:` (word processing) namespace,
+/// with all attributes in the same namespace.
+pub(crate) fn wp_start_token(tag_name: &str, attrs: &[(&str, &str)]) -> Token {
+    let prefix = Some(String::from("w"));
+    let ns = Some(String::from(NS_WP_ML));
+    let attributes: Vec<xml::attribute::OwnedAttribute> = attrs
+        .iter()
+        .map(|(name, value)| {
+            owned_attribute(&prefix, &ns, &String::from(*name), &String::from(*value))
+        })
+        .collect();
 
-    for paragraph in paragraphs {
-        let prequel = paragraph_prequel_tokens();
-        let run_start = run_start_token();
-        let run_end = run_end_token();
-        let chars = char_text_tokens(paragraph, true);
-        let sequel = paragraph_sequel_tokens();
+    Token {
+        token_type: TokenType::Normal,
+        xml_reader_event: start_tag_event(&prefix, &ns, &String::from(tag_name), Some(&attributes)),
+        token_text: None,
+    }
+}
 
-        result.extend(prequel);
-        result.push(run_start);
-        result.extend(chars);
-        result.push(run_end);
-        result.extend(sequel);
+/// Shorthand for an end tag token in the `w:` (word processing) namespace.
+pub(crate) fn wp_end_token(tag_name: &str) -> Token {
+    Token {
+        token_type: TokenType::Normal,
+        xml_reader_event: end_tag_event(
+            &Some(String::from("w")),
+            &Some(String::from(NS_WP_ML)),
+            &String::from(tag_name),
+        ),
+        token_text: None,
     }
+}
 
-    result
+/// Start and end tag tokens of an element without children, e.g. `<w:b/>`.
+pub(crate) fn wp_empty_tokens(tag_name: &str, attrs: &[(&str, &str)]) -> Vec<Token> {
+    vec![wp_start_token(tag_name, attrs), wp_end_token(tag_name)]
+}
+
+/// Is the token the start of the given `w:` element?
+pub(crate) fn is_wp_start_token(token: &Token, tag_name: &str) -> bool {
+    match &token.xml_reader_event {
+        xml::reader::XmlEvent::StartElement { name, .. } => {
+            name.local_name == tag_name && name.namespace.as_deref() == Some(NS_WP_ML)
+        }
+        _ => false,
+    }
+}
+
+/// Is the token the end of the given `w:` element?
+pub(crate) fn is_wp_end_token(token: &Token, tag_name: &str) -> bool {
+    match &token.xml_reader_event {
+        xml::reader::XmlEvent::EndElement { name } => {
+            name.local_name == tag_name && name.namespace.as_deref() == Some(NS_WP_ML)
+        }
+        _ => false,
+    }
+}
+
+/// Value of a `w:` attribute of a start tag token, e.g. `w:val`.
+pub(crate) fn wp_attribute<'a>(token: &'a Token, attr_name: &str) -> Option<&'a str> {
+    match &token.xml_reader_event {
+        xml::reader::XmlEvent::StartElement { attributes, .. } => attributes
+            .iter()
+            .find(|attr| {
+                attr.name.local_name == attr_name
+                    && attr.name.namespace.as_deref() == Some(NS_WP_ML)
+            })
+            .map(|attr| attr.value.as_str()),
+        _ => None,
+    }
 }
 
-pub(crate) fn monospace_paragraph_tokens(contents: &str) -> Vec<Token> {
+/// Given the index of a `w:` start tag, find the index of the matching end tag,
+/// taking nested elements of the same name into account.
+pub(crate) fn matching_end_index(
+    tokens: &[Token],
+    start_index: usize,
+    tag_name: &str,
+) -> Option<usize> {
+    let mut depth: usize = 0;
+    for (i, token) in tokens.iter().enumerate().skip(start_index) {
+        if is_wp_start_token(token, tag_name) {
+            depth += 1;
+        } else if is_wp_end_token(token, tag_name) {
+            depth -= 1;
+            if depth == 0 {
+                return Some(i);
+            }
+        }
+    }
+    None
+}
+
+pub(crate) fn paragraph_tokens(contents: &str) -> Vec<Token> {
     let mut result: Vec<Token> = Vec::new();
     let paragraphs = split_string_by_empty_line(contents);
 
@@ -170,264 +367,7 @@ pub(crate) fn monospace_paragraph_tokens(contents: &str) -> Vec<Token> {
         let sequel = paragraph_sequel_tokens();
 
         result.extend(prequel);
-
-        /* add this to paragraph prequel
-        <w:pPr>
-            <w:rPr>
-                <w:rFonts w:ascii="Consolas" w:hAnsi="Consolas"/>
-                <w:sz w:val="16"/>
-                <w:szCs w:val="16"/>
-            </w:rPr>
-        </w:pPr>
-        */
-        result.push(Token {
-            token_type: TokenType::Normal,
-            xml_reader_event: start_tag_event(
-                &Some(String::from("w")),
-                &Some(String::from(NS_WP_ML)),
-                &String::from("pPr"),
-                None,
-            ),
-            token_text: None,
-        });
-        result.push(Token {
-            token_type: TokenType::Normal,
-            xml_reader_event: start_tag_event(
-                &Some(String::from("w")),
-                &Some(String::from(NS_WP_ML)),
-                &String::from("rPr"),
-                None,
-            ),
-            token_text: None,
-        });
-        result.push(Token {
-            token_type: TokenType::Normal,
-            // xml_reader_event: start_tag_event(
-            //     "rFonts",
-            //     Some(&[("w", "ascii", "Consolas"), ("w", "hAnsi", "Consolas")]),
-            // ),
-            xml_reader_event: start_tag_event(
-                &Some(String::from("w")),
-                &Some(String::from(NS_WP_ML)),
-                &String::from("rFonts"),
-                Some(&vec![
-                    owned_attribute(
-                        &Some(String::from("w")),
-                        &Some(String::from(NS_WP_ML)),
-                        &String::from("ascii"),
-                        &String::from("Consolas"),
-                    ),
-                    owned_attribute(
-                        &Some(String::from("w")),
-                        &Some(String::from(NS_WP_ML)),
-                        &String::from("hAnsi"),
-                        &String::from("Consolas"),
-                    ),
-                ]),
-            ),
-            token_text: None,
-        });
-        result.push(Token {
-            token_type: TokenType::Normal,
-            xml_reader_event: end_tag_event(
-                &Some(String::from("w")),
-                &Some(String::from(NS_WP_ML)),
-                &String::from("rFonts"),
-            ),
-            token_text: None,
-        });
-        result.push(Token {
-            token_type: TokenType::Normal,
-            // xml_reader_event: start_tag_event("sz", Some(&[("w", "val", "16")])),
-            xml_reader_event: start_tag_event(
-                &Some(String::from("w")),
-                &Some(String::from(NS_WP_ML)),
-                &String::from("sz"),
-                Some(&vec![owned_attribute(
-                    &Some(String::from("w")),
-                    &Some(String::from(NS_WP_ML)),
-                    &String::from("val"),
-                    &String::from("16"),
-                )]),
-            ),
-            token_text: None,
-        });
-        result.push(Token {
-            token_type: TokenType::Normal,
-            // xml_reader_event: end_tag_event("sz"),
-            xml_reader_event: end_tag_event(
-                &Some(String::from("w")),
-                &Some(String::from(NS_WP_ML)),
-                &String::from("sz"),
-            ),
-            token_text: None,
-        });
-        result.push(Token {
-            token_type: TokenType::Normal,
-            // xml_reader_event: start_tag_event("szCs", Some(&[("w", "val", "16")])),
-            xml_reader_event: start_tag_event(
-                &Some(String::from("w")),
-                &Some(String::from(NS_WP_ML)),
-                &String::from("szCs"),
-                Some(&vec![owned_attribute(
-                    &Some(String::from("w")),
-                    &Some(String::from(NS_WP_ML)),
-                    &String::from("val"),
-                    &String::from("16"),
-                )]),
-            ),
-            token_text: None,
-        });
-        result.push(Token {
-            token_type: TokenType::Normal,
-            // xml_reader_event: end_tag_event("szCs"),
-            xml_reader_event: end_tag_event(
-                &Some(String::from("w")),
-                &Some(String::from(NS_WP_ML)),
-                &String::from("szCs"),
-            ),
-            token_text: None,
-        });
-        result.push(Token {
-            token_type: TokenType::Normal,
-            // xml_reader_event: end_tag_event("rPr"),
-            xml_reader_event: end_tag_event(
-                &Some(String::from("w")),
-                &Some(String::from(NS_WP_ML)),
-                &String::from("rPr"),
-            ),
-            token_text: None,
-        });
-        result.push(Token {
-            token_type: TokenType::Normal,
-            // xml_reader_event: end_tag_event("pPr"),
-            xml_reader_event: end_tag_event(
-                &Some(String::from("w")),
-                &Some(String::from(NS_WP_ML)),
-                &String::from("pPr"),
-            ),
-            token_text: None,
-        });
-
         result.push(run_start);
-
-        /* add this to run prequel
-        <w:rPr>
-            <w:rFonts w:ascii="Consolas" w:hAnsi="Consolas"/>
-            <w:sz w:val="16"/>
-            <w:szCs w:val="16"/>
-        </w:rPr>
-        */
-        result.push(Token {
-            token_type: TokenType::Normal,
-            // xml_reader_event: start_tag_event("rPr", None),
-            xml_reader_event: start_tag_event(
-                &Some(String::from("w")),
-                &Some(String::from(NS_WP_ML)),
-                &String::from("rPr"),
-                None,
-            ),
-            token_text: None,
-        });
-        result.push(Token {
-            token_type: TokenType::Normal,
-            // xml_reader_event: start_tag_event(
-            //     "rFonts",
-            //     Some(&[("w", "ascii", "Consolas"), ("w", "hAnsi", "Consolas")]),
-            // ),
-            xml_reader_event: start_tag_event(
-                &Some(String::from("w")),
-                &Some(String::from(NS_WP_ML)),
-                &String::from("rFonts"),
-                Some(&vec![
-                    owned_attribute(
-                        &Some(String::from("w")),
-                        &Some(String::from(NS_WP_ML)),
-                        &String::from("ascii"),
-                        &String::from("Consolas"),
-                    ),
-                    owned_attribute(
-                        &Some(String::from("w")),
-                        &Some(String::from(NS_WP_ML)),
-                        &String::from("hAnsi"),
-                        &String::from("Consolas"),
-                    ),
-                ]),
-            ),
-            token_text: None,
-        });
-        result.push(Token {
-            token_type: TokenType::Normal,
-            // xml_reader_event: end_tag_event("rFonts"),
-            xml_reader_event: end_tag_event(
-                &Some(String::from("w")),
-                &Some(String::from(NS_WP_ML)),
-                &String::from("rFonts"),
-            ),
-            token_text: None,
-        });
-        result.push(Token {
-            token_type: TokenType::Normal,
-            // xml_reader_event: start_tag_event("sz", Some(&[("w", "val", "16")])),
-            xml_reader_event: start_tag_event(
-                &Some(String::from("w")),
-                &Some(String::from(NS_WP_ML)),
-                &String::from("sz"),
-                Some(&vec![owned_attribute(
-                    &Some(String::from("w")),
-                    &Some(String::from(NS_WP_ML)),
-                    &String::from("val"),
-                    &String::from("16"),
-                )]),
-            ),
-            token_text: None,
-        });
-        result.push(Token {
-            token_type: TokenType::Normal,
-            // xml_reader_event: end_tag_event("sz"),
-            xml_reader_event: end_tag_event(
-                &Some(String::from("w")),
-                &Some(String::from(NS_WP_ML)),
-                &String::from("sz"),
-            ),
-            token_text: None,
-        });
-        result.push(Token {
-            token_type: TokenType::Normal,
-            // xml_reader_event: start_tag_event("szCs", Some(&[("w", "val", "16")])),
-            xml_reader_event: start_tag_event(
-                &Some(String::from("w")),
-                &Some(String::from(NS_WP_ML)),
-                &String::from("szCs"),
-                Some(&vec![owned_attribute(
-                    &Some(String::from("w")),
-                    &Some(String::from(NS_WP_ML)),
-                    &String::from("val"),
-                    &String::from("16"),
-                )]),
-            ),
-            token_text: None,
-        });
-        result.push(Token {
-            token_type: TokenType::Normal,
-            // xml_reader_event: end_tag_event("szCs"),
-            xml_reader_event: end_tag_event(
-                &Some(String::from("w")),
-                &Some(String::from(NS_WP_ML)),
-                &String::from("szCs"),
-            ),
-            token_text: None,
-        });
-        result.push(Token {
-            token_type: TokenType::Normal,
-            // xml_reader_event: end_tag_event("rPr"),
-            xml_reader_event: end_tag_event(
-                &Some(String::from("w")),
-                &Some(String::from(NS_WP_ML)),
-                &String::from("rPr"),
-            ),
-            token_text: None,
-        });
         result.extend(chars);
         result.push(run_end);
         result.extend(sequel);
@@ -648,6 +588,29 @@ pub(crate) fn image_paragraph_tokens(
 ) -> Vec<Token> {
     let mut result: Vec<Token> = Vec::with_capacity(60);
 
+    result.extend(paragraph_prequel_tokens());
+    result.extend(image_run_tokens(
+        relationship_id,
+        width,
+        height,
+        serial_number_in_document,
+        None,
+    ));
+    result.extend(paragraph_sequel_tokens());
+
+    result
+}
+
+/// A run with an inline picture, optionally with a description (alt text).
+pub(crate) fn image_run_tokens(
+    relationship_id: &str,
+    width: u32,
+    height: u32,
+    serial_number_in_document: usize,
+    description: Option<&str>,
+) -> Vec<Token> {
+    let mut result: Vec<Token> = Vec::with_capacity(60);
+
     let width_emu_attr = format!("{}", pixels_to_word_emu(width));
     let height_emu_attr = format!("{}", pixels_to_word_emu(height));
 
@@ -655,7 +618,6 @@ pub(crate) fn image_paragraph_tokens(
                                                         // serial ids.
     let figure_name = format!("Figure {}", serial_number_in_document);
 
-    result.extend(paragraph_prequel_tokens());
     result.push(run_start_token());
     result.push(Token {
         token_type: TokenType::Normal,
@@ -777,16 +739,25 @@ pub(crate) fn image_paragraph_tokens(
     });
 
     // TODO THIS NEEDS TO BE UPDATED
+    let mut doc_pr_attributes = vec![
+        owned_attribute(&None, &None, &String::from("id"), &format!("{}", serial_id)),
+        owned_attribute(&None, &None, &String::from("name"), &figure_name),
+    ];
+    if let Some(description) = description {
+        doc_pr_attributes.push(owned_attribute(
+            &None,
+            &None,
+            &String::from("descr"),
+            &String::from(description),
+        ));
+    }
     result.push(Token {
         token_type: TokenType::Normal,
         xml_reader_event: start_tag_event(
             &Some(String::from("wp")),
             &Some(String::from(NS_WPD_ML)),
             &String::from("docPr"),
-            Some(&vec![
-                owned_attribute(&None, &None, &String::from("id"), &format!("{}", serial_id)),
-                owned_attribute(&None, &None, &String::from("name"), &figure_name),
-            ]),
+            Some(&doc_pr_attributes),
         ), // TODO ! Id is weird here
         token_text: None,
     });
@@ -1341,9 +1312,7 @@ pub(crate) fn image_paragraph_tokens(
     });
 
     result.push(run_end_token());
-    result.extend(paragraph_sequel_tokens());
 
-    //
     result
 }
 
@@ -1429,7 +1398,128 @@ pub(crate) fn insert_images_in_document_xml_rels(
     result
 }
 
+pub(crate) fn insert_relationships_in_document_xml_rels(
+    document_xml_rels_tokens: &[Token],
+    relationships: &[Relationship],
+) -> Vec<Token> {
+    let mut result: Vec<Token> = Vec::new();
+
+    let len = document_xml_rels_tokens.len();
+    let prequel = &document_xml_rels_tokens[..(len - 2)];
+    let sequel = &document_xml_rels_tokens[(len - 2)..];
+
+    result.extend(Vec::from(prequel));
+
+    for relationship in relationships.iter() {
+        let mut attributes = vec![
+            owned_attribute(&None, &None, &String::from("Id"), &relationship.id),
+            owned_attribute(&None, &None, &String::from("Type"), &relationship.rel_type),
+            owned_attribute(&None, &None, &String::from("Target"), &relationship.target),
+        ];
+        if relationship.external {
+            attributes.push(owned_attribute(
+                &None,
+                &None,
+                &String::from("TargetMode"),
+                &String::from("External"),
+            ));
+        }
+        result.push(Token {
+            token_type: TokenType::Normal,
+            xml_reader_event: start_tag_event(
+                &None,
+                &None,
+                &String::from("Relationship"),
+                Some(&attributes),
+            ),
+            token_text: None,
+        });
+        result.push(Token {
+            token_type: TokenType::Normal,
+            xml_reader_event: end_tag_event(&None, &None, &String::from("Relationship")),
+            token_text: None,
+        })
+    }
+
+    result.extend(Vec::from(sequel));
+    result
+}
+
+/// Declare the content type of a part that was added to the package.
+pub(crate) fn insert_content_type_override(
+    content_type_tokens: &[Token],
+    part_name: &str,
+    content_type: &str,
+) -> Vec<Token> {
+    let mut result: Vec<Token> = Vec::new();
+
+    let len = content_type_tokens.len();
+    let prequel = &content_type_tokens[..(len - 2)];
+    let sequel = &content_type_tokens[(len - 2)..];
+
+    result.extend(Vec::from(prequel));
+    result.push(Token {
+        token_type: TokenType::Normal,
+        xml_reader_event: start_tag_event(
+            &None,
+            &None,
+            &String::from("Override"),
+            Some(&vec![
+                owned_attribute(
+                    &None,
+                    &None,
+                    &String::from("PartName"),
+                    &String::from(part_name),
+                ),
+                owned_attribute(
+                    &None,
+                    &None,
+                    &String::from("ContentType"),
+                    &String::from(content_type),
+                ),
+            ]),
+        ),
+        token_text: None,
+    });
+    result.push(Token {
+        token_type: TokenType::Normal,
+        xml_reader_event: end_tag_event(&None, &None, &String::from("Override")),
+        token_text: None,
+    });
+    result.extend(Vec::from(sequel));
+
+    result
+}
+
 pub(crate) fn insert_png_content_type(content_type_tokens: &[Token]) -> Vec<Token> {
+    insert_default_content_type(content_type_tokens, "png", "image/png")
+}
+
+/// Declare the content type of the parts with the given extension, unless the
+/// package already does.
+pub(crate) fn insert_default_content_type(
+    content_type_tokens: &[Token],
+    extension: &str,
+    content_type: &str,
+) -> Vec<Token> {
+    let declared = content_type_tokens
+        .iter()
+        .any(|token| match &token.xml_reader_event {
+            xml::reader::XmlEvent::StartElement {
+                name, attributes, ..
+            } => {
+                name.local_name == "Default"
+                    && attributes.iter().any(|attribute| {
+                        attribute.name.local_name == "Extension"
+                            && attribute.value.eq_ignore_ascii_case(extension)
+                    })
+            }
+            _ => false,
+        });
+    if declared {
+        return Vec::from(content_type_tokens);
+    }
+
     let mut result: Vec<Token> = Vec::new();
     let prequel = &content_type_tokens[..2];
     let sequel = &content_type_tokens[2..];
@@ -1446,13 +1536,13 @@ pub(crate) fn insert_png_content_type(content_type_tokens: &[Token]) -> Vec<Toke
                     &None,
                     &None,
                     &String::from("Extension"),
-                    &String::from("png"),
+                    &String::from(extension),
                 ),
                 owned_attribute(
                     &None,
                     &None,
                     &String::from("ContentType"),
-                    &String::from("image/png"),
+                    &String::from(content_type),
                 ),
             ]),
         ),
@@ -1544,6 +1634,47 @@ pub(crate) fn write_token_vector_to_string(
     Ok(result)
 }
 
+fn png_dimensions(png_payload: &[u8]) -> Option<(u32, u32)> {
+    let cursor = Cursor::new(png_payload);
+    let decoder = png::Decoder::new(cursor);
+    let (info, _) = decoder.read_info().ok()?;
+    Some((info.width, info.height))
+}
+
+/// Width and height of a JPEG image, from its first start-of-frame segment.
+fn jpeg_dimensions(jpeg_payload: &[u8]) -> Option<(u32, u32)> {
+    if !jpeg_payload.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+
+    let mut index = 2;
+    while index + 4 <= jpeg_payload.len() {
+        if jpeg_payload[index] != 0xFF {
+            return None;
+        }
+        let marker = jpeg_payload[index + 1];
+        if marker == 0xFF {
+            // Fill byte before a marker.
+            index += 1;
+            continue;
+        }
+        let length =
+            usize::from(jpeg_payload[index + 2]) << 8 | usize::from(jpeg_payload[index + 3]);
+        // SOF0 to SOF15, except DHT (C4), JPG (C8) and DAC (CC).
+        if (0xC0..=0xCF).contains(&marker) && ![0xC4, 0xC8, 0xCC].contains(&marker) {
+            let frame = jpeg_payload.get(index + 4..index + 2 + length)?;
+            if frame.len() < 5 {
+                return None;
+            }
+            let height = u32::from(frame[1]) << 8 | u32::from(frame[2]);
+            let width = u32::from(frame[3]) << 8 | u32::from(frame[4]);
+            return Some((width, height));
+        }
+        index += 2 + length;
+    }
+    None
+}
+
 fn pixels_to_word_emu(pixels: u32) -> u32 {
     pixels * 914_400 / 72 + 2540
 }
@@ -0,0 +1,291 @@
+//! Numbering definitions (`word/numbering.xml`) for bulleted and numbered
+//! lists. Definitions of the template are reused where they fit, missing ones
+//! are added to the template's numbering part (or to a new one).
+//!
+
+use crate::errors::TextkitDocxError;
+use crate::parse::xml_to_token_vec;
+use crate::render::{
+    is_wp_end_token, is_wp_start_token, wp_attribute, wp_empty_tokens, wp_end_token,
+    wp_start_token, write_token_vector_to_string,
+};
+use crate::Token;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Relationship type of the numbering part.
+pub(crate) const REL_TYPE_NUMBERING: &str =
+    "http://schemas.openxmlformats.org/officeDocument/2006/relationships/numbering";
+
+/// Content type of the numbering part.
+pub(crate) const CONTENT_TYPE_NUMBERING: &str =
+    "application/vnd.openxmlformats-officedocument.wordprocessingml.numbering+xml";
+
+/// Numbering part used when the template has none.
+const EMPTY_NUMBERING_XML: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<w:numbering xmlns:w="http://schemas.openxmlformats.org/wordprocessingml/2006/main"></w:numbering>"#;
+
+/// Word supports nine list levels (`w:ilvl` 0 to 8).
+pub(crate) const MAX_LIST_LEVELS: usize = 9;
+
+/// Indentation per list level and hanging indentation of the bullet or
+/// number, in twentieths of a point.
+pub(crate) const LIST_INDENT: usize = 720;
+const LIST_HANGING: usize = 360;
+
+/// Bullets of the created bullet definition, repeating every three levels.
+const BULLETS: [&str; 3] = ["\u{2022}", "\u{25E6}", "\u{25AA}"];
+
+/// Number formats of the created ordered definition, repeating every three levels.
+const NUMBER_FORMATS: [&str; 3] = ["decimal", "lowerLetter", "lowerRoman"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ListKind {
+    Bullet,
+    Ordered,
+}
+
+/// The numbering definitions of a document, and the ones added while rendering.
+#[derive(Debug)]
+pub(crate) struct Numbering {
+    tokens: Vec<Token>,
+    bullet_abstract_id: Option<usize>,
+    ordered_abstract_id: Option<usize>,
+    bullet_num_id: Option<usize>,
+    next_abstract_id: usize,
+    next_num_id: usize,
+    new_abstract_nums: Vec<Token>,
+    new_nums: Vec<Token>,
+}
+
+impl Numbering {
+    /// Read the numbering definitions of a template, if it has any.
+    pub(crate) fn from_xml(numbering_xml: Option<&str>) -> Result<Self, TextkitDocxError> {
+        let tokens = xml_to_token_vec(numbering_xml.unwrap_or(EMPTY_NUMBERING_XML))?;
+
+        // Format of the first level of each abstract numbering. Definitions tied
+        // to styles (e.g. heading numbering) are not suitable for plain lists.
+        let mut first_level_formats: BTreeMap<usize, Option<String>> = BTreeMap::new();
+        let mut style_bound: BTreeSet<usize> = BTreeSet::new();
+        let mut num_abstract_ids: Vec<(usize, usize)> = Vec::new();
+        let mut next_abstract_id: usize = 0;
+        let mut next_num_id: usize = 1;
+
+        let mut abstract_id: Option<usize> = None;
+        let mut level: Option<String> = None;
+        let mut num_id: Option<usize> = None;
+
+        for token in tokens.iter() {
+            let id_attribute = |attr_name: &str| {
+                wp_attribute(token, attr_name).and_then(|value| value.parse::<usize>().ok())
+            };
+
+            if is_wp_start_token(token, "abstractNum") {
+                abstract_id = id_attribute("abstractNumId");
+                if let Some(id) = abstract_id {
+                    next_abstract_id = next_abstract_id.max(id + 1);
+                    first_level_formats.insert(id, None);
+                }
+            } else if is_wp_end_token(token, "abstractNum") {
+                abstract_id = None;
+            } else if is_wp_start_token(token, "lvl") {
+                level = wp_attribute(token, "ilvl").map(String::from);
+            } else if is_wp_end_token(token, "lvl") {
+                level = None;
+            } else if is_wp_start_token(token, "numFmt") && level.as_deref() == Some("0") {
+                if let (Some(id), Some(format)) = (abstract_id, wp_attribute(token, "val")) {
+                    first_level_formats.insert(id, Some(String::from(format)));
+                }
+            } else if is_wp_start_token(token, "pStyle")
+                || is_wp_start_token(token, "styleLink")
+                || is_wp_start_token(token, "numStyleLink")
+            {
+                if let Some(id) = abstract_id {
+                    style_bound.insert(id);
+                }
+            } else if is_wp_start_token(token, "num") {
+                num_id = id_attribute("numId");
+                if let Some(id) = num_id {
+                    next_num_id = next_num_id.max(id + 1);
+                }
+            } else if is_wp_end_token(token, "num") {
+                num_id = None;
+            } else if is_wp_start_token(token, "abstractNumId") {
+                if let (Some(num), Some(id)) = (num_id, id_attribute("val")) {
+                    num_abstract_ids.push((num, id));
+                }
+            }
+        }
+
+        let abstract_with_format = |format: &str| {
+            first_level_formats
+                .iter()
+                .find(|(id, first_format)| {
+                    first_format.as_deref() == Some(format) && !style_bound.contains(id)
+                })
+                .map(|(id, _)| *id)
+        };
+        let bullet_abstract_id = abstract_with_format("bullet");
+        let ordered_abstract_id = abstract_with_format("decimal");
+        let bullet_num_id = num_abstract_ids
+            .iter()
+            .find(|(_, abstract_id)| Some(*abstract_id) == bullet_abstract_id)
+            .map(|(num_id, _)| *num_id);
+
+        Ok(Self {
+            tokens,
+            bullet_abstract_id,
+            ordered_abstract_id,
+            bullet_num_id,
+            next_abstract_id,
+            next_num_id,
+            new_abstract_nums: Vec::new(),
+            new_nums: Vec::new(),
+        })
+    }
+
+    /// Were any definitions added while rendering?
+    pub(crate) fn is_modified(&self) -> bool {
+        !self.new_nums.is_empty()
+    }
+
+    /// Numbering id shared by all bulleted lists.
+    pub(crate) fn bullet_num_id(&mut self) -> usize {
+        if let Some(num_id) = self.bullet_num_id {
+            return num_id;
+        }
+        let abstract_id = self.abstract_id(ListKind::Bullet);
+        let num_id = self.add_num(abstract_id, None);
+        self.bullet_num_id = Some(num_id);
+        num_id
+    }
+
+    /// A new numbering id for an ordered list at the given level, so that
+    /// every list counts from its own start number.
+    pub(crate) fn ordered_num_id(&mut self, level: usize, start: u64) -> usize {
+        let abstract_id = self.abstract_id(ListKind::Ordered);
+        self.add_num(abstract_id, Some((level, start)))
+    }
+
+    /// The numbering part with the added definitions. Abstract numberings go
+    /// after the existing ones and before the first `w:num`, numbering
+    /// instances at the end, as the schema requires.
+    pub(crate) fn to_xml(&self) -> Result<String, TextkitDocxError> {
+        let first_num_index = self
+            .tokens
+            .iter()
+            .position(|token| {
+                is_wp_start_token(token, "num") || is_wp_start_token(token, "numIdMacAtCleanup")
+            })
+            .or_else(|| {
+                self.tokens
+                    .iter()
+                    .position(|token| is_wp_end_token(token, "numbering"))
+            })
+            .ok_or(TextkitDocxError::FailedWriteXml)?;
+        let nums_end_index = self
+            .tokens
+            .iter()
+            .position(|token| {
+                is_wp_start_token(token, "numIdMacAtCleanup") || is_wp_end_token(token, "numbering")
+            })
+            .ok_or(TextkitDocxError::FailedWriteXml)?;
+
+        let mut result: Vec<Token> = Vec::new();
+        result.extend_from_slice(&self.tokens[..first_num_index]);
+        result.extend(self.new_abstract_nums.iter().cloned());
+        result.extend_from_slice(&self.tokens[first_num_index..nums_end_index]);
+        result.extend(self.new_nums.iter().cloned());
+        result.extend_from_slice(&self.tokens[nums_end_index..]);
+
+        write_token_vector_to_string(&result)
+    }
+
+    fn abstract_id(&mut self, kind: ListKind) -> usize {
+        let existing = match kind {
+            ListKind::Bullet => self.bullet_abstract_id,
+            ListKind::Ordered => self.ordered_abstract_id,
+        };
+        if let Some(abstract_id) = existing {
+            return abstract_id;
+        }
+
+        let abstract_id = self.next_abstract_id;
+        self.next_abstract_id += 1;
+        self.new_abstract_nums
+            .extend(abstract_num_tokens(abstract_id, kind));
+        match kind {
+            ListKind::Bullet => self.bullet_abstract_id = Some(abstract_id),
+            ListKind::Ordered => self.ordered_abstract_id = Some(abstract_id),
+        }
+        abstract_id
+    }
+
+    fn add_num(&mut self, abstract_id: usize, start_override: Option<(usize, u64)>) -> usize {
+        let num_id = self.next_num_id;
+        self.next_num_id += 1;
+
+        self.new_nums
+            .push(wp_start_token("num", &[("numId", &format!("{}", num_id))]));
+        self.new_nums.extend(wp_empty_tokens(
+            "abstractNumId",
+            &[("val", &format!("{}", abstract_id))],
+        ));
+        if let Some((level, start)) = start_override {
+            self.new_nums.push(wp_start_token(
+                "lvlOverride",
+                &[("ilvl", &format!("{}", level))],
+            ));
+            self.new_nums.extend(wp_empty_tokens(
+                "startOverride",
+                &[("val", &format!("{}", start))],
+            ));
+            self.new_nums.push(wp_end_token("lvlOverride"));
+        }
+        self.new_nums.push(wp_end_token("num"));
+
+        num_id
+    }
+}
+
+/// An abstract numbering with all nine levels, indented by [`LIST_INDENT`] per level.
+fn abstract_num_tokens(abstract_id: usize, kind: ListKind) -> Vec<Token> {
+    let mut result: Vec<Token> = Vec::new();
+
+    result.push(wp_start_token(
+        "abstractNum",
+        &[("abstractNumId", &format!("{}", abstract_id))],
+    ));
+    result.extend(wp_empty_tokens(
+        "multiLevelType",
+        &[("val", "hybridMultilevel")],
+    ));
+
+    for level in 0..MAX_LIST_LEVELS {
+        let (format, text) = match kind {
+            ListKind::Bullet => ("bullet", String::from(BULLETS[level % BULLETS.len()])),
+            ListKind::Ordered => (
+                NUMBER_FORMATS[level % NUMBER_FORMATS.len()],
+                format!("%{}.", level + 1),
+            ),
+        };
+
+        result.push(wp_start_token("lvl", &[("ilvl", &format!("{}", level))]));
+        result.extend(wp_empty_tokens("start", &[("val", "1")]));
+        result.extend(wp_empty_tokens("numFmt", &[("val", format)]));
+        result.extend(wp_empty_tokens("lvlText", &[("val", &text)]));
+        result.extend(wp_empty_tokens("lvlJc", &[("val", "left")]));
+        result.push(wp_start_token("pPr", &[]));
+        result.extend(wp_empty_tokens(
+            "ind",
+            &[
+                ("left", &format!("{}", LIST_INDENT * (level + 1))),
+                ("hanging", &format!("{}", LIST_HANGING)),
+            ],
+        ));
+        result.push(wp_end_token("pPr"));
+        result.push(wp_end_token("lvl"));
+    }
+
+    result.push(wp_end_token("abstractNum"));
+    result
+}
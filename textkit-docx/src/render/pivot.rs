@@ -0,0 +1,505 @@
+//! Pivot tables and group-by summaries, e.g. the sum of revenue per region
+//! and quarter, computed from a table before it is rendered.
+//!
+
+use crate::errors::TextkitDocxError;
+use crate::parse::{option_bool, option_list};
+use crate::render::format::{format_value, ValueFormat};
+use crate::render::table_data::{compare_values, sum_values, value_as_f64, TableData};
+use datakit::{value::definitions::*, value::primitives::*};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+
+/// How the values in a group are combined into a single value.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Aggregation {
+    Count,
+    Sum,
+    Mean,
+    Median,
+    Min,
+    Max,
+}
+
+impl Aggregation {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "count" | "size" => Some(Self::Count),
+            "sum" => Some(Self::Sum),
+            "mean" | "avg" | "average" => Some(Self::Mean),
+            "median" => Some(Self::Median),
+            "min" => Some(Self::Min),
+            "max" => Some(Self::Max),
+            _ => None,
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            Self::Count => "count",
+            Self::Sum => "sum",
+            Self::Mean => "mean",
+            Self::Median => "median",
+            Self::Min => "min",
+            Self::Max => "max",
+        }
+    }
+
+    /// Combine the (non-missing) values of a group. Sums, means and medians
+    /// only consider numbers; `None` means there was nothing to combine.
+    fn apply(&self, values: &[&Value]) -> Option<Value> {
+        let numbers: Vec<f64> = values
+            .iter()
+            .filter_map(|value| value_as_f64(value))
+            .collect();
+
+        match self {
+            Self::Count => Some(Value::Number(Numeric::Integer(values.len() as i64))),
+            Self::Sum if numbers.is_empty() => None,
+            Self::Sum => Some(sum_values(values)),
+            Self::Mean if numbers.is_empty() => None,
+            Self::Mean => {
+                let mean = numbers.iter().sum::<f64>() / numbers.len() as f64;
+                Some(Value::Number(Numeric::Real(mean)))
+            }
+            Self::Median if numbers.is_empty() => None,
+            Self::Median => {
+                let mut sorted = numbers;
+                sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+                let middle = sorted.len() / 2;
+                let median = match sorted.len() % 2 {
+                    0 => (sorted[middle - 1] + sorted[middle]) / 2.0,
+                    _ => sorted[middle],
+                };
+                Some(Value::Number(Numeric::Real(median)))
+            }
+            Self::Min => values
+                .iter()
+                .min_by(|a, b| compare_values(a, b))
+                .map(|value| (*value).clone()),
+            Self::Max => values
+                .iter()
+                .max_by(|a, b| compare_values(a, b))
+                .map(|value| (*value).clone()),
+        }
+    }
+}
+
+/// What to summarize: group the rows by the `rows` columns (one output row
+/// per distinct combination) and the `cols` columns (one output column per
+/// distinct combination), and aggregate each of the `values` columns with each
+/// of the `aggregations`. All columns are given by name or 1-based number.
+#[derive(Debug, Clone)]
+pub struct PivotSpec {
+    pub rows: Vec<String>,
+    pub cols: Vec<String>,
+    pub values: Vec<String>,
+    pub aggregations: Vec<Aggregation>,
+
+    /// Add a `Total` row (and a `Total` column per value if there are `cols`).
+    pub totals: bool,
+}
+
+impl PivotSpec {
+    /// Take the pivot options (`rows`, `cols`, `values`, `agg` and `totals`)
+    /// out of the hash arguments of a `{{pivot ...}}` placeholder, leaving the
+    /// table options. Without `agg`, values are summed, and rows are counted if
+    /// there are no values either.
+    pub(crate) fn take_from_hash_args(
+        hash_args: &mut BTreeMap<String, serde_json::Value>,
+    ) -> Result<Self, TextkitDocxError> {
+        let mut take_list = |key: &str| {
+            hash_args
+                .remove(key)
+                .map(|value| option_list(&value))
+                .unwrap_or_default()
+        };
+
+        let rows = take_list("rows");
+        let cols = take_list("cols");
+        let values = take_list("values");
+        let aggregations = take_list("agg")
+            .iter()
+            .map(|name| {
+                Aggregation::from_name(name)
+                    .ok_or_else(|| TextkitDocxError::BadHelperOption(format!("agg={}", name)))
+            })
+            .collect::<Result<Vec<Aggregation>, _>>()?;
+        let aggregations = match (aggregations.is_empty(), values.is_empty()) {
+            (false, _) => aggregations,
+            (true, true) => vec![Aggregation::Count],
+            (true, false) => vec![Aggregation::Sum],
+        };
+
+        let totals = match hash_args.remove("totals") {
+            Some(value) => option_bool(&value)
+                .ok_or_else(|| TextkitDocxError::BadHelperOption(format!("totals={}", value)))?,
+            None => false,
+        };
+
+        Ok(Self {
+            rows,
+            cols,
+            values,
+            aggregations,
+            totals,
+        })
+    }
+}
+
+/// A distinct combination of values of the grouping columns, along with the
+/// rows that have it.
+struct Group {
+    key: Vec<Option<Value>>,
+    row_indices: Vec<usize>,
+}
+
+/// Compute the pivot table described by `spec`.
+pub fn pivot_table(table: &TableData, spec: &PivotSpec) -> Result<TableData, TextkitDocxError> {
+    let resolve = |names: &[String]| {
+        names
+            .iter()
+            .map(|name| {
+                table.column_index(name).ok_or_else(|| {
+                    TextkitDocxError::BadHelperOption(format!("unknown column '{}'", name))
+                })
+            })
+            .collect::<Result<Vec<usize>, _>>()
+    };
+    let row_cols = resolve(&spec.rows)?;
+    let col_cols = resolve(&spec.cols)?;
+    let value_cols = resolve(&spec.values)?;
+
+    // The measures are all combinations of value column and aggregation.
+    // Counting does not need a value column.
+    if value_cols.is_empty()
+        && spec
+            .aggregations
+            .iter()
+            .any(|agg| *agg != Aggregation::Count)
+    {
+        return Err(TextkitDocxError::BadHelperOption(String::from(
+            "values are required unless counting",
+        )));
+    }
+    let measures: Vec<(Option<usize>, Aggregation)> = if value_cols.is_empty() {
+        spec.aggregations.iter().map(|agg| (None, *agg)).collect()
+    } else {
+        value_cols
+            .iter()
+            .flat_map(|col_i| {
+                spec.aggregations
+                    .iter()
+                    .map(move |agg| (Some(*col_i), *agg))
+            })
+            .collect()
+    };
+    let measure_label = |(col_i, agg): &(Option<usize>, Aggregation)| match col_i {
+        Some(col_i) => format!("{} ({})", column_name(table, *col_i), agg.label()),
+        None => String::from(agg.label()),
+    };
+
+    let row_groups = group_rows(table, &row_cols);
+    let col_groups = group_rows(table, &col_cols);
+
+    let mut col_group_of_row: Vec<usize> = vec![0; table.no_of_rows()];
+    for (group_i, group) in col_groups.iter().enumerate() {
+        for row_i in group.row_indices.iter() {
+            col_group_of_row[*row_i] = group_i;
+        }
+    }
+
+    // Header: the grouping columns, then one column per column group and
+    // measure (or just per measure, if there are no column groups).
+    let format = ValueFormat::default();
+    let mut headers: Vec<String> = row_cols
+        .iter()
+        .map(|col_i| column_name(table, *col_i))
+        .collect();
+    if col_cols.is_empty() {
+        headers.extend(measures.iter().map(measure_label));
+    } else {
+        let mut col_labels: Vec<String> = col_groups
+            .iter()
+            .map(|group| {
+                group
+                    .key
+                    .iter()
+                    .map(|value| format_value(value.as_ref(), &format))
+                    .collect::<Vec<String>>()
+                    .join(" / ")
+            })
+            .collect();
+        if spec.totals {
+            col_labels.push(String::from("Total"));
+        }
+        for col_label in col_labels.iter() {
+            for measure in measures.iter() {
+                if measures.len() == 1 {
+                    headers.push(col_label.clone());
+                } else {
+                    headers.push(format!("{} / {}", col_label, measure_label(measure)));
+                }
+            }
+        }
+    }
+
+    let mut output_rows: Vec<Vec<Option<Value>>> = Vec::new();
+    let mut add_row = |key: Vec<Option<Value>>, row_indices: &[usize]| {
+        let mut row = key;
+        if col_cols.is_empty() {
+            row.extend(
+                measures
+                    .iter()
+                    .map(|measure| aggregate(table, measure, row_indices)),
+            );
+        } else {
+            for group_i in 0..col_groups.len() {
+                let in_group: Vec<usize> = row_indices
+                    .iter()
+                    .copied()
+                    .filter(|row_i| col_group_of_row[*row_i] == group_i)
+                    .collect();
+                row.extend(
+                    measures
+                        .iter()
+                        .map(|measure| aggregate(table, measure, &in_group)),
+                );
+            }
+            if spec.totals {
+                row.extend(
+                    measures
+                        .iter()
+                        .map(|measure| aggregate(table, measure, row_indices)),
+                );
+            }
+        }
+        output_rows.push(row);
+    };
+
+    for group in row_groups.iter() {
+        add_row(group.key.clone(), &group.row_indices);
+    }
+    if spec.totals && !row_cols.is_empty() {
+        let mut key: Vec<Option<Value>> = vec![None; row_cols.len()];
+        key[0] = Some(Value::Text(String::from("Total")));
+        let all_rows: Vec<usize> = (0..table.no_of_rows()).collect();
+        add_row(key, &all_rows);
+    }
+
+    let columns: Vec<Vec<Option<Value>>> = (0..headers.len())
+        .map(|col_i| output_rows.iter().map(|row| row[col_i].clone()).collect())
+        .collect();
+
+    Ok(TableData { headers, columns })
+}
+
+fn column_name(table: &TableData, col_i: usize) -> String {
+    table
+        .headers
+        .get(col_i)
+        .cloned()
+        .unwrap_or_else(|| format!("{}", col_i + 1))
+}
+
+fn aggregate(
+    table: &TableData,
+    (col_i, agg): &(Option<usize>, Aggregation),
+    row_indices: &[usize],
+) -> Option<Value> {
+    match col_i {
+        Some(col_i) => {
+            let values: Vec<&Value> = row_indices
+                .iter()
+                .filter_map(|row_i| table.cell(*col_i, *row_i))
+                .collect();
+            agg.apply(&values)
+        }
+        None => Some(Value::Number(Numeric::Integer(row_indices.len() as i64))),
+    }
+}
+
+/// Group rows by the values of the given columns, sorted by those values
+/// (missing values last). Without columns all rows form a single group.
+fn group_rows(table: &TableData, columns: &[usize]) -> Vec<Group> {
+    if columns.is_empty() {
+        return vec![Group {
+            key: Vec::new(),
+            row_indices: (0..table.no_of_rows()).collect(),
+        }];
+    }
+
+    let format = ValueFormat::default();
+    let mut groups: Vec<Group> = Vec::new();
+    // Values are grouped by their text, as datakit values can not be hashed.
+    let mut group_index: HashMap<Vec<Option<String>>, usize> = HashMap::new();
+
+    for row_i in 0..table.no_of_rows() {
+        let key: Vec<Option<Value>> = columns
+            .iter()
+            .map(|col_i| table.cell(*col_i, row_i).cloned())
+            .collect();
+        let key_text: Vec<Option<String>> = key
+            .iter()
+            .map(|value| {
+                value
+                    .as_ref()
+                    .map(|value| format_value(Some(value), &format))
+            })
+            .collect();
+
+        match group_index.get(&key_text) {
+            Some(group_i) => groups[*group_i].row_indices.push(row_i),
+            None => {
+                group_index.insert(key_text, groups.len());
+                groups.push(Group {
+                    key,
+                    row_indices: vec![row_i],
+                });
+            }
+        }
+    }
+
+    groups.sort_by(|a, b| {
+        for (x, y) in a.key.iter().zip(b.key.iter()) {
+            let ordering = match (x, y) {
+                (None, None) => Ordering::Equal,
+                (None, Some(_)) => Ordering::Greater,
+                (Some(_), None) => Ordering::Less,
+                (Some(x), Some(y)) => compare_values(x, y),
+            };
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        }
+        Ordering::Equal
+    });
+
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(text: &str) -> Option<Value> {
+        Some(Value::Text(String::from(text)))
+    }
+
+    fn integer(int: i64) -> Option<Value> {
+        Some(Value::Number(Numeric::Integer(int)))
+    }
+
+    fn real(real: f64) -> Option<Value> {
+        Some(Value::Number(Numeric::Real(real)))
+    }
+
+    fn sales() -> TableData {
+        TableData {
+            headers: vec![
+                String::from("region"),
+                String::from("quarter"),
+                String::from("revenue"),
+            ],
+            columns: vec![
+                vec![
+                    text("South"),
+                    text("North"),
+                    text("North"),
+                    text("South"),
+                    text("North"),
+                ],
+                vec![text("Q1"), text("Q1"), text("Q2"), text("Q2"), text("Q2")],
+                vec![integer(4), integer(1), integer(2), None, integer(6)],
+            ],
+        }
+    }
+
+    fn spec(rows: &[&str], cols: &[&str], values: &[&str], aggs: &[Aggregation]) -> PivotSpec {
+        let names = |names: &[&str]| names.iter().map(|name| String::from(*name)).collect();
+        PivotSpec {
+            rows: names(rows),
+            cols: names(cols),
+            values: names(values),
+            aggregations: aggs.to_vec(),
+            totals: false,
+        }
+    }
+
+    #[test]
+    fn aggregations_per_group() {
+        let aggs = [
+            Aggregation::Count,
+            Aggregation::Sum,
+            Aggregation::Mean,
+            Aggregation::Median,
+            Aggregation::Min,
+            Aggregation::Max,
+        ];
+        let pivot = pivot_table(&sales(), &spec(&["region"], &[], &["revenue"], &aggs)).unwrap();
+        assert_eq!(
+            pivot.headers,
+            vec![
+                "region",
+                "revenue (count)",
+                "revenue (sum)",
+                "revenue (mean)",
+                "revenue (median)",
+                "revenue (min)",
+                "revenue (max)",
+            ]
+        );
+        // Groups are sorted; missing values are left out.
+        assert_eq!(pivot.columns[0], vec![text("North"), text("South")]);
+        assert_eq!(pivot.columns[1], vec![integer(3), integer(1)]);
+        assert_eq!(pivot.columns[2], vec![integer(9), integer(4)]);
+        assert_eq!(pivot.columns[3], vec![real(3.0), real(4.0)]);
+        assert_eq!(pivot.columns[4], vec![real(2.0), real(4.0)]);
+        assert_eq!(pivot.columns[5], vec![integer(1), integer(4)]);
+        assert_eq!(pivot.columns[6], vec![integer(6), integer(4)]);
+    }
+
+    #[test]
+    fn column_groups_and_totals() {
+        let mut spec = spec(&["region"], &["quarter"], &["revenue"], &[Aggregation::Sum]);
+        spec.totals = true;
+        let pivot = pivot_table(&sales(), &spec).unwrap();
+        assert_eq!(pivot.headers, vec!["region", "Q1", "Q2", "Total"]);
+        assert_eq!(
+            pivot.columns[0],
+            vec![text("North"), text("South"), text("Total")]
+        );
+        assert_eq!(pivot.columns[1], vec![integer(1), integer(4), integer(5)]);
+        // South has no revenue in Q2.
+        assert_eq!(pivot.columns[2], vec![integer(8), None, integer(8)]);
+        assert_eq!(pivot.columns[3], vec![integer(9), integer(4), integer(13)]);
+    }
+
+    #[test]
+    fn counting_rows() {
+        let pivot = pivot_table(
+            &sales(),
+            &spec(&["quarter"], &[], &[], &[Aggregation::Count]),
+        )
+        .unwrap();
+        assert_eq!(pivot.headers, vec!["quarter", "count"]);
+        assert_eq!(pivot.columns[1], vec![integer(2), integer(3)]);
+
+        let summing_nothing = spec(&["quarter"], &[], &[], &[Aggregation::Sum]);
+        assert!(pivot_table(&sales(), &summing_nothing).is_err());
+        let unknown_column = spec(&["country"], &[], &[], &[Aggregation::Count]);
+        assert!(pivot_table(&sales(), &unknown_column).is_err());
+    }
+
+    #[test]
+    fn sums_do_not_overflow() {
+        let values = [
+            Value::Number(Numeric::Integer(i64::MAX)),
+            Value::Number(Numeric::Integer(1)),
+        ];
+        let values: Vec<&Value> = values.iter().collect();
+        assert_eq!(Aggregation::Sum.apply(&values), real(i64::MAX as f64 + 1.0));
+        assert_eq!(Aggregation::Sum.apply(&values[1..]), integer(1));
+        assert_eq!(Aggregation::Sum.apply(&[]), None);
+    }
+}
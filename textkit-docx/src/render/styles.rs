@@ -0,0 +1,103 @@
+//! Styles defined in the template (`word/styles.xml`), so that rendered
+//! content only refers to styles that exist, or falls back to direct
+//! formatting.
+//!
+
+use crate::errors::TextkitDocxError;
+use crate::parse::xml_to_token_vec;
+use crate::render::{is_wp_end_token, is_wp_start_token, wp_attribute};
+
+/// Kind of a style (`w:type`): what it can be applied to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum StyleKind {
+    Paragraph,
+    Character,
+    Table,
+    Numbering,
+}
+
+impl StyleKind {
+    fn from_type(style_type: &str) -> Option<Self> {
+        match style_type {
+            "paragraph" => Some(Self::Paragraph),
+            "character" => Some(Self::Character),
+            "table" => Some(Self::Table),
+            "numbering" => Some(Self::Numbering),
+            _ => None,
+        }
+    }
+}
+
+/// A style of the template.
+#[derive(Debug, Clone)]
+struct StyleDefinition {
+    /// `w:styleId`, which paragraphs and runs refer to. Word translates the
+    /// ids of built-in styles, e.g. `berschrift1` for `Heading1` in German.
+    id: String,
+    /// `w:name`, which is what Word shows. Built-in styles keep their English
+    /// name (e.g. `heading 1`) in any language.
+    name: Option<String>,
+    kind: Option<StyleKind>,
+}
+
+/// The styles of a template.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct StyleSheet {
+    styles: Vec<StyleDefinition>,
+}
+
+impl StyleSheet {
+    /// Read the styles of a template, if it has a styles part.
+    pub(crate) fn from_xml(styles_xml: Option<&str>) -> Result<Self, TextkitDocxError> {
+        let styles_xml = match styles_xml {
+            Some(styles_xml) => styles_xml,
+            None => return Ok(Self::default()),
+        };
+
+        let mut styles: Vec<StyleDefinition> = Vec::new();
+        let mut in_style = false;
+
+        for token in xml_to_token_vec(styles_xml)?.iter() {
+            if is_wp_start_token(token, "style") {
+                in_style = false;
+                if let Some(id) = wp_attribute(token, "styleId") {
+                    styles.push(StyleDefinition {
+                        id: String::from(id),
+                        name: None,
+                        kind: wp_attribute(token, "type").and_then(StyleKind::from_type),
+                    });
+                    in_style = true;
+                }
+            } else if is_wp_end_token(token, "style") {
+                in_style = false;
+            } else if in_style && is_wp_start_token(token, "name") {
+                if let Some(style) = styles.last_mut() {
+                    style.name = wp_attribute(token, "val").map(String::from);
+                }
+            }
+        }
+
+        Ok(Self { styles })
+    }
+
+    /// Id of the style of the given kind with this id or, failing that, this
+    /// name (compared case-insensitively, as Word does).
+    pub(crate) fn resolve(&self, id_or_name: &str, kind: StyleKind) -> Option<&str> {
+        let id_or_name = id_or_name.trim();
+        let of_kind = |style: &&StyleDefinition| style.kind.is_none_or(|k| k == kind);
+
+        self.styles
+            .iter()
+            .filter(of_kind)
+            .find(|style| style.id == id_or_name)
+            .or_else(|| {
+                self.styles.iter().filter(of_kind).find(|style| {
+                    style
+                        .name
+                        .as_deref()
+                        .is_some_and(|name| name.eq_ignore_ascii_case(id_or_name))
+                })
+            })
+            .map(|style| style.id.as_str())
+    }
+}
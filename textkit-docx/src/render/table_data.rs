@@ -0,0 +1,512 @@
+//! Tabular data as it is rendered into a document, regardless of whether it
+//! came from a datakit `Table` or from plain JSON.
+//!
+
+use crate::errors::TextkitDocxError;
+use crate::render::format::{format_value, ValueFormat};
+use datakit::{table::Table, value::definitions::*, value::primitives::*};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+/// Column-oriented table contents. Missing values are `None`.
+#[derive(Debug, Clone, Default)]
+pub struct TableData {
+    /// Column names. Empty if the table has no header row.
+    pub headers: Vec<String>,
+    pub columns: Vec<Vec<Option<Value>>>,
+}
+
+impl TableData {
+    /// Take over the contents of a datakit `Table`. A `Table` does not
+    /// know the names of its columns (those live in the column contracts),
+    /// so the headers are left empty. Empty text is a missing value.
+    pub fn from_datakit(table: &Table) -> Self {
+        let columns = (0..table.columns().len())
+            .map(|col_i| {
+                (0..table.len())
+                    .map(|row_i| match &table.columns()[col_i][row_i] {
+                        Value::Text(text) if text.is_empty() => None,
+                        value => Some(value.clone()),
+                    })
+                    .collect()
+            })
+            .collect();
+
+        Self {
+            headers: vec![],
+            columns,
+        }
+    }
+
+    /// Detect the shape of some JSON data and convert it into a table. The
+    /// following shapes are supported:
+    ///
+    /// 1. A serialized datakit `Table` (with `columns` and `columnContracts`);
+    /// 2. An array of objects, e.g. `[{"name": "a", "score": 3}, ...]`, where
+    ///    the keys become column names (in order of first appearance);
+    /// 3. An array of arrays, e.g. `[["name", "score"], ["a", 3]]`, where the
+    ///    first row is used as header if it consists of strings only.
+    ///
+    /// Returns `Ok(None)` if the data has none of these shapes.
+    pub fn from_json(value: &serde_json::Value) -> Result<Option<Self>, TextkitDocxError> {
+        match value {
+            serde_json::Value::Object(obj)
+                if obj.contains_key("columns") && obj.contains_key("columnContracts") =>
+            {
+                let table: Table = serde_json::from_value(value.clone())?;
+                let mut result = Self::from_datakit(&table);
+                if let Some(serde_json::Value::Array(contracts)) = obj.get("columnContracts") {
+                    result.headers = contracts
+                        .iter()
+                        .map(|contract| match contract.get("name") {
+                            Some(serde_json::Value::String(name)) => name.clone(),
+                            _ => String::new(),
+                        })
+                        .collect();
+                }
+                Ok(Some(result))
+            }
+            serde_json::Value::Array(rows) if rows.iter().all(|row| row.is_object()) => {
+                let mut headers: Vec<String> = Vec::new();
+                for row in rows.iter() {
+                    if let serde_json::Value::Object(obj) = row {
+                        for key in obj.keys() {
+                            if !headers.contains(key) {
+                                headers.push(key.clone());
+                            }
+                        }
+                    }
+                }
+
+                let columns = headers
+                    .iter()
+                    .map(|key| {
+                        let cells: Vec<&serde_json::Value> = rows
+                            .iter()
+                            .map(|row| row.get(key).unwrap_or(&serde_json::Value::Null))
+                            .collect();
+                        json_column_to_values(&cells)
+                    })
+                    .collect();
+
+                Ok(Some(Self { headers, columns }))
+            }
+            serde_json::Value::Array(rows) if rows.iter().all(|row| row.is_array()) => {
+                let rows: Vec<&Vec<serde_json::Value>> =
+                    rows.iter().filter_map(|row| row.as_array()).collect();
+                let no_of_cols = rows.iter().map(|row| row.len()).max().unwrap_or(0);
+
+                let has_header = rows.len() > 1
+                    && rows[0].iter().all(|cell| cell.is_string())
+                    && rows[0].len() == no_of_cols;
+                let (headers, body) = if has_header {
+                    let headers = rows[0]
+                        .iter()
+                        .map(|cell| String::from(cell.as_str().unwrap_or_default()))
+                        .collect();
+                    (headers, &rows[1..])
+                } else {
+                    (vec![], &rows[..])
+                };
+
+                let columns = (0..no_of_cols)
+                    .map(|col_i| {
+                        let cells: Vec<&serde_json::Value> = body
+                            .iter()
+                            .map(|row| row.get(col_i).unwrap_or(&serde_json::Value::Null))
+                            .collect();
+                        json_column_to_values(&cells)
+                    })
+                    .collect();
+
+                Ok(Some(Self { headers, columns }))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Serialize the table the way datakit serializes a `Table`, with the
+    /// column names in the column contracts. Missing values are written as
+    /// empty text.
+    pub fn to_datakit_json(&self) -> Result<serde_json::Value, TextkitDocxError> {
+        let mut columns: Vec<serde_json::Value> = Vec::new();
+        let mut contracts: Vec<serde_json::Value> = Vec::new();
+
+        for (col_i, column) in self.columns.iter().enumerate() {
+            let missing = Value::Text(String::new());
+            let values = column
+                .iter()
+                .map(|value| serde_json::to_value(value.as_ref().unwrap_or(&missing)))
+                .collect::<Result<Vec<serde_json::Value>, _>>()?;
+            columns.push(serde_json::Value::Array(values));
+
+            let expected_type = match column.iter().flatten().next() {
+                Some(Value::Number(_)) => "number",
+                Some(Value::Boolean(_)) => "boolean",
+                Some(Value::DateTime(_)) => "datetime",
+                _ => "text",
+            };
+            contracts.push(serde_json::json!({
+                "name": self.headers.get(col_i).cloned().unwrap_or_default(),
+                "valueContract": {
+                    "expectedType": {"isType": expected_type},
+                    "valueConstraints": []
+                }
+            }));
+        }
+
+        Ok(serde_json::json!({
+            "columns": columns,
+            "columnContracts": contracts,
+            "colLength": self.no_of_cols(),
+            "rowLength": self.no_of_rows()
+        }))
+    }
+
+    /// Convert the table into a datakit `Table`.
+    pub fn to_datakit(&self) -> Result<Table, TextkitDocxError> {
+        Ok(serde_json::from_value(self.to_datakit_json()?)?)
+    }
+
+    pub fn no_of_rows(&self) -> usize {
+        self.columns.iter().map(|col| col.len()).max().unwrap_or(0)
+    }
+
+    pub fn no_of_cols(&self) -> usize {
+        self.columns.len()
+    }
+
+    /// Value at the given position, `None` if missing or out of bounds.
+    pub fn cell(&self, col_i: usize, row_i: usize) -> Option<&Value> {
+        self.columns
+            .get(col_i)
+            .and_then(|col| col.get(row_i))
+            .and_then(|cell| cell.as_ref())
+    }
+
+    /// Index of the column with the given name. Also accepts a column
+    /// number (starting at 1), so that columns can be addressed in tables
+    /// without a header.
+    pub fn column_index(&self, name: &str) -> Option<usize> {
+        self.headers
+            .iter()
+            .position(|header| header == name)
+            .or_else(|| match name.parse::<usize>() {
+                Ok(n) if n >= 1 && n <= self.no_of_cols() => Some(n - 1),
+                _ => None,
+            })
+    }
+
+    /// Keep only the given columns, in the given order. Unknown column
+    /// names are ignored.
+    pub fn select_columns(&mut self, names: &[String]) {
+        let indices: Vec<usize> = names
+            .iter()
+            .filter_map(|name| self.column_index(name))
+            .collect();
+
+        if !self.headers.is_empty() {
+            self.headers = indices.iter().map(|i| self.headers[*i].clone()).collect();
+        }
+        self.columns = indices.iter().map(|i| self.columns[*i].clone()).collect();
+    }
+
+    /// Keep only the rows for which `predicate` holds.
+    pub fn filter_rows<F: Fn(&TableData, usize) -> bool>(&mut self, predicate: F) {
+        let keep: Vec<usize> = (0..self.no_of_rows())
+            .filter(|row_i| predicate(self, *row_i))
+            .collect();
+        self.reorder_rows(&keep);
+    }
+
+    /// Sort the rows by the given columns, each either ascending or descending
+    /// (`true`). Missing values always go last, the sort is stable.
+    pub fn sort_rows(&mut self, keys: &[(usize, bool)]) {
+        let mut order: Vec<usize> = (0..self.no_of_rows()).collect();
+        order.sort_by(|a, b| {
+            for (col_i, descending) in keys.iter() {
+                let ordering = match (self.cell(*col_i, *a), self.cell(*col_i, *b)) {
+                    (None, None) => Ordering::Equal,
+                    (None, Some(_)) => Ordering::Greater,
+                    (Some(_), None) => Ordering::Less,
+                    (Some(x), Some(y)) if *descending => compare_values(y, x),
+                    (Some(x), Some(y)) => compare_values(x, y),
+                };
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+            Ordering::Equal
+        });
+        self.reorder_rows(&order);
+    }
+
+    /// Keep only the first `limit` rows.
+    pub fn truncate_rows(&mut self, limit: usize) {
+        for column in self.columns.iter_mut() {
+            column.truncate(limit);
+        }
+    }
+
+    /// Append a row with the sums of the given columns. The first column that
+    /// is not summed up gets the label `label`.
+    pub fn append_totals_row(&mut self, columns: &[usize], label: &str) {
+        let no_of_rows = self.no_of_rows();
+        let label_column = (0..self.no_of_cols()).find(|col_i| !columns.contains(col_i));
+
+        for col_i in 0..self.no_of_cols() {
+            let total = if columns.contains(&col_i) {
+                let values: Vec<&Value> = (0..no_of_rows)
+                    .filter_map(|row_i| self.cell(col_i, row_i))
+                    .collect();
+                Some(sum_values(&values))
+            } else if Some(col_i) == label_column {
+                Some(Value::Text(String::from(label)))
+            } else {
+                None
+            };
+
+            let column = &mut self.columns[col_i];
+            column.resize(no_of_rows, None);
+            column.push(total);
+        }
+    }
+
+    /// Rebuild the rows from the given row indices (which may skip rows).
+    fn reorder_rows(&mut self, order: &[usize]) {
+        for column in self.columns.iter_mut() {
+            *column = order
+                .iter()
+                .map(|row_i| column.get(*row_i).cloned().unwrap_or(None))
+                .collect();
+        }
+    }
+
+    /// Change the names of the columns in the header, `renames` maps old
+    /// names to new ones.
+    pub fn rename_columns(&mut self, renames: &BTreeMap<String, String>) {
+        for header in self.headers.iter_mut() {
+            if let Some(new_name) = renames.get(header) {
+                *header = new_name.clone();
+            }
+        }
+    }
+}
+
+/// The sum of the numbers among the values. Integers add up to an integer,
+/// unless there are real numbers among them or the sum does not fit into one.
+pub(crate) fn sum_values(values: &[&Value]) -> Value {
+    let integer_sum = values.iter().try_fold(0i64, |sum, value| match value {
+        Value::Number(Numeric::Integer(int)) => sum.checked_add(*int),
+        _ => None,
+    });
+
+    match integer_sum {
+        Some(sum) => Value::Number(Numeric::Integer(sum)),
+        None => Value::Number(Numeric::Real(
+            values.iter().filter_map(|value| value_as_f64(value)).sum(),
+        )),
+    }
+}
+
+/// The numeric value of a cell, if it has one.
+pub(crate) fn value_as_f64(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(Numeric::Integer(int)) => Some(*int as f64),
+        Value::Number(Numeric::Real(real)) if !real.is_nan() => Some(*real),
+        _ => None,
+    }
+}
+
+/// Order two values for sorting: numbers numerically, booleans `false` first,
+/// everything else by its text representation. Numbers go before text.
+pub(crate) fn compare_values(a: &Value, b: &Value) -> Ordering {
+    match (a, b) {
+        (Value::Boolean(x), Value::Boolean(y)) => x.cmp(y),
+        (Value::Text(x), Value::Text(y)) => x.cmp(y),
+        _ => match (value_as_f64(a), value_as_f64(b)) {
+            (Some(x), Some(y)) => x.partial_cmp(&y).unwrap_or(Ordering::Equal),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => {
+                let format = ValueFormat::default();
+                format_value(Some(a), &format).cmp(&format_value(Some(b), &format))
+            }
+        },
+    }
+}
+
+/// Compare a (possibly missing) cell value with a JSON constant. Numbers are
+/// compared numerically (also if the constant is a numeric string), text
+/// lexicographically. Returns `None` if the two are not comparable.
+pub(crate) fn compare_with_json(
+    value: Option<&Value>,
+    constant: &serde_json::Value,
+) -> Option<Ordering> {
+    match (value, constant) {
+        (None, serde_json::Value::Null) => Some(Ordering::Equal),
+        (None, _) | (_, serde_json::Value::Null) => None,
+        (Some(Value::Boolean(b)), serde_json::Value::Bool(c)) => Some(b.cmp(c)),
+        (Some(Value::Text(text)), serde_json::Value::String(c)) => Some(text.as_str().cmp(c)),
+        (Some(value), constant) => {
+            let x = value_as_f64(value)?;
+            let y = match constant {
+                serde_json::Value::Number(n) => n.as_f64()?,
+                serde_json::Value::String(s) => s.trim().parse::<f64>().ok()?,
+                _ => return None,
+            };
+            x.partial_cmp(&y)
+        }
+    }
+}
+
+/// Convert a column of JSON values into datakit values. Integers are turned
+/// into reals if the column contains any reals, and everything is turned into
+/// text if the column mixes text with other values.
+fn json_column_to_values(cells: &[&serde_json::Value]) -> Vec<Option<Value>> {
+    let has_text = cells.iter().any(|cell| cell.is_string());
+    let has_real = cells.iter().any(|cell| cell.is_f64());
+    let has_other = cells
+        .iter()
+        .any(|cell| !cell.is_string() && !cell.is_null());
+
+    cells
+        .iter()
+        .map(|cell| match cell {
+            serde_json::Value::Null => None,
+            serde_json::Value::String(s) => Some(Value::Text(s.clone())),
+            other if has_text && has_other => Some(Value::Text(json_to_plain_text(other))),
+            serde_json::Value::Bool(b) => Some(Value::Boolean(*b)),
+            serde_json::Value::Number(n) => match n.as_i64() {
+                Some(x) if !has_real => Some(Value::Number(Numeric::Integer(x))),
+                _ => n.as_f64().map(|x| Value::Number(Numeric::Real(x))),
+            },
+            other => Some(Value::Text(json_to_plain_text(other))),
+        })
+        .collect()
+}
+
+fn json_to_plain_text(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(text: &str) -> Option<Value> {
+        Some(Value::Text(String::from(text)))
+    }
+
+    fn integer(int: i64) -> Option<Value> {
+        Some(Value::Number(Numeric::Integer(int)))
+    }
+
+    fn real(real: f64) -> Option<Value> {
+        Some(Value::Number(Numeric::Real(real)))
+    }
+
+    #[test]
+    fn array_of_objects() {
+        let json = serde_json::json!([
+            {"name": "a", "score": 3},
+            {"score": 4, "name": "b", "note": "late"},
+        ]);
+        let table = TableData::from_json(&json).unwrap().unwrap();
+        // Keys in order of first appearance; missing keys are missing values.
+        assert_eq!(table.headers, vec!["name", "score", "note"]);
+        assert_eq!(table.columns[0], vec![text("a"), text("b")]);
+        assert_eq!(table.columns[1], vec![integer(3), integer(4)]);
+        assert_eq!(table.columns[2], vec![None, text("late")]);
+    }
+
+    #[test]
+    fn array_of_arrays() {
+        let with_header = serde_json::json!([["name", "score"], ["a", 3], ["b", 4]]);
+        let table = TableData::from_json(&with_header).unwrap().unwrap();
+        assert_eq!(table.headers, vec!["name", "score"]);
+        assert_eq!(table.no_of_rows(), 2);
+
+        // A first row with numbers is data.
+        let without_header = serde_json::json!([["a", 3], ["b", 4]]);
+        let table = TableData::from_json(&without_header).unwrap().unwrap();
+        assert!(table.headers.is_empty());
+        assert_eq!(table.columns[0], vec![text("a"), text("b")]);
+    }
+
+    #[test]
+    fn ragged_rows() {
+        let json = serde_json::json!([["a", 1], ["b"], ["c", 3, true]]);
+        let table = TableData::from_json(&json).unwrap().unwrap();
+        assert!(table.headers.is_empty());
+        assert_eq!(table.no_of_cols(), 3);
+        assert_eq!(table.columns[1], vec![integer(1), None, integer(3)]);
+        assert_eq!(
+            table.columns[2],
+            vec![None, None, Some(Value::Boolean(true))]
+        );
+
+        // A header shorter than the widest row is taken to be data.
+        let json = serde_json::json!([["name"], ["a", 1]]);
+        let table = TableData::from_json(&json).unwrap().unwrap();
+        assert!(table.headers.is_empty());
+        assert_eq!(table.no_of_rows(), 2);
+    }
+
+    #[test]
+    fn type_inference() {
+        let json = serde_json::json!([
+            {"int": 1, "real": 1, "mixed": 1, "flag": true},
+            {"int": 2, "real": 2.5, "mixed": "two", "flag": null},
+        ]);
+        let table = TableData::from_json(&json).unwrap().unwrap();
+        // Integers become reals if any value in the column is one, and
+        // columns mixing text with other values are text.
+        assert_eq!(table.columns[0], vec![integer(1), integer(2)]);
+        assert_eq!(table.columns[1], vec![real(1.0), real(2.5)]);
+        assert_eq!(table.columns[2], vec![text("1"), text("two")]);
+        assert_eq!(table.columns[3], vec![Some(Value::Boolean(true)), None]);
+    }
+
+    #[test]
+    fn other_shapes() {
+        for json in [
+            serde_json::json!({"name": "a"}),
+            serde_json::json!([1, 2, 3]),
+            serde_json::json!([{"name": "a"}, ["b"]]),
+            serde_json::json!("text"),
+        ]
+        .iter()
+        {
+            assert!(TableData::from_json(json).unwrap().is_none(), "{}", json);
+        }
+    }
+
+    #[test]
+    fn totals_row() {
+        let mut table = TableData {
+            headers: vec![
+                String::from("name"),
+                String::from("count"),
+                String::from("share"),
+                String::from("big"),
+            ],
+            columns: vec![
+                vec![text("a"), text("b")],
+                vec![integer(1), integer(2)],
+                vec![real(0.25), integer(1)],
+                vec![integer(i64::MAX), integer(i64::MAX)],
+            ],
+        };
+        table.append_totals_row(&[1, 2, 3], "Total");
+        assert_eq!(table.no_of_rows(), 3);
+        assert_eq!(table.cell(0, 2), text("Total").as_ref());
+        assert_eq!(table.cell(1, 2), integer(3).as_ref());
+        assert_eq!(table.cell(2, 2), real(1.25).as_ref());
+        // Integer sums that overflow become real numbers.
+        assert_eq!(table.cell(3, 2), real(2.0 * i64::MAX as f64).as_ref());
+    }
+}
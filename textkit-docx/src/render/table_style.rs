@@ -0,0 +1,475 @@
+//! Conditional styling, merges and shading of generated tables.
+//!
+
+use crate::parse::quote_kind;
+use crate::render::table_data::{compare_with_json, TableData};
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Deserializer};
+use std::cmp::Ordering;
+
+lazy_static! {
+    /// `column op value`, see [`Condition::parse`].
+    static ref CONDITION_PATTERN: Regex =
+        Regex::new(r"^\s*(.+?)\s*(==|!=|<>|<=|>=|=|<|>)\s*(.*?)\s*$").unwrap();
+}
+
+/// Styling applied to a table on top of its table style (`TableGrid`). Can be
+/// deserialized from JSON, for example:
+///
+/// ```json
+/// {
+///     "groupHeaders": [["", "2019", "2019", "2020", "2020"]],
+///     "mergeRepeated": ["region"],
+///     "shading": [{"column": "profit", "op": "<", "value": 0, "fill": "FFC7CE"}],
+///     "rowStyles": [{"column": "region", "op": "==", "value": "Total", "bold": true}]
+/// }
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct TableStyling {
+    /// Rows of labels rendered above the column headers. Runs of equal adjacent
+    /// labels are merged horizontally (`w:gridSpan`) into a group header.
+    pub group_headers: Vec<Vec<String>>,
+
+    /// Columns (by name or 1-based number) in which runs of equal adjacent
+    /// values are merged vertically (`w:vMerge`).
+    pub merge_repeated: Vec<String>,
+
+    /// Columns (by 0-based index of the rendered table) merged like those of
+    /// `merge_repeated`. For callers that know the positions, e.g. the index
+    /// columns of a DataFrame, whose names can look like column numbers.
+    #[serde(skip)]
+    pub merge_repeated_columns: Vec<usize>,
+
+    /// Background colours of cells.
+    pub shading: Vec<ShadingRule>,
+
+    /// Bold and italic text for whole rows.
+    pub row_styles: Vec<RowStyleRule>,
+}
+
+/// Comparison of a cell value with a constant.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum ComparisonOp {
+    #[serde(rename = "==")]
+    Eq,
+    #[serde(rename = "!=")]
+    Ne,
+    #[serde(rename = "<")]
+    Lt,
+    #[serde(rename = "<=")]
+    Le,
+    #[serde(rename = ">")]
+    Gt,
+    #[serde(rename = ">=")]
+    Ge,
+}
+
+impl ComparisonOp {
+    pub fn from_symbol(symbol: &str) -> Option<Self> {
+        match symbol {
+            "==" | "=" => Some(Self::Eq),
+            "!=" | "<>" => Some(Self::Ne),
+            "<" => Some(Self::Lt),
+            "<=" => Some(Self::Le),
+            ">" => Some(Self::Gt),
+            ">=" => Some(Self::Ge),
+            _ => None,
+        }
+    }
+
+    fn holds_for(&self, ordering: Option<Ordering>) -> bool {
+        match (self, ordering) {
+            (Self::Ne, None) => true,
+            (_, None) => false,
+            (Self::Eq, Some(o)) => o == Ordering::Equal,
+            (Self::Ne, Some(o)) => o != Ordering::Equal,
+            (Self::Lt, Some(o)) => o == Ordering::Less,
+            (Self::Le, Some(o)) => o != Ordering::Greater,
+            (Self::Gt, Some(o)) => o == Ordering::Greater,
+            (Self::Ge, Some(o)) => o != Ordering::Less,
+        }
+    }
+}
+
+/// A condition on the value of a column in a row, e.g. `score < 0.5`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Condition {
+    pub column: String,
+    pub op: ComparisonOp,
+    pub value: serde_json::Value,
+}
+
+impl Condition {
+    /// Parse a condition written as `column op value`, e.g. `status == 'open'`
+    /// or `amount >= 1000`. Quoted values are text, otherwise numbers,
+    /// `true`, `false` and `null` are recognized, and anything else is text.
+    pub fn parse(text: &str) -> Option<Self> {
+        let capture = CONDITION_PATTERN.captures(text)?;
+        let op = ComparisonOp::from_symbol(&capture[2])?;
+        let raw_value = &capture[3];
+
+        let mut chars = raw_value.chars();
+        let quoted = match (
+            chars.next().and_then(quote_kind),
+            chars.last().and_then(quote_kind),
+        ) {
+            (Some(open), Some(close)) => open == close && raw_value.chars().count() >= 2,
+            _ => false,
+        };
+        let value = if quoted {
+            let inner: String = raw_value
+                .chars()
+                .skip(1)
+                .take(raw_value.chars().count() - 2)
+                .collect();
+            serde_json::Value::String(inner)
+        } else {
+            match raw_value {
+                "true" => serde_json::Value::Bool(true),
+                "false" => serde_json::Value::Bool(false),
+                "null" => serde_json::Value::Null,
+                _ => match raw_value
+                    .parse::<f64>()
+                    .ok()
+                    .and_then(serde_json::Number::from_f64)
+                {
+                    Some(number) => serde_json::Value::Number(number),
+                    None => serde_json::Value::String(String::from(raw_value)),
+                },
+            }
+        };
+
+        Some(Self {
+            column: String::from(&capture[1]),
+            op,
+            value,
+        })
+    }
+
+    /// Parse conditions joined with `and`, e.g.
+    /// `status == 'open' and name == 'Smith and Sons'`. An `and` inside a
+    /// quoted value does not join conditions.
+    pub fn parse_all(text: &str) -> Option<Vec<Self>> {
+        let mut condition_texts: Vec<String> = vec![String::new()];
+        let mut word = String::new();
+        let mut quote: Option<char> = None;
+
+        let end_word = |condition_texts: &mut Vec<String>, word: &mut String| {
+            let current = condition_texts.last_mut().unwrap();
+            if word.eq_ignore_ascii_case("and") && !current.trim().is_empty() {
+                condition_texts.push(String::new());
+            } else {
+                current.push_str(word);
+            }
+            word.clear();
+        };
+
+        for c in text.chars() {
+            match quote {
+                Some(open) => {
+                    word.push(c);
+                    if quote_kind(c) == Some(open) {
+                        quote = None;
+                    }
+                }
+                None if c.is_whitespace() => {
+                    end_word(&mut condition_texts, &mut word);
+                    condition_texts.last_mut().unwrap().push(c);
+                }
+                None => {
+                    // Quotes open values, so an apostrophe within a word
+                    // (`O'Brien`) is just a character.
+                    let opens_value = word.is_empty() || word.ends_with(&['=', '<', '>'][..]);
+                    if opens_value {
+                        quote = quote_kind(c);
+                    }
+                    word.push(c);
+                }
+            }
+        }
+        end_word(&mut condition_texts, &mut word);
+
+        condition_texts
+            .iter()
+            .map(|condition_text| Self::parse(condition_text))
+            .collect()
+    }
+
+    pub fn matches(&self, table: &TableData, row_i: usize) -> bool {
+        match table.column_index(&self.column) {
+            Some(col_i) => {
+                let ordering = compare_with_json(table.cell(col_i, row_i), &self.value);
+                self.op.holds_for(ordering)
+            }
+            None => false,
+        }
+    }
+}
+
+/// Shade the cell of `condition.column` with `fill` (hex RGB, e.g. `FF0000`,
+/// or `auto`) wherever the condition holds.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ShadingRule {
+    #[serde(flatten)]
+    pub condition: Condition,
+    #[serde(deserialize_with = "deserialize_fill")]
+    pub fill: String,
+}
+
+/// A `w:fill` value, which Word insists on being six hex digits or `auto`.
+fn deserialize_fill<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    let fill = String::deserialize(deserializer)?;
+    let is_hex_rgb = fill.len() == 6 && fill.chars().all(|c| c.is_ascii_hexdigit());
+    if is_hex_rgb || fill == "auto" {
+        Ok(fill)
+    } else {
+        Err(serde::de::Error::custom(format!(
+            "invalid fill `{}`, expected six hex digits (RRGGBB) or `auto`",
+            fill
+        )))
+    }
+}
+
+/// Make all text in the rows for which the condition holds bold and/or italic.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RowStyleRule {
+    #[serde(flatten)]
+    pub condition: Condition,
+    #[serde(default)]
+    pub bold: bool,
+    #[serde(default)]
+    pub italic: bool,
+}
+
+/// How a single cell of a vertically merged column takes part in the merge.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum VerticalMerge {
+    None,
+    Restart,
+    Continue,
+}
+
+impl TableStyling {
+    /// Background colour of a cell, if any rule applies. Later rules win.
+    pub(crate) fn cell_fill(&self, table: &TableData, col_i: usize, row_i: usize) -> Option<&str> {
+        self.shading
+            .iter()
+            .filter(|rule| table.column_index(&rule.condition.column) == Some(col_i))
+            .filter(|rule| rule.condition.matches(table, row_i))
+            .map(|rule| rule.fill.as_str())
+            .next_back()
+    }
+
+    /// Whether the text in a row is bold and italic, respectively.
+    pub(crate) fn row_emphasis(&self, table: &TableData, row_i: usize) -> (bool, bool) {
+        self.row_styles
+            .iter()
+            .filter(|rule| rule.condition.matches(table, row_i))
+            .fold((false, false), |(bold, italic), rule| {
+                (bold || rule.bold, italic || rule.italic)
+            })
+    }
+
+    /// Vertical merging of a cell, based on the (formatted) text of the cells
+    /// above and below it. Empty cells are never merged.
+    pub(crate) fn vertical_merge(
+        &self,
+        table: &TableData,
+        cell_texts: &[Vec<String>],
+        col_i: usize,
+        row_i: usize,
+    ) -> VerticalMerge {
+        let merged = self.merge_repeated_columns.contains(&col_i)
+            || self
+                .merge_repeated
+                .iter()
+                .any(|name| table.column_index(name) == Some(col_i));
+        if !merged {
+            return VerticalMerge::None;
+        }
+
+        let column = &cell_texts[col_i];
+        if column[row_i].is_empty() {
+            VerticalMerge::None
+        } else if row_i > 0 && column[row_i - 1] == column[row_i] {
+            VerticalMerge::Continue
+        } else if row_i + 1 < table.no_of_rows() && column[row_i + 1] == column[row_i] {
+            VerticalMerge::Restart
+        } else {
+            VerticalMerge::None
+        }
+    }
+}
+
+/// Split a row of group header labels into `(label, span)` pairs, merging
+/// runs of equal adjacent labels. The row is padded (or cut) to `no_of_cols`.
+pub(crate) fn group_header_spans(labels: &[String], no_of_cols: usize) -> Vec<(String, usize)> {
+    let mut result: Vec<(String, usize)> = Vec::new();
+
+    for col_i in 0..no_of_cols {
+        let label = labels.get(col_i).cloned().unwrap_or_default();
+        match result.last_mut() {
+            Some((last_label, span)) if *last_label == label && !label.is_empty() => {
+                *span += 1;
+            }
+            _ => result.push((label, 1)),
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datakit::{value::definitions::*, value::primitives::*};
+
+    #[test]
+    fn and_inside_quotes_does_not_split() {
+        let conditions =
+            Condition::parse_all("name == 'Smith and Sons' AND amount >= 1000").unwrap();
+        assert_eq!(conditions.len(), 2);
+        assert_eq!(conditions[0].column, "name");
+        assert_eq!(conditions[0].value, serde_json::json!("Smith and Sons"));
+        assert_eq!(conditions[1].column, "amount");
+        assert_eq!(conditions[1].op, ComparisonOp::Ge);
+        assert_eq!(conditions[1].value, serde_json::json!(1000.0));
+    }
+
+    #[test]
+    fn quotes_without_spaces_and_apostrophes() {
+        let conditions = Condition::parse_all("name==\"a and b\" and owner == O'Brien").unwrap();
+        assert_eq!(conditions.len(), 2);
+        assert_eq!(conditions[0].value, serde_json::json!("a and b"));
+        assert_eq!(conditions[1].value, serde_json::json!("O'Brien"));
+    }
+
+    #[test]
+    fn broken_condition() {
+        assert!(Condition::parse_all("status == 'open' and").is_none());
+    }
+
+    fn sales_table() -> TableData {
+        let text = |text: &str| Some(Value::Text(String::from(text)));
+        let integer = |int: i64| Some(Value::Number(Numeric::Integer(int)));
+        TableData {
+            headers: vec![String::from("region"), String::from("profit")],
+            columns: vec![
+                vec![text("North"), text("North"), None, None, text("South")],
+                vec![integer(5), integer(-3), integer(0), integer(-1), integer(8)],
+            ],
+        }
+    }
+
+    fn styling(json: serde_json::Value) -> TableStyling {
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn spans_of_group_headers() {
+        let labels: Vec<String> = ["", "2019", "2019", "2020", "", ""]
+            .iter()
+            .map(|label| String::from(*label))
+            .collect();
+        assert_eq!(
+            group_header_spans(&labels, 6),
+            vec![
+                (String::from(""), 1),
+                (String::from("2019"), 2),
+                (String::from("2020"), 1),
+                // Empty labels are never merged.
+                (String::from(""), 1),
+                (String::from(""), 1),
+            ]
+        );
+        // Padded to the number of columns, or cut.
+        assert_eq!(
+            group_header_spans(&labels[1..3], 3),
+            vec![(String::from("2019"), 2), (String::from(""), 1)]
+        );
+        assert_eq!(group_header_spans(&labels, 2).len(), 2);
+    }
+
+    #[test]
+    fn merges_of_repeated_values() {
+        let table = sales_table();
+        let cell_texts = vec![
+            vec!["North", "North", "", "", "South"],
+            vec!["5", "-3", "0", "-1", "8"],
+        ]
+        .into_iter()
+        .map(|column| column.into_iter().map(String::from).collect())
+        .collect::<Vec<Vec<String>>>();
+        let merges = |styling: &TableStyling, col_i: usize| -> Vec<VerticalMerge> {
+            (0..table.no_of_rows())
+                .map(|row_i| styling.vertical_merge(&table, &cell_texts, col_i, row_i))
+                .collect()
+        };
+
+        let by_name = styling(serde_json::json!({"mergeRepeated": ["region"]}));
+        assert_eq!(
+            merges(&by_name, 0),
+            vec![
+                VerticalMerge::Restart,
+                VerticalMerge::Continue,
+                // Missing values are not merged.
+                VerticalMerge::None,
+                VerticalMerge::None,
+                VerticalMerge::None,
+            ]
+        );
+        assert!(merges(&by_name, 1)
+            .iter()
+            .all(|merge| *merge == VerticalMerge::None));
+
+        let by_index = TableStyling {
+            merge_repeated_columns: vec![0],
+            ..TableStyling::default()
+        };
+        assert_eq!(merges(&by_index, 0), merges(&by_name, 0));
+    }
+
+    #[test]
+    fn shading_rules() {
+        let table = sales_table();
+        let styling = styling(serde_json::json!({"shading": [
+            {"column": "profit", "op": "<", "value": 0, "fill": "FFC7CE"},
+            {"column": "profit", "op": "<", "value": -2, "fill": "auto"},
+        ]}));
+        assert_eq!(styling.cell_fill(&table, 1, 0), None);
+        assert_eq!(styling.cell_fill(&table, 1, 3), Some("FFC7CE"));
+        // Later rules win.
+        assert_eq!(styling.cell_fill(&table, 1, 1), Some("auto"));
+        // Only the cells of the rule's column are shaded.
+        assert_eq!(styling.cell_fill(&table, 0, 1), None);
+    }
+
+    #[test]
+    fn invalid_fills() {
+        for fill in ["red", "#FFC7CE", "FFC7C", "GGGGGG", ""].iter() {
+            let json = serde_json::json!({"shading": [
+                {"column": "profit", "op": "<", "value": 0, "fill": fill},
+            ]});
+            assert!(
+                serde_json::from_value::<TableStyling>(json).is_err(),
+                "{}",
+                fill
+            );
+        }
+    }
+
+    #[test]
+    fn row_styles() {
+        let table = sales_table();
+        let styling = styling(serde_json::json!({"rowStyles": [
+            {"column": "region", "op": "==", "value": "North", "bold": true},
+            {"column": "profit", "op": "<", "value": 0, "italic": true},
+        ]}));
+        assert_eq!(styling.row_emphasis(&table, 0), (true, false));
+        assert_eq!(styling.row_emphasis(&table, 1), (true, true));
+        assert_eq!(styling.row_emphasis(&table, 3), (false, true));
+        assert_eq!(styling.row_emphasis(&table, 4), (false, false));
+    }
+}
@@ -0,0 +1,91 @@
+//! Helpers shared by the unit tests of the renderers.
+
+use crate::parse::unzip_text_file;
+use crate::{PageDimensions, Token};
+use std::io::{Cursor, Write};
+use xml::reader::XmlEvent;
+use zip::{write::FileOptions, ZipArchive, ZipWriter};
+
+/// An A4 page with margins of one inch.
+pub(crate) fn a4_dimensions() -> PageDimensions {
+    PageDimensions {
+        height: 16838,
+        width: 11906,
+        m_top: 1440,
+        m_bottom: 1440,
+        m_right: 1440,
+        m_left: 1440,
+        header: 708,
+        footer: 708,
+        gutter: 0,
+    }
+}
+
+/// A compact serialization of the tokens, e.g. `<w:p><w:r><w:t>a</w:t></w:r></w:p>`,
+/// for assertions on the emitted XML. Empty elements are not collapsed, and
+/// text is not escaped.
+pub(crate) fn xml(tokens: &[Token]) -> String {
+    let qualified = |prefix: &Option<String>, local_name: &str| match prefix {
+        Some(prefix) => format!("{}:{}", prefix, local_name),
+        None => String::from(local_name),
+    };
+
+    let mut result = String::new();
+    for token in tokens {
+        match &token.xml_reader_event {
+            XmlEvent::StartElement {
+                name, attributes, ..
+            } => {
+                result.push('<');
+                result.push_str(&qualified(&name.prefix, &name.local_name));
+                for attribute in attributes {
+                    result.push_str(&format!(
+                        " {}=\"{}\"",
+                        qualified(&attribute.name.prefix, &attribute.name.local_name),
+                        attribute.value
+                    ));
+                }
+                result.push('>');
+            }
+            XmlEvent::EndElement { name } => {
+                result.push_str(&format!("</{}>", qualified(&name.prefix, &name.local_name)));
+            }
+            XmlEvent::Characters(text) | XmlEvent::Whitespace(text) => result.push_str(text),
+            _ => (),
+        }
+    }
+    result
+}
+
+/// A minimal .docx file with the given contents of `w:body` (followed by the
+/// section properties of an A4 page).
+pub(crate) fn docx_bytes(body: &str) -> Vec<u8> {
+    let content_types = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types"><Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/><Default Extension="xml" ContentType="application/xml"/><Override PartName="/word/document.xml" ContentType="application/vnd.openxmlformats-officedocument.wordprocessingml.document.main+xml"/></Types>"#;
+    let rels = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/styles" Target="styles.xml"/></Relationships>"#;
+    let document = format!(
+        r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<w:document xmlns:w="http://schemas.openxmlformats.org/wordprocessingml/2006/main" xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships"><w:body>{}<w:sectPr><w:pgSz w:w="11906" w:h="16838"/><w:pgMar w:top="1440" w:right="1440" w:bottom="1440" w:left="1440" w:header="708" w:footer="708" w:gutter="0"/></w:sectPr></w:body></w:document>"#,
+        body
+    );
+
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    for (name, contents) in [
+        ("[Content_Types].xml", content_types),
+        ("word/_rels/document.xml.rels", rels),
+        ("word/document.xml", &document),
+    ]
+    .iter()
+    {
+        zip.start_file(*name, FileOptions::default()).unwrap();
+        zip.write_all(contents.as_bytes()).unwrap();
+    }
+    zip.finish().unwrap().into_inner()
+}
+
+/// A text file from a rendered .docx file, e.g. `word/document.xml`.
+pub(crate) fn docx_part(docx: &[u8], name: &str) -> String {
+    let mut archive = ZipArchive::new(Cursor::new(docx)).unwrap();
+    unzip_text_file(&mut archive, name).unwrap()
+}
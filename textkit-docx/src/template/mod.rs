@@ -3,17 +3,36 @@
 
 use crate::{
     errors::TextkitDocxError,
-    parse::{find_template_areas, parse_page_dimensions, unzip_text_file, xml_to_token_vec},
+    files::FileAccess,
+    parse::{
+        find_enclosing_element, find_template_areas, option_bool, parse_page_dimensions,
+        unzip_text_file, xml_to_token_vec, HelperCall, HelperScope,
+    },
     render::{
-        datakit_table::datakit_table_to_tokens, get_last_id_number_for_document_xml_rels,
-        insert_images_in_document_xml_rels, insert_png_content_type, jupyter_nb::*,
-        markdown::markdown_to_tokens, new_zip_bytes_with_document_xml, render_and_paste_tokens,
-        replace_file_in_zip, write_token_vector_to_string,
+        code_block::CodeBlockOptions,
+        datakit_table::{datakit_table_to_tokens, prototype_row_to_tokens, TableOptions},
+        describe::describe_table,
+        footnotes::{Footnotes, CONTENT_TYPE_FOOTNOTES, REL_TYPE_FOOTNOTES},
+        get_last_id_number_for_document_xml_rels,
+        inline::{inline_paragraph_tokens, InlinePiece},
+        insert_content_type_override, insert_default_content_type,
+        insert_images_in_document_xml_rels, insert_png_content_type,
+        insert_relationships_in_document_xml_rels,
+        jupyter_nb::*,
+        kv_table::{kv_table, KvOptions},
+        markdown::{markdown_to_inline_tokens, markdown_to_tokens, MarkdownStyles},
+        math::{math_paragraph_tokens, math_tokens},
+        new_zip_bytes_with_document_xml,
+        numbering::{Numbering, CONTENT_TYPE_NUMBERING, REL_TYPE_NUMBERING},
+        pivot::{pivot_table, PivotSpec},
+        render_and_paste_tokens, replace_file_in_zip, split_string_by_empty_line,
+        styles::StyleSheet,
+        table_data::TableData,
+        table_style::TableStyling,
+        write_token_vector_to_string, DocumentParts, RenderSettings,
     },
-    DocxPayload, ImageFileContents, PageDimensions, TemplateArea, TemplatePlaceholder, Token,
-    TokenType, PAT_HB_ALL,
+    DocxPayload, PageDimensions, TemplateArea, TemplatePlaceholder, Token, TokenType, PAT_HB_ALL,
 };
-use datakit::table::Table;
 use regex::Regex;
 use serde::Serialize;
 use std::collections::{BTreeMap, HashSet};
@@ -29,11 +48,20 @@ pub struct DocxTemplate {
     document_xml: String,
     document_xml_rels: String,
     content_types: String,
+    numbering_xml: Option<String>,
+    footnotes_xml: Option<String>,
+    styles: StyleSheet,
     tokens: Vec<Token>,
     document_rels_tokens: Vec<Token>,
     content_types_tokens: Vec<Token>,
     dimensions: PageDimensions,
     template_areas: Vec<TemplateArea>,
+    table_options: TableOptions,
+    table_styling: TableStyling,
+    code_block_options: CodeBlockOptions,
+    jupyter_options: JupyterOptions,
+    markdown_styles: MarkdownStyles,
+    file_access: FileAccess,
 }
 
 impl DocxTemplate {
@@ -47,6 +75,10 @@ impl DocxTemplate {
         let document_xml_rels =
             unzip_text_file(&mut source_payload, "word/_rels/document.xml.rels")?;
         let content_types = unzip_text_file(&mut source_payload, "[Content_Types].xml")?;
+        let numbering_xml = unzip_text_file(&mut source_payload, "word/numbering.xml").ok();
+        let footnotes_xml = unzip_text_file(&mut source_payload, "word/footnotes.xml").ok();
+        let styles_xml = unzip_text_file(&mut source_payload, "word/styles.xml").ok();
+        let styles = StyleSheet::from_xml(styles_xml.as_deref())?;
 
         let tokens = xml_to_token_vec(&document_xml)?;
         let document_rels_tokens = xml_to_token_vec(&document_xml_rels)?;
@@ -61,11 +93,20 @@ impl DocxTemplate {
             document_xml,
             document_xml_rels,
             content_types,
+            numbering_xml,
+            footnotes_xml,
+            styles,
             tokens,
             document_rels_tokens,
             content_types_tokens,
             dimensions,
             template_areas,
+            table_options: TableOptions::default(),
+            table_styling: TableStyling::default(),
+            code_block_options: CodeBlockOptions::default(),
+            jupyter_options: JupyterOptions::default(),
+            markdown_styles: MarkdownStyles::default(),
+            file_access: FileAccess::default(),
         })
     }
 
@@ -77,17 +118,55 @@ impl DocxTemplate {
         DocxTemplate::from_bytes(&buf)
     }
 
+    /// Set the options (column widths, formats, column selection, ...) used
+    /// for all tables rendered with the `table` helper.
+    pub fn set_table_options(&mut self, options: TableOptions) {
+        self.table_options = options;
+    }
+
+    /// Set the styling (merges, shading, emphasis) used for all tables rendered
+    /// with the `table` helper.
+    pub fn set_table_styling(&mut self, styling: TableStyling) {
+        self.table_styling = styling;
+    }
+
+    /// Set how code blocks in markdown and notebooks are rendered (syntax
+    /// highlighting, line numbers).
+    pub fn set_code_block_options(&mut self, options: CodeBlockOptions) {
+        self.code_block_options = options;
+    }
+
+    /// Set which parts of notebook code cells are rendered (input, output or
+    /// both) and whether inputs get their `In [n]:` prompts.
+    pub fn set_jupyter_options(&mut self, options: JupyterOptions) {
+        self.jupyter_options = options;
+    }
+
+    /// Set which local files the template and the data may refer to (images
+    /// in markdown, `csv` paths). By default no files are read.
+    pub fn set_file_access(&mut self, file_access: FileAccess) {
+        self.file_access = file_access;
+    }
+
+    /// Set the template styles that markdown headings, paragraphs, quotes,
+    /// etc. are rendered with, in markdown and notebooks.
+    pub fn set_markdown_styles(&mut self, styles: MarkdownStyles) {
+        self.markdown_styles = styles;
+    }
+
     /// Render the template given some data context into a new .docx file (returned)
     /// as a vector of bytes.
     pub fn render<T: Serialize>(&self, data: &T) -> Result<Vec<u8>, TextkitDocxError> {
-        let mut result: Vec<Token> = Vec::new();
+        self.render_with_warnings(data).map(|(docx, _)| docx)
+    }
 
-        // Here we track all possible images that need to be added to the DOCX file
-        // via templating (for example, by importing a Jupyter Notebook with charts).
-        // To add images to a DOCX file, not only do we need to modify the `word/document.xml`
-        // file, but also the `word/_rels/document.xml.rels`, as well as adding the file to
-        // `media/<filename>.png`.
-        let mut images: BTreeMap<String, ImageFileContents> = BTreeMap::new();
+    /// Like [`DocxTemplate::render`], but also returns warnings about content
+    /// that did not render as given, e.g. images replaced by their alt text.
+    pub fn render_with_warnings<T: Serialize>(
+        &self,
+        data: &T,
+    ) -> Result<(Vec<u8>, Vec<String>), TextkitDocxError> {
+        let mut result: Vec<Token> = Vec::new();
 
         // This index tracks the position in the `self.tokens` vector of the last
         // non-template token that was processed.
@@ -103,13 +182,38 @@ impl DocxTemplate {
         // duplicates.
         let mut already_seen_start_indices: HashSet<usize> = HashSet::new();
 
-        // Figure out the latest numerical part of the IDs in word/_rels/document.xml.rels
-        let mut latest_rels_id =
-            get_last_id_number_for_document_xml_rels(&self.document_rels_tokens);
+        // Here we track all possible images that need to be added to the DOCX file
+        // via templating (for example, by importing a Jupyter Notebook with charts).
+        // To add images to a DOCX file, not only do we need to modify the `word/document.xml`
+        // file, but also the `word/_rels/document.xml.rels`, as well as adding the file to
+        // `media/<filename>.png`. The same goes for list numbering definitions, which
+        // live in `word/numbering.xml`, and footnotes, which live in `word/footnotes.xml`.
+        let mut parts = DocumentParts {
+            // Figure out the latest numerical part of the IDs in word/_rels/document.xml.rels
+            latest_rels_id: get_last_id_number_for_document_xml_rels(&self.document_rels_tokens),
+            images: BTreeMap::new(),
+            relationships: Vec::new(),
+            numbering: Numbering::from_xml(self.numbering_xml.as_deref())?,
+            footnotes: Footnotes::from_xml(self.footnotes_xml.as_deref())?,
+            warnings: Vec::new(),
+        };
+        let settings = RenderSettings {
+            dimensions: &self.dimensions,
+            table_options: &self.table_options,
+            code_blocks: self.code_block_options,
+            jupyter: self.jupyter_options,
+            styles: &self.styles,
+            markdown_styles: &self.markdown_styles,
+            files: &self.file_access,
+        };
 
         // Also, we need a json serialized version of the data (mimicking Handlebars)
         // to render custom complex templates.
         let serialized_data = serde_json::to_value(data)?;
+        let scope = HelperScope {
+            data: &serialized_data,
+            files: &self.file_access,
+        };
 
         for context in self.template_areas.iter() {
             if let TemplateArea {
@@ -127,6 +231,38 @@ impl DocxTemplate {
                     already_seen_start_indices.insert(*start);
                 }
 
+                // Template areas can be swallowed by an earlier placeholder that
+                // replaced a larger chunk of the document (e.g. a table row).
+                if *start < bookmark_index {
+                    continue;
+                }
+
+                // Placeholders that fill a table from the template replace the
+                // whole table row they are in, not just their paragraph.
+                if let Some((row_start, row_end, helper_text)) =
+                    find_table_row_placeholder(&self.tokens, *index)
+                {
+                    if row_start >= bookmark_index {
+                        result.extend(self.tokens[bookmark_index..row_start].to_vec());
+                        bookmark_index = row_end + 1;
+
+                        let call = HelperCall::parse(&helper_text)?;
+                        if let Some(table_json) = call.param(0, &scope)? {
+                            if let Some(table_data) = TableData::from_json(&table_json)? {
+                                let hash_args = call.hash_values(&scope)?;
+                                let options = self.table_options.with_hash_args(&hash_args)?;
+                                let row_tokens = prototype_row_to_tokens(
+                                    &self.tokens[row_start..=row_end],
+                                    &table_data,
+                                    &options,
+                                )?;
+                                result.extend(row_tokens);
+                            }
+                        }
+                        continue;
+                    }
+                }
+
                 // The template area (expressed as a vector of tokens) identified
                 // by the running TemplateArea.
                 let subvector_index = index - start;
@@ -158,71 +294,184 @@ impl DocxTemplate {
                         TokenType::ComplexTemplate => {
                             let mut index: usize = 0;
                             let placeholders = parse_template_placeholders(template_text);
+                            let mut segments: Vec<ParagraphSegment> = Vec::new();
 
                             for placeholder in placeholders.iter() {
                                 if let Some(helper_name) = &placeholder.helper_name {
                                     if index != placeholder.start_position {
                                         let prequel =
                                             &template_text[index..placeholder.start_position];
-
-                                        let prequel_tokens = render_and_paste_tokens(
-                                            &template_tokens,
-                                            prequel,
-                                            subvector_index,
-                                            data,
-                                        );
-                                        result.extend(prequel_tokens);
+                                        segments
+                                            .push(ParagraphSegment::Text(String::from(prequel)));
                                     }
 
                                     index = placeholder.end_position;
 
+                                    // The placeholder without its curly braces.
+                                    let helper_text = &template_text[placeholder.start_position + 2
+                                        ..placeholder.end_position - 2];
+
                                     if helper_name == "table" {
-                                        // get the bit that's datakit table
-                                        if let Some(table_serialized) =
-                                            serialized_data.get(&placeholder.expression)
-                                        {
-                                            let table: Table =
-                                                serde_json::from_value(table_serialized.clone())?;
-                                            let table_tokens =
-                                                datakit_table_to_tokens(&table, &self.dimensions);
-                                            result.extend(table_tokens);
-                                        }
+                                        let call = HelperCall::parse(helper_text)?;
+                                        let table_tokens =
+                                            self.table_helper_tokens(&call, &scope)?;
+                                        segments.push(ParagraphSegment::Blocks(table_tokens));
+                                    } else if helper_name == "pivot" {
+                                        let call = HelperCall::parse(helper_text)?;
+                                        let pivot_tokens =
+                                            self.pivot_helper_tokens(&call, &scope)?;
+                                        segments.push(ParagraphSegment::Blocks(pivot_tokens));
+                                    } else if helper_name == "describe" {
+                                        let call = HelperCall::parse(helper_text)?;
+                                        let describe_tokens =
+                                            self.describe_helper_tokens(&call, &scope)?;
+                                        segments.push(ParagraphSegment::Blocks(describe_tokens));
+                                    } else if helper_name == "kvtable" {
+                                        let call = HelperCall::parse(helper_text)?;
+                                        let kv_tokens =
+                                            self.kvtable_helper_tokens(&call, &scope)?;
+                                        segments.push(ParagraphSegment::Blocks(kv_tokens));
                                     } else if helper_name == "jupyter" {
+                                        let call = HelperCall::parse(helper_text)?;
                                         if let Some(jupyter_nb) =
-                                            serialized_data.get(&placeholder.expression)
+                                            call.param(0, &scope)?.filter(|value| !value.is_null())
                                         {
                                             let notebook: JupyterNotebook =
-                                                serde_json::from_value(jupyter_nb.clone())?;
+                                                serde_json::from_value(jupyter_nb)?;
+                                            let mut hash_args = call.hash_values(&scope)?;
+                                            let filter = JupyterCellFilter::take_from_hash_args(
+                                                &mut hash_args,
+                                            );
+                                            let mut notebook_settings = settings;
+                                            notebook_settings.jupyter = settings
+                                                .jupyter
+                                                .take_from_hash_args(&mut hash_args)?;
+                                            let notebook_settings = helper_render_settings(
+                                                &notebook_settings,
+                                                &mut hash_args,
+                                            )?;
                                             let notebook_tokens = jupyter_nb_to_tokens(
                                                 &notebook,
-                                                &mut latest_rels_id,
-                                                &mut images,
-                                            );
-                                            result.extend(notebook_tokens);
+                                                &filter,
+                                                &notebook_settings,
+                                                &mut parts,
+                                            )?;
+                                            segments
+                                                .push(ParagraphSegment::Blocks(notebook_tokens));
                                         }
                                     } else if helper_name == "markdown" {
+                                        let call = HelperCall::parse(helper_text)?;
                                         if let Some(markdown_source) =
-                                            serialized_data.get(&placeholder.expression)
+                                            call.param(0, &scope)?.filter(|value| !value.is_null())
                                         {
                                             let source_text: String =
-                                                serde_json::from_value(markdown_source.clone())?;
-                                            let md_tokens = markdown_to_tokens(&source_text);
-                                            result.extend(md_tokens);
+                                                serde_json::from_value(markdown_source)?;
+                                            let mut hash_args = call.hash_values(&scope)?;
+                                            // By default markdown goes inline when the
+                                            // placeholder is not alone in its paragraph.
+                                            let inline = match hash_args.remove("inline") {
+                                                Some(value) => {
+                                                    option_bool(&value).ok_or_else(|| {
+                                                        TextkitDocxError::BadHelperOption(format!(
+                                                            "inline={}",
+                                                            value
+                                                        ))
+                                                    })?
+                                                }
+                                                None => {
+                                                    template_text.trim()
+                                                        != &template_text[placeholder.start_position
+                                                            ..placeholder.end_position]
+                                                }
+                                            };
+                                            let md_settings =
+                                                helper_render_settings(&settings, &mut hash_args)?;
+                                            let inline_tokens = if inline {
+                                                markdown_to_inline_tokens(
+                                                    &source_text,
+                                                    &md_settings,
+                                                    &mut parts,
+                                                )
+                                            } else {
+                                                None
+                                            };
+                                            match inline_tokens {
+                                                Some(runs) => {
+                                                    segments.push(ParagraphSegment::Runs(runs))
+                                                }
+                                                None => {
+                                                    let md_tokens = markdown_to_tokens(
+                                                        &source_text,
+                                                        &md_settings,
+                                                        &mut parts,
+                                                    );
+                                                    segments
+                                                        .push(ParagraphSegment::Blocks(md_tokens));
+                                                }
+                                            }
+                                        }
+                                    } else if helper_name == "math" {
+                                        let call = HelperCall::parse(helper_text)?;
+                                        if let Some(latex) =
+                                            call.param(0, &scope)?.filter(|value| !value.is_null())
+                                        {
+                                            let latex: String = serde_json::from_value(latex)?;
+                                            let mut hash_args = call.hash_values(&scope)?;
+                                            // By default an equation is displayed on its
+                                            // own when the placeholder is alone in its
+                                            // paragraph.
+                                            let display = match hash_args.remove("display") {
+                                                Some(value) => {
+                                                    option_bool(&value).ok_or_else(|| {
+                                                        TextkitDocxError::BadHelperOption(format!(
+                                                            "display={}",
+                                                            value
+                                                        ))
+                                                    })?
+                                                }
+                                                None => {
+                                                    template_text.trim()
+                                                        == &template_text[placeholder.start_position
+                                                            ..placeholder.end_position]
+                                                }
+                                            };
+                                            if let Some((key, value)) = hash_args.iter().next() {
+                                                return Err(TextkitDocxError::BadHelperOption(
+                                                    format!("{}={}", key, value),
+                                                ));
+                                            }
+                                            if display {
+                                                segments.push(ParagraphSegment::Blocks(
+                                                    math_paragraph_tokens(&latex),
+                                                ));
+                                            } else {
+                                                segments.push(ParagraphSegment::Runs(math_tokens(
+                                                    &latex,
+                                                )));
+                                            }
                                         }
+                                    } else if helper_name == "tablerows" {
+                                        // Table rows from the template are handled
+                                        // above, so this one is outside of a table,
+                                        // or its row was taken by a placeholder before.
+                                        return Err(TextkitDocxError::BadHelperCall(format!(
+                                            "{} (must be the first placeholder of a table row)",
+                                            helper_text
+                                        )));
                                     } else {
                                     }
                                 }
                             }
                             if index != template_text.len() {
                                 let sequel = &template_text[index..];
-                                let sequel_tokens = render_and_paste_tokens(
-                                    &template_tokens,
-                                    sequel,
-                                    subvector_index,
-                                    data,
-                                );
-                                result.extend(sequel_tokens);
+                                segments.push(ParagraphSegment::Text(String::from(sequel)));
                             }
+                            result.extend(paragraph_segments_to_tokens(
+                                &template_tokens,
+                                subvector_index,
+                                segments,
+                                data,
+                            ));
                         }
                         _ => (),
                     }
@@ -240,9 +489,32 @@ impl DocxTemplate {
         // NOTE Not sure if cloning here is really necessary.
         let mut payload = self.source_payload.clone();
 
+        // Lists need their numbering definitions, in a new numbering part if
+        // the template has none.
+        let numbering_xml = if parts.numbering.is_modified() {
+            if self.numbering_xml.is_none() {
+                parts.add_relationship(REL_TYPE_NUMBERING, "numbering.xml", false);
+            }
+            Some(parts.numbering.to_xml()?)
+        } else {
+            None
+        };
+
+        // Same for footnotes.
+        let footnotes_xml = if parts.footnotes.is_modified() {
+            if self.footnotes_xml.is_none() {
+                parts.add_relationship(REL_TYPE_FOOTNOTES, "footnotes.xml", false);
+            }
+            Some(parts.footnotes.to_xml()?)
+        } else {
+            None
+        };
+
         // Deal with any potential images that need to be inserted as well.
-        let new_document_xml_rels_tokens =
-            insert_images_in_document_xml_rels(&self.document_rels_tokens, &images);
+        let new_document_xml_rels_tokens = insert_relationships_in_document_xml_rels(
+            &insert_images_in_document_xml_rels(&self.document_rels_tokens, &parts.images),
+            &parts.relationships,
+        );
 
         let document_xml_rels_contents =
             write_token_vector_to_string(&new_document_xml_rels_tokens)?;
@@ -255,7 +527,7 @@ impl DocxTemplate {
         let mut cursor = Cursor::new(new_payload);
         let mut new_zip = ZipArchive::new(cursor)?;
 
-        for (_, image_contents) in images.iter() {
+        for (_, image_contents) in parts.images.iter() {
             let path_to_image = format!("word/media/{}", image_contents.file_contents.filename);
             new_payload = replace_file_in_zip(
                 &mut new_zip,
@@ -266,7 +538,29 @@ impl DocxTemplate {
             new_zip = ZipArchive::new(cursor)?;
         }
 
-        let new_content_type_tokens = insert_png_content_type(&self.content_types_tokens);
+        let mut new_content_type_tokens = insert_png_content_type(&self.content_types_tokens);
+        if parts
+            .images
+            .values()
+            .any(|image| image.file_contents.filename.ends_with(".jpeg"))
+        {
+            new_content_type_tokens =
+                insert_default_content_type(&new_content_type_tokens, "jpeg", "image/jpeg");
+        }
+        if numbering_xml.is_some() && self.numbering_xml.is_none() {
+            new_content_type_tokens = insert_content_type_override(
+                &new_content_type_tokens,
+                "/word/numbering.xml",
+                CONTENT_TYPE_NUMBERING,
+            );
+        }
+        if footnotes_xml.is_some() && self.footnotes_xml.is_none() {
+            new_content_type_tokens = insert_content_type_override(
+                &new_content_type_tokens,
+                "/word/footnotes.xml",
+                CONTENT_TYPE_FOOTNOTES,
+            );
+        }
         let new_content_type_payload = write_token_vector_to_string(&new_content_type_tokens)?;
         new_payload = replace_file_in_zip(
             &mut new_zip,
@@ -277,33 +571,366 @@ impl DocxTemplate {
         cursor = Cursor::new(new_payload);
         new_zip = ZipArchive::new(cursor)?;
 
-        new_zip_bytes_with_document_xml(&mut new_zip, &document_xml_contents)
+        if let Some(numbering_xml) = numbering_xml {
+            new_payload =
+                replace_file_in_zip(&mut new_zip, "word/numbering.xml", numbering_xml.as_bytes())?;
+            cursor = Cursor::new(new_payload);
+            new_zip = ZipArchive::new(cursor)?;
+        }
+
+        if let Some(footnotes_xml) = footnotes_xml {
+            new_payload =
+                replace_file_in_zip(&mut new_zip, "word/footnotes.xml", footnotes_xml.as_bytes())?;
+            cursor = Cursor::new(new_payload);
+            new_zip = ZipArchive::new(cursor)?;
+        }
+
+        let docx = new_zip_bytes_with_document_xml(&mut new_zip, &document_xml_contents)?;
+        Ok((docx, parts.warnings))
+    }
+
+    /// Tokens of a `{{table data ...}}` placeholder: the table given by the first
+    /// parameter, with the hash arguments overriding the table options.
+    fn table_helper_tokens(
+        &self,
+        call: &HelperCall,
+        scope: &HelperScope,
+    ) -> Result<Vec<Token>, TextkitDocxError> {
+        match helper_table_data(call, scope)? {
+            Some(table_data) => self.table_tokens(&table_data, &call.hash_values(scope)?),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Tokens of a `{{pivot data rows=... cols=... values=... agg=...}}`
+    /// placeholder: the summary of the table given by the first parameter. The
+    /// remaining hash arguments are table options.
+    fn pivot_helper_tokens(
+        &self,
+        call: &HelperCall,
+        scope: &HelperScope,
+    ) -> Result<Vec<Token>, TextkitDocxError> {
+        match helper_table_data(call, scope)? {
+            Some(table_data) => {
+                let mut hash_args = call.hash_values(scope)?;
+                let spec = PivotSpec::take_from_hash_args(&mut hash_args)?;
+                let pivoted = pivot_table(&table_data, &spec)?;
+                self.table_tokens(&pivoted, &hash_args)
+            }
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Tokens of a `{{describe data}}` placeholder: descriptive statistics of
+    /// each column of the table given by the first parameter. The hash
+    /// arguments are table options.
+    fn describe_helper_tokens(
+        &self,
+        call: &HelperCall,
+        scope: &HelperScope,
+    ) -> Result<Vec<Token>, TextkitDocxError> {
+        match helper_table_data(call, scope)? {
+            Some(table_data) => {
+                self.table_tokens(&describe_table(&table_data), &call.hash_values(scope)?)
+            }
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Tokens of a `{{kvtable obj separator="." humanize=true}}` placeholder: a
+    /// "Property | Value" table of the object given by the first parameter.
+    /// The remaining hash arguments are table options.
+    fn kvtable_helper_tokens(
+        &self,
+        call: &HelperCall,
+        scope: &HelperScope,
+    ) -> Result<Vec<Token>, TextkitDocxError> {
+        match call.param(0, scope)? {
+            Some(object) if object.is_object() || object.is_array() => {
+                let mut hash_args = call.hash_values(scope)?;
+                let kv_options = KvOptions::take_from_hash_args(&mut hash_args)?;
+                self.table_tokens(&kv_table(&object, &kv_options), &hash_args)
+            }
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    /// Render a table, with table options and styling (`styling=...`, taken
+    /// from the data) overridden by the given hash arguments.
+    fn table_tokens(
+        &self,
+        table_data: &TableData,
+        hash_args: &BTreeMap<String, serde_json::Value>,
+    ) -> Result<Vec<Token>, TextkitDocxError> {
+        let options = self.table_options.with_hash_args(hash_args)?;
+        let styling = match hash_args.get("styling") {
+            Some(styling_json) if !styling_json.is_null() => {
+                serde_json::from_value(styling_json.clone())?
+            }
+            _ => self.table_styling.clone(),
+        };
+
+        Ok(datakit_table_to_tokens(
+            table_data,
+            &styling,
+            &self.dimensions,
+            &options,
+        ))
     }
 }
 
+/// What the text and the placeholders of a template paragraph render to.
+#[derive(Debug)]
+enum ParagraphSegment {
+    /// Template text (outside of helper placeholders), rendered with Handlebars.
+    Text(String),
+    /// Runs that go into the paragraph, e.g. inline markdown.
+    Runs(Vec<Token>),
+    /// Block content (paragraphs, tables) that replaces the paragraph.
+    Blocks(Vec<Token>),
+}
+
+/// Paragraphs (copies of the template paragraph) and blocks for the segments
+/// of a template paragraph. Text and runs next to each other share a
+/// paragraph; text alone gets a paragraph per chunk between empty lines, as
+/// simple placeholders do.
+fn paragraph_segments_to_tokens<T: Serialize>(
+    template_tokens: &[Token],
+    text_index: usize,
+    segments: Vec<ParagraphSegment>,
+    data: &T,
+) -> Vec<Token> {
+    let mut result: Vec<Token> = Vec::new();
+    let mut pieces: Vec<InlinePiece> = Vec::new();
+    let has_runs = segments
+        .iter()
+        .any(|segment| matches!(segment, ParagraphSegment::Runs(_)));
+    let hb = handlebars::Handlebars::new();
+
+    let flush = |result: &mut Vec<Token>, pieces: &mut Vec<InlinePiece>| {
+        if !pieces.is_empty() {
+            result.extend(inline_paragraph_tokens(template_tokens, text_index, pieces));
+            pieces.clear();
+        }
+    };
+
+    for segment in segments {
+        match segment {
+            ParagraphSegment::Text(text) if !has_runs => result.extend(render_and_paste_tokens(
+                template_tokens,
+                &text,
+                text_index,
+                data,
+            )),
+            ParagraphSegment::Text(text) => {
+                let rendered_text = hb.render_template(&text, data).unwrap_or(text);
+                for (chunk_i, chunk) in split_string_by_empty_line(&rendered_text).enumerate() {
+                    if chunk_i > 0 {
+                        flush(&mut result, &mut pieces);
+                    }
+                    pieces.push(InlinePiece::Text(String::from(chunk)));
+                }
+            }
+            ParagraphSegment::Runs(runs) => pieces.push(InlinePiece::Runs(runs)),
+            ParagraphSegment::Blocks(tokens) => {
+                flush(&mut result, &mut pieces);
+                result.extend(tokens);
+            }
+        }
+    }
+    flush(&mut result, &mut pieces);
+
+    result
+}
+
+/// Render settings for a `markdown` or `jupyter` placeholder: the template's,
+/// with the code block options overridden by the (remaining) hash arguments.
+fn helper_render_settings<'a>(
+    settings: &RenderSettings<'a>,
+    hash_args: &mut BTreeMap<String, serde_json::Value>,
+) -> Result<RenderSettings<'a>, TextkitDocxError> {
+    let mut helper_settings = *settings;
+    helper_settings.code_blocks = settings.code_blocks.take_from_hash_args(hash_args)?;
+
+    match hash_args.iter().next() {
+        Some((key, value)) => Err(TextkitDocxError::BadHelperOption(format!(
+            "{}={}",
+            key, value
+        ))),
+        None => Ok(helper_settings),
+    }
+}
+
+/// The table given by the first parameter of a helper call, either a
+/// serialized datakit table or plain JSON rows.
+fn helper_table_data(
+    call: &HelperCall,
+    scope: &HelperScope,
+) -> Result<Option<TableData>, TextkitDocxError> {
+    match call.param(0, scope)? {
+        Some(table_json) => TableData::from_json(&table_json),
+        None => Ok(None),
+    }
+}
+
+/// If the token at `token_index` contains a `{{tablerows ...}}` placeholder
+/// and sits inside a table row, return the start and end indices of that row
+/// along with the placeholder text (without the curly braces).
+fn find_table_row_placeholder(
+    tokens: &[Token],
+    token_index: usize,
+) -> Option<(usize, usize, String)> {
+    let token = &tokens[token_index];
+    if token.token_type != TokenType::ComplexTemplate {
+        return None;
+    }
+
+    let text = token.token_text.as_ref()?;
+    let placeholders = parse_template_placeholders(text);
+    let placeholder = placeholders
+        .into_iter()
+        .find(|placeholder| placeholder.helper_name.as_deref() == Some("tablerows"))?;
+    let (row_start, row_end) = find_enclosing_element(tokens, token_index, "tr")?;
+
+    let helper_text = &text[placeholder.start_position + 2..placeholder.end_position - 2];
+
+    Some((row_start, row_end, String::from(helper_text)))
+}
+
 pub(crate) fn parse_template_placeholders(text: &str) -> Vec<TemplatePlaceholder> {
     let mut result: Vec<TemplatePlaceholder> = Vec::new();
     let placeholder_pattern = Regex::new(PAT_HB_ALL).unwrap();
 
     for capture in placeholder_pattern.captures_iter(text) {
-        let start_position = text.find(&capture[0]).unwrap();
-        let end_position = start_position + &capture[0].len();
-        if capture.len() == 3 {
-            result.push(TemplatePlaceholder {
-                helper_name: Some(capture[1].into()),
-                expression: capture[2].into(),
-                start_position: start_position,
-                end_position: end_position,
-            })
-        } else {
-            result.push(TemplatePlaceholder {
-                helper_name: None,
-                expression: capture[1].into(),
-                start_position: start_position,
-                end_position: end_position,
-            })
-        }
+        let whole_match = capture.get(0).unwrap();
+        // Only placeholders with arguments, e.g. `{{table my_table}}`, are
+        // helper calls. The arguments are parsed by `HelperCall::parse`.
+        let helper_name = capture.get(2).map(|_| String::from(&capture[1]));
+        result.push(TemplatePlaceholder {
+            helper_name,
+            start_position: whole_match.start(),
+            end_position: whole_match.end(),
+        })
     }
 
     result
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::testing::{docx_bytes, docx_part};
+
+    /// A table whose second row is the given row.
+    fn table_with_row(row: &str) -> String {
+        format!(
+            "<w:tbl><w:tr><w:tc><w:p><w:r><w:t>Name</w:t></w:r></w:p></w:tc></w:tr>{}</w:tbl>",
+            row
+        )
+    }
+
+    fn render(body: &str, data: serde_json::Value) -> Result<String, TextkitDocxError> {
+        let template = DocxTemplate::from_bytes(&docx_bytes(body))?;
+        let docx = template.render(&data)?;
+        Ok(docx_part(&docx, "word/document.xml"))
+    }
+
+    #[test]
+    fn tablerows_fills_its_row() {
+        let body = table_with_row(
+            "<w:tr><w:tc><w:p><w:r><w:t>{{tablerows names}}</w:t></w:r></w:p></w:tc></w:tr>",
+        );
+        let data = serde_json::json!({"names": [{"name": "a"}, {"name": "b"}]});
+        let document = render(&body, data).unwrap();
+        assert_eq!(document.matches("<w:tr>").count(), 3);
+        assert!(document.contains(">a</w:t>"));
+        assert!(document.contains(">b</w:t>"));
+        assert!(!document.contains("tablerows"));
+    }
+
+    #[test]
+    fn tablerows_outside_of_its_own_row() {
+        let data = serde_json::json!({"names": [{"name": "a"}], "title": "Names"});
+        let outside_of_table = "<w:p><w:r><w:t>{{tablerows names}}</w:t></w:r></w:p>";
+        let row_taken = table_with_row(
+            "<w:tr><w:tc><w:p><w:r><w:t>{{title}}</w:t></w:r></w:p></w:tc>\
+             <w:tc><w:p><w:r><w:t>{{tablerows names}}</w:t></w:r></w:p></w:tc></w:tr>",
+        );
+        for body in [String::from(outside_of_table), row_taken].iter() {
+            let result = render(body, data.clone());
+            assert!(
+                matches!(result, Err(TextkitDocxError::BadHelperCall(_))),
+                "{}",
+                body
+            );
+        }
+    }
+
+    #[test]
+    fn jpeg_images_and_warnings() {
+        let jpeg = [
+            0xFF, 0xD8, 0xFF, 0xC0, 0x00, 0x0B, 0x08, 0x00, 0x02, 0x00, 0x03, 0x01, 0x01, 0x11,
+            0x00, 0xFF, 0xD9,
+        ];
+        let data = serde_json::json!({
+            "text": format!(
+                "![a](data:image/jpeg;base64,{}) ![b](missing.png)",
+                base64::encode(jpeg)
+            )
+        });
+        let template = DocxTemplate::from_bytes(&docx_bytes(
+            "<w:p><w:r><w:t>{{markdown text}}</w:t></w:r></w:p>",
+        ))
+        .unwrap();
+        let (docx, warnings) = template.render_with_warnings(&data).unwrap();
+
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].starts_with("Image missing.png replaced by its alt text"));
+        let content_types = docx_part(&docx, "[Content_Types].xml");
+        assert_eq!(content_types.matches(r#"Extension="jpeg""#).count(), 1);
+        assert!(content_types.contains(r#"ContentType="image/jpeg""#));
+        assert!(docx_part(&docx, "word/_rels/document.xml.rels")
+            .contains(r#"Target="media/figure-1.jpeg""#));
+    }
+
+    #[test]
+    fn footnotes_part() {
+        let template = DocxTemplate::from_bytes(&docx_bytes(
+            "<w:p><w:r><w:t>{{markdown text}}</w:t></w:r></w:p>",
+        ))
+        .unwrap();
+        let data = serde_json::json!({"text": "Text[^1].\n\n[^1]: The note.\n"});
+        let docx = template.render(&data).unwrap();
+
+        assert!(
+            docx_part(&docx, "word/document.xml").contains(r#"<w:footnoteReference w:id="1"/>"#)
+        );
+        let footnotes = docx_part(&docx, "word/footnotes.xml");
+        assert!(footnotes.contains(r#"<w:footnote w:id="1">"#));
+        assert!(footnotes.contains("The note."));
+        let rels = docx_part(&docx, "word/_rels/document.xml.rels");
+        assert!(rels.contains(&format!(
+            r#"Type="{}" Target="footnotes.xml""#,
+            REL_TYPE_FOOTNOTES
+        )));
+        let content_types = docx_part(&docx, "[Content_Types].xml");
+        assert!(content_types.contains(&format!(
+            r#"<Override PartName="/word/footnotes.xml" ContentType="{}"/>"#,
+            CONTENT_TYPE_FOOTNOTES
+        )));
+    }
+
+    #[test]
+    fn inline_markdown_is_spliced_into_its_paragraph() {
+        let body = "<w:p><w:pPr><w:jc w:val=\"center\"/></w:pPr><w:r><w:rPr><w:i/></w:rPr>\
+                    <w:t>The result was {{markdown verdict}}.</w:t></w:r></w:p>";
+        let document = render(body, serde_json::json!({"verdict": "**passed**"})).unwrap();
+        assert_eq!(document.matches("<w:p>").count(), 1);
+        assert!(document.contains(
+            "<w:p><w:pPr><w:jc w:val=\"center\"/></w:pPr>\
+             <w:r><w:rPr><w:i/></w:rPr><w:t xml:space=\"preserve\">The result was </w:t></w:r>\
+             <w:r><w:rPr><w:b/><w:bCs/><w:i/></w:rPr><w:t xml:space=\"preserve\">passed</w:t></w:r>\
+             <w:r><w:rPr><w:i/></w:rPr><w:t xml:space=\"preserve\">.</w:t></w:r></w:p>"
+        ));
+    }
+}
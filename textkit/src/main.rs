@@ -2,6 +2,9 @@ use clap::Clap;
 use std::fs::File;
 use std::io::{Error, ErrorKind, Write};
 use std::path::PathBuf;
+use textkit_docx::csv_table::{parse_delimiter, read_csv_as_datakit_json, CsvOptions, CsvSource};
+use textkit_docx::files::FileAccess;
+use textkit_docx::render::markdown::{MarkdownElement, MarkdownStyles};
 use textkit_docx::DocxTemplate;
 
 fn main() -> std::io::Result<()> {
@@ -26,12 +29,24 @@ fn docx_template_apply(opts: DocxTemplateOptions) -> std::io::Result<()> {
         Err(error)
     } else {
         match DocxTemplate::from_file(&opts.template) {
-            Ok(template) => {
+            Ok(mut template) => {
+                template.set_markdown_styles(markdown_styles(&opts)?);
+                if let Some(files) = &opts.files {
+                    let file_access = FileAccess::within(files).map_err(|error| {
+                        let message = format!("Could not use --files {:?}: {}", files, error);
+                        Error::new(ErrorKind::InvalidInput, message)
+                    })?;
+                    template.set_file_access(file_access);
+                }
                 let data_fh = File::open(&opts.json)?;
                 let mut output_fh = File::create(&opts.output)?;
-                let data: serde_json::Value = serde_json::from_reader(data_fh)?;
-                match template.render(&data) {
-                    Ok(mut new_docx_data) => {
+                let mut data: serde_json::Value = serde_json::from_reader(data_fh)?;
+                add_csv_tables(&mut data, &opts)?;
+                match template.render_with_warnings(&data) {
+                    Ok((mut new_docx_data, warnings)) => {
+                        for warning in warnings {
+                            eprintln!("Warning: {}", warning);
+                        }
                         output_fh.write_all(&mut new_docx_data)?;
                     }
                     Err(error) => {
@@ -51,6 +66,75 @@ fn docx_template_apply(opts: DocxTemplateOptions) -> std::io::Result<()> {
     }
 }
 
+/// Parse `--csv-delimiter`.
+fn csv_delimiter(text: &str) -> Result<char, String> {
+    parse_delimiter(text)
+        .ok_or_else(|| format!("expected a single character or \\t, got '{}'", text))
+}
+
+/// Read the CSV files given with `--table name=file.csv` and put them into
+/// the data (as serialized datakit tables) under their names.
+fn add_csv_tables(data: &mut serde_json::Value, opts: &DocxTemplateOptions) -> std::io::Result<()> {
+    let csv_options = CsvOptions {
+        delimiter: opts.csv_delimiter,
+        has_header: !opts.csv_no_header,
+        infer_types: !opts.csv_no_type_inference,
+    };
+
+    for table_arg in opts.tables.iter() {
+        let (name, path) = match table_arg.find('=') {
+            Some(i) => (&table_arg[..i], &table_arg[i + 1..]),
+            None => {
+                let message = format!("Expected --table <name>=<file>, got '{}'.", table_arg);
+                return Err(Error::new(ErrorKind::InvalidInput, message));
+            }
+        };
+
+        let source = CsvSource::Path(PathBuf::from(path));
+        let table_json = read_csv_as_datakit_json(&source, &csv_options).map_err(|error| {
+            let message = format!("Could not read the CSV file {}: {}", path, error);
+            Error::new(ErrorKind::InvalidData, message)
+        })?;
+
+        match data.as_object_mut() {
+            Some(obj) => {
+                obj.insert(String::from(name), table_json);
+            }
+            None => {
+                let message = "Tables can only be added if the JSON data is an object.";
+                return Err(Error::new(ErrorKind::InvalidInput, message));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// The styles given with `--markdown-style element=style`.
+fn markdown_styles(opts: &DocxTemplateOptions) -> std::io::Result<MarkdownStyles> {
+    let mut styles = MarkdownStyles::default();
+
+    for style_arg in opts.markdown_styles.iter() {
+        let element_and_style = style_arg
+            .find('=')
+            .map(|i| (&style_arg[..i], &style_arg[i + 1..]));
+        match element_and_style.and_then(|(name, style)| {
+            MarkdownElement::from_name(name).map(|element| (element, style))
+        }) {
+            Some((element, style)) => styles.set(element, style),
+            None => {
+                let message = format!(
+                    "Expected --markdown-style <element>=<style>, got '{}'.",
+                    style_arg
+                );
+                return Err(Error::new(ErrorKind::InvalidInput, message));
+            }
+        }
+    }
+
+    Ok(styles)
+}
+
 #[derive(Clap, Debug)]
 #[clap(version = "0.1.0", author = "Jevgeni Tarasov <jevgeni@hey.com>")]
 struct Options {
@@ -77,4 +161,37 @@ struct DocxTemplateOptions {
     /// Output file name.
     #[clap(short, long)]
     output: PathBuf,
+
+    /// A CSV/TSV file to add to the data as a table, as `<name>=<file>`. Can be
+    /// given several times.
+    #[clap(long = "table")]
+    tables: Vec<String>,
+
+    /// Field delimiter of the CSV files, e.g. `;` or `\t` (guessed if not
+    /// given).
+    #[clap(long, parse(try_from_str = csv_delimiter))]
+    csv_delimiter: Option<char>,
+
+    /// The CSV files have no header row.
+    #[clap(long)]
+    csv_no_header: bool,
+
+    /// Read all CSV values as text instead of inferring numbers and booleans.
+    #[clap(long)]
+    csv_no_type_inference: bool,
+
+    /// The template style (id or name) of a markdown element, as
+    /// `<element>=<style>`, e.g. `heading1=Report Heading 1`. Elements are
+    /// heading1 to heading6, paragraph, quote, intense_quote, list_paragraph,
+    /// hyperlink, code, footnote_text and footnote_reference. Can be given
+    /// several times.
+    #[clap(long = "markdown-style")]
+    markdown_styles: Vec<String>,
+
+    /// Directory that local files named in the template or the data (images
+    /// in markdown, `csv` paths) may be read from, including its
+    /// subdirectories. Relative paths are taken relative to it. Without it,
+    /// no local files are read.
+    #[clap(long)]
+    files: Option<PathBuf>,
 }